@@ -0,0 +1,51 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process;
+use chip8::asm::assemble_file;
+
+const USAGE: &str = "Usage: chip8-asm <source> [-o <rom>]";
+
+/// Command line options
+struct Args {
+    source_path: String,
+    /// Defaults to the source path with a `.ch8` extension
+    output_path: String,
+}
+
+fn main() {
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(message) => {
+            eprintln!("{message}\n{USAGE}");
+            process::exit(2);
+        }
+    };
+    let rom = match assemble_file(&args.source_path) {
+        Ok(rom) => rom,
+        Err(err) => {
+            eprintln!("{err}");
+            process::exit(1);
+        }
+    };
+    if let Err(err) = fs::write(&args.output_path, &rom) {
+        eprintln!("Failed to write {}: {err}", args.output_path);
+        process::exit(1);
+    }
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut source_path = None;
+    let mut output_path = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" => output_path = Some(args.next().ok_or("Missing output path")?),
+            _ if source_path.is_none() => source_path = Some(arg),
+            _ => return Err(format!("Unexpected argument: {arg}")),
+        }
+    }
+    let source_path: String = source_path.ok_or("Missing source path")?;
+    let output_path = output_path.unwrap_or_else(|| Path::new(&source_path).with_extension("ch8").display().to_string());
+    Ok(Args { source_path, output_path })
+}
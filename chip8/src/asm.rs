@@ -0,0 +1,684 @@
+//! Assembler for classic CHIP-8 mnemonics.
+//!
+//! Accepts the syntax printed by the disassembler:
+//!
+//! ```text
+//! SPEED EQU 2             ; constants
+//! start:                  ; labels
+//!     LD V0, SPEED * 2    ; expressions
+//!     LD I, sprite
+//!     DRW V0, V1, 5
+//!     JP start
+//! sprite:
+//!     DB 0xF0, 0x90, 0xF0 ; data
+//!     INCLUDE "more.asm"  ; other files, relative to this one
+//! ```
+//!
+//! Mnemonics, registers and directives are case-insensitive, symbols are
+//! not. The output is a ROM to be loaded at the program start address.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use crate::cpu::START_ADDRESS;
+use crate::instruction::Instruction;
+
+/// How deeply INCLUDE may nest, to catch files including themselves
+const MAX_INCLUDE_DEPTH: usize = 16;
+/// How deeply constants may refer to other constants
+const MAX_SYMBOL_DEPTH: usize = 64;
+/// How deeply expressions may nest, so parsing and evaluating them can't
+/// overflow the stack
+const MAX_EXPR_DEPTH: usize = 256;
+
+/// Assembly failure with the position of the offending token
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    pub file: String,
+    /// 1-based line number
+    pub line: usize,
+    /// 1-based column number
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}: {}", self.file, self.line, self.column, self.message)
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+/// Assembles source text, resolving includes relative to the working directory
+pub fn assemble(source: &str) -> Result<Vec<u8>, AsmError> {
+    let mut assembler = Assembler::new(&|path| fs::read_to_string(path));
+    assembler.add_source("<input>", Path::new(""), source, 0)?;
+    assembler.finish()
+}
+
+/// Assembles a source file, resolving includes relative to it
+pub fn assemble_file<P: AsRef<Path>>(path: P) -> Result<Vec<u8>, AsmError> {
+    assemble_with_loader(path.as_ref(), &|path| fs::read_to_string(path))
+}
+
+/// Assembles the file at `path`, reading it and its includes with `loader`
+pub fn assemble_with_loader(path: &Path, loader: &dyn Fn(&Path) -> io::Result<String>) -> Result<Vec<u8>, AsmError> {
+    let name = path.display().to_string();
+    let source = loader(path).map_err(|err| AsmError {
+        file: name.clone(),
+        line: 0,
+        column: 0,
+        message: format!("cannot read file: {err}"),
+    })?;
+    let mut assembler = Assembler::new(loader);
+    assembler.add_source(&name, path.parent().unwrap_or(Path::new("")), &source, 0)?;
+    assembler.finish()
+}
+
+/// Position in the source, for error messages
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Location {
+    file: String,
+    line: usize,
+    column: usize,
+}
+
+impl Location {
+    fn error(&self, message: impl Into<String>) -> AsmError {
+        AsmError {
+            file: self.file.clone(),
+            line: self.line,
+            column: self.column,
+            message: message.into(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Number(i64),
+    Str(Vec<u8>),
+    Punct(&'static str),
+}
+
+/// Operators and punctuation, longest first
+const PUNCTUATION: [&str; 18] = ["<<", ">>", "+", "-", "*", "/", "%", "&", "|", "^", "~", "(", ")", "[", "]", ",", ":", "="];
+
+fn tokenize(text: &str, file: &str, line: usize) -> Result<Vec<(Token, Location)>, AsmError> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+    while pos < chars.len() {
+        let c = chars[pos];
+        let location = Location { file: file.to_string(), line, column: pos + 1 };
+        if c == ';' {
+            break;
+        } else if c.is_whitespace() {
+            pos += 1;
+        } else if c.is_ascii_alphabetic() || c == '_' || c == '.' {
+            let start = pos;
+            while pos < chars.len() && (chars[pos].is_ascii_alphanumeric() || chars[pos] == '_' || chars[pos] == '.') {
+                pos += 1;
+            }
+            tokens.push((Token::Ident(chars[start..pos].iter().collect()), location));
+        } else if c == '$' && !chars.get(pos + 1).is_some_and(|c| c.is_ascii_hexdigit()) {
+            pos += 1;
+            tokens.push((Token::Ident("$".to_string()), location));
+        } else if c.is_ascii_digit() || c == '$' {
+            let start = pos;
+            pos += 1;
+            while pos < chars.len() && (chars[pos].is_ascii_alphanumeric() || chars[pos] == '_') {
+                pos += 1;
+            }
+            let literal: String = chars[start..pos].iter().filter(|&&c| c != '_').collect();
+            let value = parse_number(&literal).ok_or_else(|| location.error(format!("invalid number '{literal}'")))?;
+            tokens.push((Token::Number(value), location));
+        } else if c == '"' {
+            let mut bytes = Vec::new();
+            pos += 1;
+            loop {
+                match chars.get(pos) {
+                    None => return Err(location.error("unterminated string")),
+                    Some('"') => break,
+                    Some('\\') => {
+                        let escaped = match chars.get(pos + 1) {
+                            Some('n') => '\n',
+                            Some('0') => '\0',
+                            Some(&other @ ('\\' | '"')) => other,
+                            _ => return Err(location.error("invalid escape in string")),
+                        };
+                        bytes.push(escaped as u8);
+                        pos += 2;
+                    }
+                    Some(&other) if other.is_ascii() => {
+                        bytes.push(other as u8);
+                        pos += 1;
+                    }
+                    Some(_) => return Err(location.error("strings may only contain ASCII")),
+                }
+            }
+            pos += 1;
+            tokens.push((Token::Str(bytes), location));
+        } else {
+            let rest: String = chars[pos..(pos + 2).min(chars.len())].iter().collect();
+            let punct = PUNCTUATION
+                .iter()
+                .find(|punct| rest.starts_with(**punct))
+                .ok_or_else(|| location.error(format!("unexpected character '{c}'")))?;
+            pos += punct.len();
+            tokens.push((Token::Punct(punct), location));
+        }
+    }
+    Ok(tokens)
+}
+
+/// Parses decimal, `0x` hex, `0b` binary and `$` hex literals. A `$` on
+/// its own is the address of the current line.
+fn parse_number(literal: &str) -> Option<i64> {
+    let lower = literal.to_ascii_lowercase();
+    if let Some(hex) = lower.strip_prefix("0x").or_else(|| lower.strip_prefix('$')) {
+        i64::from_str_radix(hex, 16).ok()
+    } else if let Some(binary) = lower.strip_prefix("0b") {
+        i64::from_str_radix(binary, 2).ok()
+    } else {
+        lower.parse().ok()
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    Number(i64),
+    Symbol(String, Location),
+    /// `$`, the address of the current line
+    Here,
+    Unary(&'static str, Box<Expr>),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+}
+
+/// Binary operators from loosest to tightest binding
+const PRECEDENCE: [&[&str]; 6] = [&["|"], &["^"], &["&"], &["<<", ">>"], &["+", "-"], &["*", "/", "%"]];
+
+/// Precedence climbing parser over the tokens of one operand
+struct ExprParser<'a> {
+    tokens: &'a [(Token, Location)],
+    pos: usize,
+    end: Location,
+    /// Parentheses and unary operators currently open
+    depth: usize,
+}
+
+/// A parsed expression and the height of its tree
+type Parsed = (Expr, usize);
+
+impl ExprParser<'_> {
+    fn peek_punct(&self) -> Option<&'static str> {
+        match self.tokens.get(self.pos) {
+            Some((Token::Punct(punct), _)) => Some(punct),
+            _ => None,
+        }
+    }
+
+    fn location(&self) -> Location {
+        self.tokens.get(self.pos).map_or_else(|| self.end.clone(), |(_, location)| location.clone())
+    }
+
+    fn checked_height(location: &Location, height: usize) -> Result<usize, AsmError> {
+        if height > MAX_EXPR_DEPTH {
+            return Err(location.error("expression nested too deeply"));
+        }
+        Ok(height)
+    }
+
+    /// Parses operators binding at least as tightly as `level`
+    fn parse(&mut self, level: usize) -> Result<Parsed, AsmError> {
+        let (mut lhs, mut height) = self.parse_unary()?;
+        while let Some((op_level, op)) = self.peek_operator().filter(|&(op_level, _)| op_level >= level) {
+            let location = self.location();
+            self.pos += 1;
+            let (rhs, rhs_height) = self.parse(op_level + 1)?;
+            // Chains like `1 + 1 + 1` grow the tree without nesting
+            height = Self::checked_height(&location, height.max(rhs_height) + 1)?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+        Ok((lhs, height))
+    }
+
+    /// Binary operator at the current position and its precedence level
+    fn peek_operator(&self) -> Option<(usize, &'static str)> {
+        let op = self.peek_punct()?;
+        PRECEDENCE.iter().position(|operators| operators.contains(&op)).map(|level| (level, op))
+    }
+
+    fn parse_unary(&mut self) -> Result<Parsed, AsmError> {
+        let location = self.location();
+        let Some((token, _)) = self.tokens.get(self.pos) else {
+            return Err(location.error("expected an expression"));
+        };
+        if matches!(token, Token::Punct("-" | "~" | "+" | "(")) {
+            Self::checked_height(&location, self.depth + 1)?;
+        }
+        self.pos += 1;
+        match token {
+            Token::Number(value) => Ok((Expr::Number(*value), 0)),
+            Token::Ident(name) if name == "$" => Ok((Expr::Here, 0)),
+            Token::Ident(name) => Ok((Expr::Symbol(name.clone(), location), 0)),
+            Token::Punct(op @ ("-" | "~" | "+")) => {
+                self.depth += 1;
+                let operand = self.parse_unary();
+                self.depth -= 1;
+                let (operand, height) = operand?;
+                Ok((Expr::Unary(op, Box::new(operand)), Self::checked_height(&location, height + 1)?))
+            }
+            Token::Punct("(") => {
+                self.depth += 1;
+                let parsed = self.parse(0);
+                self.depth -= 1;
+                let parsed = parsed?;
+                if self.peek_punct() != Some(")") {
+                    return Err(self.location().error("expected ')'"));
+                }
+                self.pos += 1;
+                Ok(parsed)
+            }
+            _ => Err(location.error("expected an expression")),
+        }
+    }
+}
+
+/// Parses a whole token slice as one expression
+fn parse_expr(tokens: &[(Token, Location)], end: &Location) -> Result<Expr, AsmError> {
+    let mut parser = ExprParser { tokens, pos: 0, end: end.clone(), depth: 0 };
+    let (expr, _) = parser.parse(0)?;
+    if parser.pos < tokens.len() {
+        return Err(parser.location().error("unexpected token in expression"));
+    }
+    Ok(expr)
+}
+
+/// Operand of an instruction
+#[derive(Debug, Clone)]
+enum Operand {
+    Register(u8),
+    /// Named special operand such as I, DT, ST, K, F, HF, B or R
+    Keyword(&'static str),
+    /// `[I]`
+    IndirectI,
+    /// `LONG expr`
+    Long(Expr),
+    Value(Expr),
+}
+
+const KEYWORDS: [&str; 8] = ["I", "DT", "ST", "K", "F", "HF", "B", "R"];
+
+fn parse_operand(tokens: &[(Token, Location)], end: &Location) -> Result<Operand, AsmError> {
+    match tokens {
+        [] => Err(end.error("missing operand")),
+        [(Token::Ident(name), _)] => {
+            let upper = name.to_ascii_uppercase();
+            if let Some(register) = parse_register(&upper) {
+                Ok(Operand::Register(register))
+            } else if let Some(keyword) = KEYWORDS.iter().find(|keyword| **keyword == upper) {
+                Ok(Operand::Keyword(keyword))
+            } else {
+                Ok(Operand::Value(parse_expr(tokens, end)?))
+            }
+        }
+        [(Token::Punct("["), _), (Token::Ident(name), _), (Token::Punct("]"), _)] if name.eq_ignore_ascii_case("I") => {
+            Ok(Operand::IndirectI)
+        }
+        [(Token::Ident(name), _), rest @ ..] if name.eq_ignore_ascii_case("LONG") => {
+            Ok(Operand::Long(parse_expr(rest, end)?))
+        }
+        _ => Ok(Operand::Value(parse_expr(tokens, end)?)),
+    }
+}
+
+/// `V0` - `VF`
+fn parse_register(name: &str) -> Option<u8> {
+    let digit = name.strip_prefix('V')?;
+    if digit.len() != 1 {
+        return None;
+    }
+    u8::from_str_radix(digit, 16).ok()
+}
+
+/// Splits tokens on top-level commas
+fn split_operands(tokens: &[(Token, Location)]) -> Vec<&[(Token, Location)]> {
+    if tokens.is_empty() {
+        return Vec::new();
+    }
+    tokens.split(|(token, _)| *token == Token::Punct(",")).collect()
+}
+
+#[derive(Debug, Clone)]
+enum Item {
+    Instruction { mnemonic: String, operands: Vec<Operand> },
+    Bytes(Vec<DataValue>),
+    Words(Vec<Expr>),
+}
+
+#[derive(Debug, Clone)]
+enum DataValue {
+    Expr(Expr),
+    Str(Vec<u8>),
+}
+
+/// A line of output with the address it is placed at
+struct Placed {
+    item: Item,
+    address: u32,
+    location: Location,
+}
+
+#[derive(Debug, Clone)]
+enum Symbol {
+    Label(u32),
+    Constant(Expr),
+}
+
+struct Assembler<'a> {
+    loader: &'a dyn Fn(&Path) -> io::Result<String>,
+    items: Vec<Placed>,
+    symbols: HashMap<String, (Symbol, Location)>,
+    address: u32,
+}
+
+impl<'a> Assembler<'a> {
+    fn new(loader: &'a dyn Fn(&Path) -> io::Result<String>) -> Self {
+        Self {
+            loader,
+            items: Vec::new(),
+            symbols: HashMap::new(),
+            address: START_ADDRESS as u32,
+        }
+    }
+
+    /// First pass: parses lines, places items and records symbols
+    fn add_source(&mut self, file: &str, dir: &Path, source: &str, depth: usize) -> Result<(), AsmError> {
+        for (index, text) in source.lines().enumerate() {
+            let line = index + 1;
+            let tokens = tokenize(text, file, line)?;
+            let end = Location { file: file.to_string(), line, column: text.len() + 1 };
+            let mut rest = &tokens[..];
+
+            // Labels
+            while let [(Token::Ident(name), location), (Token::Punct(":"), _), tail @ ..] = rest {
+                self.define(name, Symbol::Label(self.address), location)?;
+                rest = tail;
+            }
+            // Constants
+            if let [(Token::Ident(name), location), (separator, _), value @ ..] = rest {
+                let is_equ = matches!(separator, Token::Ident(equ) if equ.eq_ignore_ascii_case("EQU"));
+                if is_equ || *separator == Token::Punct("=") {
+                    let expr = parse_expr(value, &end)?;
+                    self.define(name, Symbol::Constant(expr), location)?;
+                    continue;
+                }
+            }
+            let [(first, location), operands @ ..] = rest else { continue };
+            let Token::Ident(name) = first else {
+                return Err(location.error("expected an instruction or directive"));
+            };
+            let mnemonic = name.trim_start_matches('.').to_ascii_uppercase();
+            let operands = split_operands(operands);
+            let (item, size) = match mnemonic.as_str() {
+                "INCLUDE" => {
+                    let [[(Token::Str(path), path_location)]] = operands[..] else {
+                        return Err(location.error("INCLUDE expects a quoted file name"));
+                    };
+                    self.include(dir, path, path_location, depth)?;
+                    continue;
+                }
+                "DB" => {
+                    let mut values = Vec::new();
+                    for operand in &operands {
+                        values.push(match operand {
+                            [(Token::Str(bytes), _)] => DataValue::Str(bytes.clone()),
+                            _ => DataValue::Expr(parse_expr(operand, &end)?),
+                        });
+                    }
+                    let size = values
+                        .iter()
+                        .map(|value| match value {
+                            DataValue::Str(bytes) => bytes.len(),
+                            DataValue::Expr(_) => 1,
+                        })
+                        .sum::<usize>();
+                    (Item::Bytes(values), size)
+                }
+                "DW" => {
+                    let words = operands.iter().map(|operand| parse_expr(operand, &end)).collect::<Result<Vec<_>, _>>()?;
+                    let size = words.len() * 2;
+                    (Item::Words(words), size)
+                }
+                _ => {
+                    let operands = operands
+                        .iter()
+                        .map(|operand| parse_operand(operand, &end))
+                        .collect::<Result<Vec<_>, _>>()?;
+                    let size = if operands.iter().any(|operand| matches!(operand, Operand::Long(_))) { 4 } else { 2 };
+                    (Item::Instruction { mnemonic, operands }, size)
+                }
+            };
+            self.items.push(Placed { item, address: self.address, location: location.clone() });
+            self.address += size as u32;
+            if self.address > 0x10000 {
+                return Err(location.error("program does not fit in 64 KiB"));
+            }
+        }
+        Ok(())
+    }
+
+    fn include(&mut self, dir: &Path, path: &[u8], location: &Location, depth: usize) -> Result<(), AsmError> {
+        if depth >= MAX_INCLUDE_DEPTH {
+            return Err(location.error("includes nested too deeply"));
+        }
+        let path: PathBuf = dir.join(String::from_utf8_lossy(path).as_ref());
+        let source = (self.loader)(&path).map_err(|err| location.error(format!("cannot include {}: {err}", path.display())))?;
+        let name = path.display().to_string();
+        self.add_source(&name, path.parent().unwrap_or(Path::new("")), &source, depth + 1)
+    }
+
+    fn define(&mut self, name: &str, symbol: Symbol, location: &Location) -> Result<(), AsmError> {
+        if parse_register(&name.to_ascii_uppercase()).is_some() || KEYWORDS.contains(&name.to_ascii_uppercase().as_str()) {
+            return Err(location.error(format!("'{name}' is a reserved name")));
+        }
+        if let Some((_, previous)) = self.symbols.get(name) {
+            return Err(location.error(format!("'{name}' is already defined on line {}", previous.line)));
+        }
+        self.symbols.insert(name.to_string(), (symbol, location.clone()));
+        Ok(())
+    }
+
+    fn eval(&self, expr: &Expr, here: u32, depth: usize) -> Result<i64, AsmError> {
+        match expr {
+            Expr::Number(value) => Ok(*value),
+            Expr::Here => Ok(here as i64),
+            Expr::Symbol(name, location) => match self.symbols.get(name) {
+                Some((Symbol::Label(address), _)) => Ok(*address as i64),
+                Some((Symbol::Constant(_), _)) if depth >= MAX_SYMBOL_DEPTH => {
+                    Err(location.error(format!("'{name}' is defined in terms of itself")))
+                }
+                Some((Symbol::Constant(value), _)) => self.eval(value, here, depth + 1),
+                None => Err(location.error(format!("undefined symbol '{name}'"))),
+            },
+            Expr::Unary(op, operand) => {
+                let value = self.eval(operand, here, depth)?;
+                Ok(match *op {
+                    "-" => value.wrapping_neg(),
+                    "~" => !value,
+                    _ => value,
+                })
+            }
+            Expr::Binary(op, lhs, rhs) => {
+                let lhs = self.eval(lhs, here, depth)?;
+                let rhs = self.eval(rhs, here, depth)?;
+                match *op {
+                    "+" => Ok(lhs.wrapping_add(rhs)),
+                    "-" => Ok(lhs.wrapping_sub(rhs)),
+                    "*" => Ok(lhs.wrapping_mul(rhs)),
+                    "/" | "%" if rhs == 0 => Err(self.location_of(expr).error("division by zero")),
+                    "/" => Ok(lhs.wrapping_div(rhs)),
+                    "%" => Ok(lhs.wrapping_rem(rhs)),
+                    "&" => Ok(lhs & rhs),
+                    "|" => Ok(lhs | rhs),
+                    "^" => Ok(lhs ^ rhs),
+                    "<<" => Ok(lhs.wrapping_shl(rhs as u32)),
+                    _ => Ok(lhs.wrapping_shr(rhs as u32)),
+                }
+            }
+        }
+    }
+
+    /// Best location for an error in `expr`, its first symbol if any
+    fn location_of(&self, expr: &Expr) -> Location {
+        match expr {
+            Expr::Symbol(_, location) => location.clone(),
+            Expr::Unary(_, operand) => self.location_of(operand),
+            Expr::Binary(_, lhs, rhs) => {
+                let location = self.location_of(lhs);
+                if location.line == 0 {
+                    self.location_of(rhs)
+                } else {
+                    location
+                }
+            }
+            _ => Location { file: String::new(), line: 0, column: 0 },
+        }
+    }
+
+    /// Evaluates `expr` and checks that it fits in `bits` bits. Negative
+    /// values are accepted down to the signed minimum and stored as two's
+    /// complement.
+    fn value(&self, expr: &Expr, placed: &Placed, bits: u32) -> Result<u16, AsmError> {
+        let value = self.eval(expr, placed.address, 0)?;
+        let max = (1i64 << bits) - 1;
+        if value > max || value < -(1i64 << (bits - 1)) {
+            let location = match self.location_of(expr) {
+                location if location.line == 0 => placed.location.clone(),
+                location => location,
+            };
+            return Err(location.error(format!("value {value} does not fit in {bits} bits")));
+        }
+        Ok((value & max) as u16)
+    }
+
+    /// Second pass: evaluates expressions and encodes every item
+    fn finish(self) -> Result<Vec<u8>, AsmError> {
+        let mut rom = Vec::new();
+        for placed in &self.items {
+            match &placed.item {
+                Item::Bytes(values) => {
+                    for value in values {
+                        match value {
+                            DataValue::Str(bytes) => rom.extend_from_slice(bytes),
+                            DataValue::Expr(expr) => rom.push(self.value(expr, placed, 8)? as u8),
+                        }
+                    }
+                }
+                Item::Words(words) => {
+                    for word in words {
+                        rom.extend_from_slice(&self.value(word, placed, 16)?.to_be_bytes());
+                    }
+                }
+                Item::Instruction { mnemonic, operands } => {
+                    let (instruction, long) = self.encode(mnemonic, operands, placed)?;
+                    rom.extend_from_slice(&instruction.encode().to_be_bytes());
+                    if let Some(address) = long {
+                        rom.extend_from_slice(&address.to_be_bytes());
+                    }
+                }
+            }
+        }
+        Ok(rom)
+    }
+
+    /// Picks the instruction matching a mnemonic and its operands, plus the
+    /// address word of `LD I, LONG`
+    fn encode(&self, mnemonic: &str, operands: &[Operand], placed: &Placed) -> Result<(Instruction, Option<u16>), AsmError> {
+        use Operand::{IndirectI, Keyword, Long, Register, Value};
+        let address = |expr| self.value(expr, placed, 12);
+        let byte = |expr| self.value(expr, placed, 8).map(|value| value as u8);
+        let nibble = |expr| self.value(expr, placed, 4).map(|value| value as u8);
+
+        let instruction = match (mnemonic, operands) {
+            ("NOP", []) => Instruction::Nop,
+            ("CLS", []) => Instruction::Clear,
+            ("RET", []) => Instruction::Return,
+            ("SCD", [Value(n)]) => Instruction::ScrollDown(nibble(n)?),
+            ("SCU", [Value(n)]) => Instruction::ScrollUp(nibble(n)?),
+            ("SCR", []) => Instruction::ScrollRight,
+            ("SCL", []) => Instruction::ScrollLeft,
+            ("EXIT", []) => Instruction::Exit,
+            ("LOW", []) => Instruction::Lores,
+            ("HIGH", []) => Instruction::Hires,
+            ("SYS", [Value(nnn)]) => Instruction::Sys(address(nnn)?),
+            ("JP", [Value(nnn)]) => Instruction::Jump(address(nnn)?),
+            ("JP", [Register(0), Value(nnn)]) => Instruction::JumpOffset(address(nnn)?),
+            ("CALL", [Value(nnn)]) => Instruction::Call(address(nnn)?),
+            ("SE", [Register(x), Value(nn)]) => Instruction::SkipEqImm { x: *x, nn: byte(nn)? },
+            ("SE", [Register(x), Register(y)]) => Instruction::SkipEq { x: *x, y: *y },
+            ("SNE", [Register(x), Value(nn)]) => Instruction::SkipNeImm { x: *x, nn: byte(nn)? },
+            ("SNE", [Register(x), Register(y)]) => Instruction::SkipNe { x: *x, y: *y },
+            ("SAVE", [Register(x), Register(y)]) => Instruction::SaveRange { x: *x, y: *y },
+            ("LOAD", [Register(x), Register(y)]) => Instruction::LoadRange { x: *x, y: *y },
+            ("LD", [Register(x), Value(nn)]) => Instruction::SetImm { x: *x, nn: byte(nn)? },
+            ("LD", [Register(x), Register(y)]) => Instruction::Set { x: *x, y: *y },
+            ("LD", [Register(x), Keyword("DT")]) => Instruction::GetDelay(*x),
+            ("LD", [Register(x), Keyword("K")]) => Instruction::WaitKey(*x),
+            ("LD", [Register(x), IndirectI]) => Instruction::Load(*x),
+            ("LD", [Register(x), Keyword("R")]) => Instruction::LoadFlags(*x),
+            ("LD", [Keyword("I"), Value(nnn)]) => Instruction::SetI(address(nnn)?),
+            ("LD", [Keyword("I"), Long(nnnn)]) => {
+                return Ok((Instruction::SetILong, Some(self.value(nnnn, placed, 16)?)));
+            }
+            ("LD", [Keyword("DT"), Register(x)]) => Instruction::SetDelay(*x),
+            ("LD", [Keyword("ST"), Register(x)]) => Instruction::SetSound(*x),
+            ("LD", [Keyword("F"), Register(x)]) => Instruction::Font(*x),
+            ("LD", [Keyword("HF"), Register(x)]) => Instruction::BigFont(*x),
+            ("LD", [Keyword("B"), Register(x)]) => Instruction::Bcd(*x),
+            ("LD", [IndirectI, Register(x)]) => Instruction::Store(*x),
+            ("LD", [Keyword("R"), Register(x)]) => Instruction::StoreFlags(*x),
+            ("ADD", [Register(x), Value(nn)]) => Instruction::AddImm { x: *x, nn: byte(nn)? },
+            ("ADD", [Register(x), Register(y)]) => Instruction::Add { x: *x, y: *y },
+            ("ADD", [Keyword("I"), Register(x)]) => Instruction::AddI(*x),
+            ("OR", [Register(x), Register(y)]) => Instruction::Or { x: *x, y: *y },
+            ("AND", [Register(x), Register(y)]) => Instruction::And { x: *x, y: *y },
+            ("XOR", [Register(x), Register(y)]) => Instruction::Xor { x: *x, y: *y },
+            ("SUB", [Register(x), Register(y)]) => Instruction::Sub { x: *x, y: *y },
+            ("SUBN", [Register(x), Register(y)]) => Instruction::SubReverse { x: *x, y: *y },
+            ("SHR", [Register(x)]) => Instruction::ShiftRight { x: *x, y: *x },
+            ("SHR", [Register(x), Register(y)]) => Instruction::ShiftRight { x: *x, y: *y },
+            ("SHL", [Register(x)]) => Instruction::ShiftLeft { x: *x, y: *x },
+            ("SHL", [Register(x), Register(y)]) => Instruction::ShiftLeft { x: *x, y: *y },
+            ("RND", [Register(x), Value(nn)]) => Instruction::Random { x: *x, nn: byte(nn)? },
+            ("DRW", [Register(x), Register(y), Value(n)]) => Instruction::Draw { x: *x, y: *y, n: nibble(n)? },
+            ("SKP", [Register(x)]) => Instruction::SkipKey(*x),
+            ("SKNP", [Register(x)]) => Instruction::SkipNotKey(*x),
+            ("PLANE", [Value(n)]) => Instruction::Plane(nibble(n)?),
+            ("AUDIO", []) => Instruction::Audio,
+            ("PITCH", [Register(x)]) => Instruction::Pitch(*x),
+            _ if is_mnemonic(mnemonic) => {
+                return Err(placed.location.error(format!("invalid operands for {mnemonic}")));
+            }
+            _ => return Err(placed.location.error(format!("unknown instruction '{mnemonic}'"))),
+        };
+        Ok((instruction, None))
+    }
+}
+
+fn is_mnemonic(mnemonic: &str) -> bool {
+    const MNEMONICS: [&str; 33] = [
+        "NOP", "CLS", "RET", "SCD", "SCU", "SCR", "SCL", "EXIT", "LOW", "HIGH", "SYS", "JP", "CALL", "SE", "SNE", "SAVE",
+        "LOAD", "LD", "ADD", "OR", "AND", "XOR", "SUB", "SUBN", "SHR", "SHL", "RND", "DRW", "SKP", "SKNP", "PLANE",
+        "AUDIO", "PITCH",
+    ];
+    MNEMONICS.contains(&mnemonic)
+}
+
+#[cfg(test)]
+mod tests;
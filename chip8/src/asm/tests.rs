@@ -0,0 +1,176 @@
+use super::*;
+use crate::config::{Config, Mode};
+use crate::emulator::Emulator;
+use crate::instruction::{decode, decode_for};
+
+fn words(rom: &[u8]) -> Vec<u16> {
+    rom.chunks(2).map(|word| u16::from_be_bytes([word[0], word[1]])).collect()
+}
+
+fn error(source: &str) -> AsmError {
+    assemble(source).unwrap_err()
+}
+
+#[test]
+fn every_opcode_round_trips() {
+    // The disassembler's mnemonics assemble back to the same word, which
+    // decodes to the same instruction the emulator executes
+    for operation in 0..=u16::MAX {
+        let instruction = decode(operation);
+        if instruction == Instruction::SetILong {
+            continue;
+        }
+        let rom = assemble(&instruction.to_string()).unwrap_or_else(|err| panic!("{instruction}: {err}"));
+        assert_eq!(words(&rom), [operation], "{instruction}");
+        assert_eq!(decode_for(words(&rom)[0], Mode::XoChip), instruction);
+    }
+}
+
+#[test]
+fn alternative_forms() {
+    let rom = assemble("ld i, long 0x1234\nshr v1\nshl vA\nld v0, -1\nlow\n.db 1").unwrap();
+    assert_eq!(rom, [0xF0, 0x00, 0x12, 0x34, 0x81, 0x16, 0x8A, 0xAE, 0x60, 0xFF, 0x00, 0xFE, 0x01]);
+}
+
+#[test]
+fn labels_and_forward_references() {
+    let source = "
+start:  CALL draw
+        JP start
+draw:   LD I, sprite
+        DRW V0, V1, sprite_end - sprite
+        RET
+sprite: DB 0xF0, 0x90, 0xF0
+sprite_end:
+";
+    let rom = assemble(source).unwrap();
+    assert_eq!(words(&rom[..10]), [0x2204, 0x1200, 0xA20A, 0xD013, 0x00EE]);
+    assert_eq!(rom[10..], [0xF0, 0x90, 0xF0]);
+}
+
+#[test]
+fn constants_and_expressions() {
+    let source = "
+WIDTH EQU 64
+HALF = WIDTH / 2
+    LD V0, HALF - 1
+    LD V1, (WIDTH * 3) % 10 | 0b1000
+    LD V2, ~0 & $0F
+    LD V3, 1 << 4 + 1
+    JP $
+    DW $, HALF
+";
+    let rom = assemble(source).unwrap();
+    assert_eq!(words(&rom), [0x601F, 0x610A, 0x620F, 0x6320, 0x1208, 0x020A, 0x0020]);
+}
+
+#[test]
+fn data_directives() {
+    let rom = assemble("db \"Hi\\n\", 0x21\ndw 0xBEEF, 1").unwrap();
+    assert_eq!(rom, [b'H', b'i', b'\n', 0x21, 0xBE, 0xEF, 0x00, 0x01]);
+}
+
+#[test]
+fn includes_resolve_relative_to_file() {
+    let loader = |path: &Path| match path.to_str().unwrap() {
+        "src/main.asm" => Ok("CALL sub\nINCLUDE \"lib/sub.asm\"\n".to_string()),
+        "src/lib/sub.asm" => Ok("sub: RET\n".to_string()),
+        "src/loop.asm" => Ok("INCLUDE \"loop.asm\"\n".to_string()),
+        _ => Err(io::Error::from(io::ErrorKind::NotFound)),
+    };
+    let rom = assemble_with_loader(Path::new("src/main.asm"), &loader).unwrap();
+    assert_eq!(words(&rom), [0x2202, 0x00EE]);
+
+    let err = assemble_with_loader(Path::new("src/loop.asm"), &loader).unwrap_err();
+    assert_eq!(err.message, "includes nested too deeply");
+}
+
+#[test]
+fn expression_nesting_is_limited() {
+    let nested = |depth: usize| format!("LD V0, {}1{}", "(".repeat(depth), ")".repeat(depth));
+    assert_eq!(words(&assemble(&nested(256)).unwrap()), [0x6001]);
+    let err = error(&nested(257));
+    assert_eq!((err.line, err.column, err.message.as_str()), (1, 264, "expression nested too deeply"));
+    assert_eq!(error(&nested(100_000)).message, "expression nested too deeply");
+    assert_eq!(error(&format!("LD V0, {}1", "-".repeat(100_000))).message, "expression nested too deeply");
+    assert_eq!(error(&format!("LD V0, {}1", "~".repeat(100_000))).message, "expression nested too deeply");
+
+    // Long chains of operators deepen the tree without any nesting
+    let chain = |terms: usize| format!("LD V0, {}", vec!["1"; terms].join(" * "));
+    assert_eq!(words(&assemble(&chain(257)).unwrap()), [0x6001]);
+    assert_eq!(error(&chain(100_000)).message, "expression nested too deeply");
+}
+
+#[test]
+fn errors_point_at_the_problem() {
+    let err = error("CLS\n  JP nowhere");
+    assert_eq!((err.line, err.column), (2, 6));
+    assert_eq!(err.to_string(), "<input>:2:6: undefined symbol 'nowhere'");
+
+    let err = error("LD V0, 256");
+    assert_eq!((err.line, err.column, err.message.as_str()), (1, 1, "value 256 does not fit in 8 bits"));
+
+    let err = error("DRW V0, V1, 16");
+    assert_eq!(err.message, "value 16 does not fit in 4 bits");
+
+    let err = error("\n\n  FOO V0");
+    assert_eq!((err.line, err.column), (3, 3));
+    assert_eq!(err.message, "unknown instruction 'FOO'");
+
+    let err = error("ADD V0, DT");
+    assert_eq!(err.message, "invalid operands for ADD");
+
+    let err = error("a: CLS\na: CLS");
+    assert_eq!((err.line, err.message.as_str()), (2, "'a' is already defined on line 1"));
+
+    let err = error("LD V0, (1 + 2");
+    assert_eq!((err.line, err.column, err.message.as_str()), (1, 14, "expected ')'"));
+
+    let err = error("X EQU X + 1\nLD V0, X");
+    assert_eq!(err.message, "'X' is defined in terms of itself");
+
+    assert_eq!(error("LD V0, 1 / 0").message, "division by zero");
+    assert_eq!(error("v1: CLS").message, "'v1' is a reserved name");
+    assert_eq!(error("DB \"open").message, "unterminated string");
+}
+
+#[test]
+fn assembled_program_runs() {
+    let source = "
+        LD V0, 10
+loop:   ADD V1, 3
+        ADD V0, -1
+        SE V0, 0
+        JP loop
+done:   JP done
+";
+    let rom = assemble(source).unwrap();
+    let mut emulator = Emulator::with_config(Config::default());
+    emulator.load_rom(&rom).unwrap();
+    for _ in 0..50 {
+        emulator.tick().unwrap();
+    }
+    assert_eq!(emulator.get_cpu_state().v_registers[1], 30);
+    assert_eq!(emulator.get_cpu_state().program_counter, 0x20A);
+}
+
+#[test]
+fn disassembly_reassembles() {
+    use crate::disasm::{disassemble, DisasmOptions};
+    let rom = assemble(
+        "
+start:  LD I, LONG sprite
+        CALL draw
+        SNE V0, 3
+        LD I, LONG sprite
+        JP start
+draw:   DRW V0, V1, 4
+        RET
+sprite: DB 0x18, 0x3C, 0x7E, 0xFF, 0x00
+",
+    )
+    .unwrap();
+    let options = DisasmOptions { mode: Mode::XoChip, ..DisasmOptions::default() };
+    let listing = disassemble(&rom, &options).unwrap().to_string();
+    assert_eq!(assemble(&listing).unwrap(), rom, "{listing}");
+}
@@ -0,0 +1,125 @@
+use crate::memory::{AUDIO_PATTERN_SIZE, DEFAULT_PITCH};
+
+/// Frequency of the classic CHIP-8 buzzer
+pub const DEFAULT_BEEP_FREQUENCY: f32 = 440.0;
+/// Peak amplitude of generated samples
+pub const DEFAULT_VOLUME: f32 = 0.25;
+/// Bits in the XO-CHIP audio pattern
+const PATTERN_BITS: usize = AUDIO_PATTERN_SIZE * 8;
+
+/// Sound parameters of the machine at a point in time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AudioState {
+    /// Sound timer is running
+    pub active: bool,
+    /// XO-CHIP 1-bit pattern, `None` plays the classic square wave beep
+    pub pattern: Option<[u8; AUDIO_PATTERN_SIZE]>,
+    /// XO-CHIP playback rate register
+    pub pitch: u8,
+}
+
+impl Default for AudioState {
+    fn default() -> Self {
+        Self {
+            active: false,
+            pattern: None,
+            pitch: DEFAULT_PITCH,
+        }
+    }
+}
+
+impl AudioState {
+    /// Pattern playback rate in bits per second: 4000 * 2 ^ ((pitch - 64) / 48)
+    pub fn pattern_rate(&self) -> f32 {
+        4000.0 * 2f32.powf((self.pitch as f32 - 64.0) / 48.0)
+    }
+}
+
+/// Frontend agnostic PCM generator.
+///
+/// Frontends update the state once per frame with `Emulator::get_audio_state`
+/// and pull as many mono samples as their output needs.
+pub struct AudioGenerator {
+    sample_rate: u32,
+    beep_frequency: f32,
+    volume: f32,
+    state: AudioState,
+    phase: f32,
+}
+
+impl AudioGenerator {
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            sample_rate,
+            beep_frequency: DEFAULT_BEEP_FREQUENCY,
+            volume: DEFAULT_VOLUME,
+            state: AudioState::default(),
+            phase: 0.0,
+        }
+    }
+
+    pub fn get_sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn set_beep_frequency(&mut self, frequency: f32) {
+        self.beep_frequency = frequency;
+    }
+
+    pub fn set_volume(&mut self, volume: f32) {
+        self.volume = volume;
+    }
+
+    pub fn set_state(&mut self, state: AudioState) {
+        // Restart the waveform on each new sound so it starts on a rising edge
+        if state.active && !self.state.active {
+            self.phase = 0.0;
+        }
+        self.state = state;
+    }
+
+    /// Number of samples covering one 60 Hz frame
+    pub fn samples_per_frame(&self) -> usize {
+        (self.sample_rate as usize).div_ceil(60)
+    }
+
+    /// Fills the buffer with samples in the range -volume..=volume
+    pub fn generate(&mut self, buffer: &mut [f32]) {
+        if !self.state.active {
+            buffer.fill(0.0);
+            return;
+        }
+        match self.state.pattern {
+            Some(pattern) => {
+                // Phase counts pattern bits
+                let step = self.state.pattern_rate() / self.sample_rate as f32;
+                for sample in buffer.iter_mut() {
+                    let bit = self.phase as usize % PATTERN_BITS;
+                    let on = pattern[bit / 8] & (0b1000_0000 >> (bit % 8)) != 0;
+                    *sample = if on { self.volume } else { -self.volume };
+                    self.phase = (self.phase + step) % PATTERN_BITS as f32;
+                }
+            }
+            None => {
+                // Phase counts waveform cycles
+                let step = self.beep_frequency / self.sample_rate as f32;
+                for sample in buffer.iter_mut() {
+                    *sample = if self.phase < 0.5 { self.volume } else { -self.volume };
+                    self.phase = (self.phase + step) % 1.0;
+                }
+            }
+        }
+    }
+
+    /// Fills the buffer with signed 16 bit samples, as used by WAV files
+    pub fn generate_i16(&mut self, buffer: &mut [i16]) {
+        let mut samples = vec![0.0; buffer.len()];
+        self.generate(&mut samples);
+        for (out, sample) in buffer.iter_mut().zip(samples) {
+            *out = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,56 @@
+use super::*;
+
+fn state(pattern: Option<[u8; AUDIO_PATTERN_SIZE]>) -> AudioState {
+    AudioState { active: true, pattern, pitch: DEFAULT_PITCH }
+}
+
+#[test]
+fn silent_when_sound_timer_stopped() {
+    let mut generator = AudioGenerator::new(44100);
+    let mut buffer = [1.0; 64];
+    generator.generate(&mut buffer);
+    assert!(buffer.iter().all(|&sample| sample == 0.0));
+}
+
+#[test]
+fn beep_is_square_wave() {
+    // 400 Hz at 8000 Hz gives a 20 sample period
+    let mut generator = AudioGenerator::new(8000);
+    generator.set_beep_frequency(400.0);
+    generator.set_state(state(None));
+    let mut buffer = [0.0; 40];
+    generator.generate(&mut buffer);
+    assert!(buffer[..10].iter().all(|&sample| sample == DEFAULT_VOLUME));
+    assert!(buffer[10..20].iter().all(|&sample| sample == -DEFAULT_VOLUME));
+    assert_eq!(buffer[..20], buffer[20..]);
+}
+
+#[test]
+fn pattern_plays_at_pitch_rate() {
+    // Pitch 64 plays 4000 bits per second, two samples per bit at 8000 Hz
+    let mut pattern = [0; AUDIO_PATTERN_SIZE];
+    pattern[0] = 0xF0;
+    let mut generator = AudioGenerator::new(8000);
+    generator.set_state(state(Some(pattern)));
+    let mut buffer = [0.0; 256];
+    generator.generate(&mut buffer);
+    assert!(buffer[..8].iter().all(|&sample| sample > 0.0));
+    assert!(buffer[8..].iter().all(|&sample| sample < 0.0));
+}
+
+#[test]
+fn pattern_rate_doubles_every_48_steps() {
+    let mut audio = state(None);
+    audio.pitch = 112;
+    assert_eq!(audio.pattern_rate(), 8000.0);
+}
+
+#[test]
+fn i16_samples_scale_volume() {
+    let mut generator = AudioGenerator::new(8000);
+    generator.set_volume(1.0);
+    generator.set_state(state(None));
+    let mut buffer = [0; 4];
+    generator.generate_i16(&mut buffer);
+    assert_eq!(buffer[0], i16::MAX);
+}
@@ -0,0 +1,96 @@
+use std::str::FromStr;
+use crate::memory::{RAM_SIZE, XO_RAM_SIZE};
+use crate::quirks::Quirks;
+use crate::rewind::RewindConfig;
+
+/// What the emulator does when an instruction faults
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FaultPolicy {
+    /// Stop the machine and report the fault
+    #[default]
+    Halt,
+    /// Treat the faulting instruction as a no-op and keep running
+    Skip,
+    /// Wrap memory addresses and the stack pointer around; other faults are skipped
+    Wrap,
+}
+
+/// Instruction set and machine model to emulate
+///
+/// Modes are ordered by instruction set, each one extends the previous.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum Mode {
+    /// Original CHIP-8
+    #[default]
+    Chip8,
+    /// SUPER-CHIP 1.1: hires mode, scrolling, 16x16 sprites, large font and RPL flags
+    SuperChip,
+    /// XO-CHIP: SUPER-CHIP plus 64 KiB of memory, two bitplanes and pattern audio
+    XoChip,
+}
+
+impl Mode {
+    /// Amount of addressable memory
+    pub fn ram_size(&self) -> usize {
+        match self {
+            Mode::Chip8 | Mode::SuperChip => RAM_SIZE,
+            Mode::XoChip => XO_RAM_SIZE,
+        }
+    }
+}
+
+impl FromStr for Mode {
+    type Err = String;
+
+    /// Parses the names used on frontend command lines
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "chip8" => Ok(Mode::Chip8),
+            "schip" => Ok(Mode::SuperChip),
+            "xochip" => Ok(Mode::XoChip),
+            _ => Err(format!("unknown mode: {name}")),
+        }
+    }
+}
+
+/// Default number of instructions executed per 60 Hz frame
+pub const DEFAULT_INSTRUCTIONS_PER_FRAME: u32 = 11;
+
+/// Emulator settings chosen at construction
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub mode: Mode,
+    pub quirks: Quirks,
+    pub fault_policy: FaultPolicy,
+    /// Instructions executed by `Emulator::run_frame` before the timers tick
+    pub instructions_per_frame: u32,
+    /// Records rewind history when set
+    pub rewind: Option<RewindConfig>,
+    /// Seed for the CXNN generator, chosen at random by the emulator when unset
+    pub seed: Option<u64>,
+}
+
+impl Config {
+    /// Default settings with the quirks most programs for the mode expect
+    pub fn for_mode(mode: Mode) -> Self {
+        let quirks = match mode {
+            Mode::Chip8 => Quirks::COSMAC_VIP,
+            Mode::SuperChip => Quirks::SCHIP_1_1,
+            Mode::XoChip => Quirks::XO_CHIP,
+        };
+        Self { mode, quirks, ..Self::default() }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            mode: Mode::default(),
+            quirks: Quirks::default(),
+            fault_policy: FaultPolicy::default(),
+            instructions_per_frame: DEFAULT_INSTRUCTIONS_PER_FRAME,
+            rewind: None,
+            seed: None,
+        }
+    }
+}
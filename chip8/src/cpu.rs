@@ -1,32 +1,87 @@
-use std::cell::RefCell;
-use std::rc::Rc;
-use rand::random;
-use crate::emulator::{Emulator, EmulatorComponent};
+use crate::display::BIG_FONT_ADDRESS;
+use crate::emulator::EmulatorComponent;
+use crate::error::Chip8Error;
+use crate::memory::Memory;
+use crate::rng::Rng;
+use crate::state::{invalid, StateReader, StateWriter};
 
-const NUMBER_OF_REGISTERS: usize = 16;
-const STACK_SIZE: usize = 16;
-const START_ADDRESS: u16 = 0x200;
+pub const NUMBER_OF_REGISTERS: usize = 16;
+pub const STACK_SIZE: usize = 16;
+const NUMBER_OF_FLAGS: usize = 16;
+pub const START_ADDRESS: u16 = 0x200;
 
+/// Snapshot of the CPU registers for debuggers and frontends
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CpuState {
+    /// V0 - VF
+    pub v_registers: [u8; NUMBER_OF_REGISTERS],
+    pub i_register: u16,
+    pub program_counter: u16,
+    pub stack_pointer: u16,
+    /// Return addresses, only the first `stack_pointer` entries are in use
+    pub stack: [u16; STACK_SIZE],
+}
+
+#[allow(clippy::upper_case_acronyms)]
 pub struct CPU {
-    emulator: Rc<RefCell<Emulator>>,
     v_registers: [u8; NUMBER_OF_REGISTERS],
     i_register: u16,
     stack: [u16; STACK_SIZE],
     program_counter: u16,
     stack_pointer: u16,
+    flag_registers: [u8; NUMBER_OF_FLAGS],
+    wrap_stack: bool,
 }
 
 impl CPU {
-    pub fn new(emulator: Rc<RefCell<Emulator>>) -> Self {
-        let cpu = Self {
-            emulator,
+    pub fn new() -> Self {
+        Self {
             v_registers: [0; NUMBER_OF_REGISTERS],
             i_register: 0,
             stack: [0; STACK_SIZE],
             program_counter: START_ADDRESS,
             stack_pointer: 0,
-        };
-        cpu
+            flag_registers: [0; NUMBER_OF_FLAGS],
+            wrap_stack: false,
+        }
+    }
+
+    /// Copy of all registers and the stack
+    pub fn get_state(&self) -> CpuState {
+        CpuState {
+            v_registers: self.v_registers,
+            i_register: self.i_register,
+            program_counter: self.program_counter,
+            stack_pointer: self.stack_pointer,
+            stack: self.stack,
+        }
+    }
+
+    /// Serializes registers, stack and RPL flags
+    pub(crate) fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&self.v_registers);
+        writer.write_u16(self.i_register);
+        writer.write_u16(self.program_counter);
+        writer.write_u16(self.stack_pointer);
+        for &address in &self.stack {
+            writer.write_u16(address);
+        }
+        writer.write_bytes(&self.flag_registers);
+    }
+
+    pub(crate) fn load_state(&mut self, reader: &mut StateReader) -> Result<(), Chip8Error> {
+        self.v_registers.copy_from_slice(reader.read_bytes(NUMBER_OF_REGISTERS)?);
+        self.i_register = reader.read_u16()?;
+        self.program_counter = reader.read_u16()?;
+        self.stack_pointer = reader.read_u16()?;
+        if self.stack_pointer as usize > STACK_SIZE {
+            return Err(invalid("stack pointer out of range"));
+        }
+        for address in &mut self.stack {
+            *address = reader.read_u16()?;
+        }
+        self.flag_registers.copy_from_slice(reader.read_bytes(NUMBER_OF_FLAGS)?);
+        Ok(())
     }
 
     pub fn get_program_counter(&self) -> u16 {
@@ -41,6 +96,10 @@ impl CPU {
         self.i_register
     }
 
+    pub fn set_i_register(&mut self, value: u16) {
+        self.i_register = value;
+    }
+
     pub fn set_register_value(&mut self, index: usize, value: u8) {
         self.v_registers[index] = value;
     }
@@ -49,27 +108,47 @@ impl CPU {
         self.v_registers[index]
     }
 
-    fn push(&mut self, val: u16) {
+    /// Sets whether the stack pointer wraps around instead of faulting
+    pub fn set_wrap_stack(&mut self, wrap: bool) {
+        self.wrap_stack = wrap;
+    }
+
+    fn push(&mut self, val: u16) -> Result<(), Chip8Error> {
+        if self.stack_pointer as usize >= STACK_SIZE {
+            if !self.wrap_stack {
+                return Err(Chip8Error::StackOverflow);
+            }
+            self.stack_pointer = 0;
+        }
         self.stack[self.stack_pointer as usize] = val;
         self.stack_pointer += 1;
+        Ok(())
     }
 
-    fn pop(&mut self) -> u16 {
+    fn pop(&mut self) -> Result<u16, Chip8Error> {
+        if self.stack_pointer == 0 {
+            if !self.wrap_stack {
+                return Err(Chip8Error::StackUnderflow);
+            }
+            self.stack_pointer = STACK_SIZE as u16;
+        }
         self.stack_pointer -= 1;
-        self.stack[self.stack_pointer as usize]
+        Ok(self.stack[self.stack_pointer as usize])
     }
 
     /// RET - Return from subroutine
-    pub fn op_ret(&mut self) {
-        let return_address = self.pop();
+    pub fn op_ret(&mut self) -> Result<(), Chip8Error> {
+        let return_address = self.pop()?;
         self.program_counter = return_address;
+        Ok(())
     }
 
     /// CALL - Call subroutine
-    pub fn op_call(&mut self, operation: u16) {
+    pub fn op_call(&mut self, operation: u16) -> Result<(), Chip8Error> {
         let nnn = operation & 0xFFF;
-        self.push(self.program_counter);
+        self.push(self.program_counter)?;
         self.program_counter = nnn;
+        Ok(())
     }
 
     /// JMP NNN - Move program counter to given address
@@ -79,31 +158,34 @@ impl CPU {
     }
 
     /// JMP V0 + NNN - Move program counter to given address
-    pub fn op_reg_jmp(&mut self, operation: u16) {
+    ///
+    /// With `use_vx` the high nibble of NNN selects the register instead (BXNN).
+    pub fn op_reg_jmp(&mut self, operation: u16, use_vx: bool) {
         let nnn = operation & 0xFFF;
-        self.program_counter = (self.v_registers[0] as u16) + nnn;
+        let x = if use_vx { (nnn >> 8) as usize } else { 0 };
+        self.program_counter = (self.v_registers[x] as u16) + nnn;
     }
 
     /// SKIP VX == NN - Skip next instruction if register VX == NN
     pub fn op_se(&mut self, operation: u16, x: usize) {
         let nn = (operation & 0xFF) as u8;
         if self.v_registers[x] == nn {
-            self.program_counter += 2;
+            self.program_counter = self.program_counter.wrapping_add(2);
         }
     }
 
     /// SKIP VX == VY - Skip next instruction if register VX == NN
     pub fn op_reg_se(&mut self, x: usize, y: usize) {
         if self.v_registers[x] == self.v_registers[y] {
-            self.program_counter += 2;
+            self.program_counter = self.program_counter.wrapping_add(2);
         }
     }
 
     /// SKIP VX != NN - Skip next instruction if register VX != NN
     pub fn op_sne(&mut self, operation: u16, x: usize) {
         let nn = (operation & 0xFF) as u8;
-        if self.v_registers[x] == nn {
-            self.program_counter += 2;
+        if self.v_registers[x] != nn {
+            self.program_counter = self.program_counter.wrapping_add(2);
         }
     }
 
@@ -118,10 +200,10 @@ impl CPU {
         self.v_registers[x] = self.v_registers[y];
     }
 
-    /// ADD VX += NN - Add NN to VX
+    /// ADD VX += NN - Add NN to VX, wrapping without touching VF
     pub fn op_add(&mut self, operation: u16, x: usize) {
         let nn = (operation & 0xFF) as u8;
-        self.v_registers[x] += nn;
+        self.v_registers[x] = self.v_registers[x].wrapping_add(nn);
     }
 
     /// ADD VX += VY - Add VY to VX
@@ -147,19 +229,41 @@ impl CPU {
     }
 
     /// OR VX |= VY - Bitwise OR between VX and VY
-    pub fn op_reg_or(&mut self, x: usize, y: usize) {
+    pub fn op_reg_or(&mut self, x: usize, y: usize, vf_reset: bool) {
         self.v_registers[x] |= self.v_registers[y];
+        if vf_reset {
+            self.v_registers[0xF] = 0;
+        }
+    }
+
+    /// AND VX &= VY - Bitwise AND between VX and VY
+    pub fn op_reg_and(&mut self, x: usize, y: usize, vf_reset: bool) {
+        self.v_registers[x] &= self.v_registers[y];
+        if vf_reset {
+            self.v_registers[0xF] = 0;
+        }
+    }
+
+    /// XOR VX ^= VY - Bitwise XOR between VX and VY
+    pub fn op_reg_xor(&mut self, x: usize, y: usize, vf_reset: bool) {
+        self.v_registers[x] ^= self.v_registers[y];
+        if vf_reset {
+            self.v_registers[0xF] = 0;
+        }
     }
 
     /// SHR VX >>= 1 - Bitwise shift left or right one
-    pub fn op_shift(&mut self, x: usize, right: bool) {
+    ///
+    /// With `use_vy` VY is shifted into VX, otherwise VX is shifted in place.
+    pub fn op_shift(&mut self, x: usize, y: usize, right: bool, use_vy: bool) {
+        let value = if use_vy { self.v_registers[y] } else { self.v_registers[x] };
         let bit;
         if right {
-            bit = self.v_registers[x] & 1;
-            self.v_registers[x] >>= 1;
+            bit = value & 1;
+            self.v_registers[x] = value >> 1;
         } else {
-            bit = (self.v_registers[x] >> 7) & 1;
-            self.v_registers[x] <<= 1;
+            bit = (value >> 7) & 1;
+            self.v_registers[x] = value << 1;
         }
         self.v_registers[0xF] = bit;
     }
@@ -167,7 +271,7 @@ impl CPU {
     /// SNE VX != VY - Skip next instruction if VX != VY
     pub fn op_reg_sne(&mut self, x: usize, y: usize) {
         if self.v_registers[x] != self.v_registers[y] {
-            self.program_counter += 2;
+            self.program_counter = self.program_counter.wrapping_add(2);
         }
     }
 
@@ -178,15 +282,14 @@ impl CPU {
     }
 
     /// RND Vx = Rand & NN
-    pub fn op_rnd(&mut self, operation: u16, x: usize) {
+    pub fn op_rnd(&mut self, rng: &mut dyn Rng, operation: u16, x: usize) {
         let nn = (operation & 0xFF) as u8;
-        let rand: u8 = random();
-        self.v_registers[x] = rand & nn;
+        self.v_registers[x] = rng.next_byte() & nn;
     }
 
     /// LD VX = DT - Load VX with Delay Timer value
-    pub fn op_ld_dt(&mut self, x: usize) {
-        self.v_registers[x] = self.emulator.get_mut().get_memory().get_delay_timer()
+    pub fn op_ld_dt(&mut self, memory: &Memory, x: usize) {
+        self.v_registers[x] = memory.get_delay_timer()
     }
 
     /// ADD I += VX - Add Vx to I
@@ -197,11 +300,34 @@ impl CPU {
 
     /// LD I = FONT - Load font into i register
     pub fn op_ld_font(&mut self, x: usize) {
-        let c = self.v_registers[x] as u16;
+        let c = (self.v_registers[x] & 0xF) as u16;
         self.i_register = c * 5;
     }
+
+    /// LD I = BIG FONT - Load large font character into i register
+    pub fn op_ld_big_font(&mut self, x: usize) {
+        let c = (self.v_registers[x] & 0xF) as u16;
+        self.i_register = BIG_FONT_ADDRESS as u16 + c * 10;
+    }
+
+    /// STR FLAGS - Save V0 - VX into the RPL user flags
+    pub fn op_str_flags(&mut self, x: usize) {
+        self.flag_registers[..=x].copy_from_slice(&self.v_registers[..=x]);
+    }
+
+    /// LD FLAGS - Load V0 - VX from the RPL user flags
+    pub fn op_ld_flags(&mut self, x: usize) {
+        self.v_registers[..=x].copy_from_slice(&self.flag_registers[..=x]);
+    }
+}
+
+impl Default for CPU {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
+/// RPL user flags survive a reset, like on the HP-48
 impl EmulatorComponent for CPU {
     fn reset(&mut self) {
         self.v_registers = [0; NUMBER_OF_REGISTERS];
@@ -0,0 +1,484 @@
+//! Run control for debuggers: breakpoints, watchpoints, stepping over and
+//! out of subroutines, running to an address and stepping backwards.
+//!
+//! The debugger drives the emulator one instruction at a time with
+//! `Emulator::tick`, ending a frame after the configured number of
+//! instructions so timers run at the same rate as with `run_frame`.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::ops::RangeInclusive;
+use crate::emulator::{Emulator, StepOutcome};
+use crate::error::Chip8Error;
+use crate::instruction::{decode_for, Instruction};
+use crate::memory::{Access, MemoryAccess};
+
+mod expr;
+mod history;
+
+pub use expr::Expression;
+pub use history::{DEFAULT_SNAPSHOT_INTERVAL, MAX_SNAPSHOTS};
+
+use history::{History, Snapshot};
+
+/// Instructions a single resume may execute before giving up
+pub const DEFAULT_INSTRUCTION_LIMIT: u64 = 10_000_000;
+
+/// Why execution stopped
+#[derive(Debug)]
+pub enum Stop {
+    /// The requested step or run completed
+    Done,
+    /// About to execute an instruction with a breakpoint on it
+    Breakpoint(u16),
+    /// Blocked on FX0A until a key is pressed
+    WaitingForKey,
+    /// Program exited with 00FD
+    Exited,
+    /// Machine is halted after an earlier fault
+    Halted,
+    /// Instruction faulted
+    Fault(Chip8Error),
+    /// Executed the instruction limit without stopping
+    LimitReached,
+    /// The instruction just executed accessed watched memory
+    MemoryWatch { number: usize, access: MemoryAccess },
+    /// A watched condition turned true
+    Condition { number: usize },
+    /// Reverse execution reached the oldest recorded state
+    HistoryStart,
+}
+
+/// What a watchpoint stops on, checked after every instruction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Watchpoint {
+    /// Accesses of the given kinds to a range of RAM
+    Memory { range: RangeInclusive<usize>, accesses: Vec<Access> },
+    /// The expression turning true, e.g. `V3 == 0x10` to stop when V3
+    /// takes that value
+    Condition(Expression),
+}
+
+/// Wraps an emulator with breakpoints and run control
+pub struct Debugger {
+    emulator: Emulator,
+    breakpoints: BTreeSet<u16>,
+    /// Conditions of conditional breakpoints
+    breakpoint_conditions: HashMap<u16, Expression>,
+    watchpoints: BTreeMap<usize, Watchpoint>,
+    /// Whether each condition watchpoint held after the last instruction
+    condition_states: HashMap<usize, bool>,
+    next_watchpoint: usize,
+    /// Instructions executed in the current frame
+    frame_cycles: u32,
+    /// Instructions executed since the debugger was created, less any
+    /// stepped back over
+    cycles: u64,
+    instruction_limit: u64,
+    history: History,
+    /// Whether the emulator may have been changed since the last snapshot
+    modified: bool,
+}
+
+impl Debugger {
+    /// Constructor
+    pub fn new(emulator: Emulator) -> Self {
+        Self {
+            emulator,
+            breakpoints: BTreeSet::new(),
+            breakpoint_conditions: HashMap::new(),
+            watchpoints: BTreeMap::new(),
+            condition_states: HashMap::new(),
+            next_watchpoint: 1,
+            frame_cycles: 0,
+            cycles: 0,
+            instruction_limit: DEFAULT_INSTRUCTION_LIMIT,
+            history: History::new(),
+            modified: false,
+        }
+    }
+
+    pub fn get_emulator(&self) -> &Emulator {
+        &self.emulator
+    }
+
+    /// Mutable access to the emulator. Changes made through it, such as
+    /// key presses, are recorded so reverse execution replays them.
+    pub fn get_emulator_mut(&mut self) -> &mut Emulator {
+        self.modified = true;
+        &mut self.emulator
+    }
+
+    /// Instructions executed since the debugger was created, less any
+    /// stepped back over
+    pub fn get_cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// Sets the instructions between snapshots. Shorter intervals make
+    /// reverse execution faster at the cost of memory.
+    pub fn set_snapshot_interval(&mut self, interval: u64) {
+        self.history.set_interval(interval);
+    }
+
+    /// Oldest cycle reverse execution can return to
+    pub fn get_history_start(&self) -> Option<u64> {
+        self.history.start()
+    }
+
+    /// Caps how many instructions a single resume may execute
+    pub fn set_instruction_limit(&mut self, limit: u64) {
+        self.instruction_limit = limit;
+    }
+
+    /// Adds a breakpoint, returning false if one was already set there.
+    /// A condition on an existing breakpoint is removed.
+    pub fn add_breakpoint(&mut self, address: u16) -> bool {
+        self.breakpoint_conditions.remove(&address);
+        self.breakpoints.insert(address)
+    }
+
+    /// Adds a breakpoint that only stops when `condition` holds, replacing
+    /// any breakpoint already set there
+    pub fn add_conditional_breakpoint(&mut self, address: u16, condition: Expression) {
+        self.breakpoints.insert(address);
+        self.breakpoint_conditions.insert(address, condition);
+    }
+
+    /// Removes a breakpoint, returning false if none was set there
+    pub fn remove_breakpoint(&mut self, address: u16) -> bool {
+        self.breakpoint_conditions.remove(&address);
+        self.breakpoints.remove(&address)
+    }
+
+    /// Condition of a conditional breakpoint
+    pub fn get_breakpoint_condition(&self, address: u16) -> Option<&Expression> {
+        self.breakpoint_conditions.get(&address)
+    }
+
+    /// Adds a watchpoint, returning the number it is listed and removed by
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) -> usize {
+        let number = self.next_watchpoint;
+        self.next_watchpoint += 1;
+        match &watchpoint {
+            Watchpoint::Memory { range, accesses } => self.emulator.watch_memory(range.clone(), accesses),
+            Watchpoint::Condition(condition) => {
+                self.condition_states.insert(number, condition.is_true(&self.emulator));
+            }
+        }
+        self.watchpoints.insert(number, watchpoint);
+        number
+    }
+
+    /// Removes a watchpoint, returning false if there is none with that number
+    pub fn remove_watchpoint(&mut self, number: usize) -> bool {
+        let Some(watchpoint) = self.watchpoints.remove(&number) else {
+            return false;
+        };
+        self.condition_states.remove(&number);
+        if let Watchpoint::Memory { .. } = watchpoint {
+            // Memory only keeps the union of the watched ranges, rebuild it
+            self.emulator.clear_memory_watches();
+            for watchpoint in self.watchpoints.values() {
+                if let Watchpoint::Memory { range, accesses } = watchpoint {
+                    self.emulator.watch_memory(range.clone(), accesses);
+                }
+            }
+        }
+        true
+    }
+
+    /// Watchpoints by number
+    pub fn get_watchpoints(&self) -> &BTreeMap<usize, Watchpoint> {
+        &self.watchpoints
+    }
+
+    /// Breakpoint addresses in ascending order
+    pub fn get_breakpoints(&self) -> &BTreeSet<u16> {
+        &self.breakpoints
+    }
+
+    fn pc(&self) -> u16 {
+        self.emulator.get_cpu().get_program_counter()
+    }
+
+    fn depth(&self) -> u16 {
+        self.emulator.get_cpu_state().stack_pointer
+    }
+
+    /// Executes a single instruction, ignoring breakpoints but not
+    /// watchpoints.
+    ///
+    /// Frames waiting for vblank are finished first, so a step always
+    /// executes an instruction unless the machine is blocked or halted.
+    pub fn step(&mut self) -> Stop {
+        self.record_history();
+        let stop = self.execute();
+        match self.check_watchpoints() {
+            Some(watch) if matches!(stop, Stop::Done) => watch,
+            _ => stop,
+        }
+    }
+
+    fn execute(&mut self) -> Stop {
+        loop {
+            match self.emulator.tick() {
+                Ok(StepOutcome::WaitingForVblank) => self.end_frame(),
+                Ok(StepOutcome::WaitingForKey) => {
+                    self.end_frame();
+                    return Stop::WaitingForKey;
+                }
+                Ok(StepOutcome::Halted) => return Stop::Halted,
+                Ok(StepOutcome::Exited) => {
+                    self.count_cycle();
+                    return Stop::Exited;
+                }
+                Ok(StepOutcome::Executed) => {
+                    self.count_cycle();
+                    return Stop::Done;
+                }
+                // The fault policy skipped the instruction, stop so it can be inspected
+                Ok(StepOutcome::Skipped(error)) => {
+                    self.count_cycle();
+                    return Stop::Fault(error);
+                }
+                Err(error) => return Stop::Fault(error),
+            }
+        }
+    }
+
+    /// Steps one instruction, running a called subroutine to completion
+    pub fn step_over(&mut self) -> Stop {
+        let pc = self.pc();
+        let operation = self.emulator.get_memory().fetch_word(pc).unwrap_or_default();
+        if !matches!(decode_for(operation, self.emulator.get_config().mode), Instruction::Call(_)) {
+            return self.step();
+        }
+        let depth = self.depth();
+        let return_address = pc.wrapping_add(2);
+        self.run(|debugger| debugger.pc() == return_address && debugger.depth() == depth)
+    }
+
+    /// Runs until the current subroutine returns, or `None` outside of one
+    pub fn finish(&mut self) -> Option<Stop> {
+        let depth = self.depth();
+        if depth == 0 {
+            return None;
+        }
+        Some(self.run(|debugger| debugger.depth() < depth))
+    }
+
+    /// Runs until execution reaches `address` or the current subroutine returns
+    pub fn run_until(&mut self, address: u16) -> Stop {
+        let depth = self.depth();
+        self.run(|debugger| debugger.pc() == address || debugger.depth() < depth)
+    }
+
+    /// Runs until a breakpoint or the program stops by itself
+    pub fn resume(&mut self) -> Stop {
+        self.run(|_| false)
+    }
+
+    /// Steps until `done` holds. Breakpoints are checked after each step,
+    /// so resuming from a breakpoint moves past it, and hitting the limit
+    /// never leaves the machine sitting on an unreported breakpoint.
+    fn run(&mut self, mut done: impl FnMut(&Self) -> bool) -> Stop {
+        for _ in 0..self.instruction_limit {
+            match self.step() {
+                Stop::Done if done(self) => return Stop::Done,
+                Stop::Done => {}
+                stop => return stop,
+            }
+            let pc = self.pc();
+            if self.breakpoints.contains(&pc) && self.breakpoint_condition_holds(pc) {
+                return Stop::Breakpoint(pc);
+            }
+        }
+        Stop::LimitReached
+    }
+
+    fn breakpoint_condition_holds(&self, address: u16) -> bool {
+        self.breakpoint_conditions
+            .get(&address)
+            .is_none_or(|condition| condition.is_true(&self.emulator))
+    }
+
+    /// Reports the first watchpoint hit by the last instruction. Every
+    /// condition is still evaluated so each one only stops on turning true.
+    fn check_watchpoints(&mut self) -> Option<Stop> {
+        if self.watchpoints.is_empty() {
+            return None;
+        }
+        let accesses = self.emulator.take_memory_accesses();
+        let mut stop = None;
+        for (&number, watchpoint) in &self.watchpoints {
+            match watchpoint {
+                Watchpoint::Memory { range, accesses: kinds } => {
+                    let hit = accesses
+                        .iter()
+                        .find(|access| range.contains(&access.address) && kinds.contains(&access.access));
+                    if let (None, Some(&access)) = (&stop, hit) {
+                        stop = Some(Stop::MemoryWatch { number, access });
+                    }
+                }
+                Watchpoint::Condition(condition) => {
+                    let holds = condition.is_true(&self.emulator);
+                    let held = self.condition_states.insert(number, holds).unwrap_or_default();
+                    if stop.is_none() && holds && !held {
+                        stop = Some(Stop::Condition { number });
+                    }
+                }
+            }
+        }
+        stop
+    }
+
+    /// Takes a snapshot when one is due. Running forward again after
+    /// stepping back replays edits made the first time through instead.
+    fn record_history(&mut self) {
+        if !self.modified && self.history.edit_at(self.cycles).is_some() {
+            self.restore(self.cycles);
+        } else if self.history.is_due(self.cycles, self.modified) {
+            self.history.push(Snapshot {
+                cycle: self.cycles,
+                frame_cycles: self.frame_cycles,
+                state: self.emulator.save_state(),
+                edited: self.modified,
+            });
+            self.modified = false;
+        }
+    }
+
+    /// Steps back to the state before the last instruction
+    pub fn reverse_step(&mut self) -> Stop {
+        self.record_history();
+        match self.cycles.checked_sub(1) {
+            Some(target) if self.history.nearest(target).is_some() => {
+                self.travel(target);
+                Stop::Done
+            }
+            _ => Stop::HistoryStart,
+        }
+    }
+
+    /// Runs backwards to the last breakpoint or watchpoint hit before the
+    /// current instruction, or to the oldest recorded state
+    pub fn reverse_continue(&mut self) -> Stop {
+        self.record_history();
+        let current = self.cycles;
+        let mut end = current;
+        // Replay one snapshot at a time, newest first, keeping the last hit
+        while let Some(start) = end.checked_sub(1).and_then(|cycle| self.history.nearest(cycle)).map(|s| s.cycle) {
+            if let Some((cycle, stop)) = self.last_hit(start, end, current) {
+                self.travel(cycle);
+                return stop;
+            }
+            end = start;
+        }
+        if let Some(start) = self.history.start() {
+            self.travel(start);
+        }
+        Stop::HistoryStart
+    }
+
+    /// Replays from the snapshot at `start` up to `end`, returning the last
+    /// state before `current` that a forward run would have stopped at
+    fn last_hit(&mut self, start: u64, end: u64, current: u64) -> Option<(u64, Stop)> {
+        self.restore(start);
+        let mut hit = None;
+        while self.cycles < end {
+            let before = self.cycles;
+            let stop = self.execute();
+            if self.cycles == before {
+                break;
+            }
+            let pc = self.pc();
+            if let Some(watch) = self.check_watchpoints().filter(|_| matches!(stop, Stop::Done)) {
+                hit = Some((self.cycles, watch));
+            } else if self.breakpoints.contains(&pc) && self.breakpoint_condition_holds(pc) {
+                hit = Some((self.cycles, Stop::Breakpoint(pc)));
+            }
+        }
+        hit.filter(|(cycle, _)| *cycle < current)
+    }
+
+    /// Moves to the state before instruction `cycle` by replaying from the
+    /// nearest snapshot
+    fn travel(&mut self, cycle: u64) {
+        let start = self.history.nearest(cycle).map_or(cycle, |snapshot| snapshot.cycle);
+        self.restore(start);
+        while self.cycles < cycle {
+            let before = self.cycles;
+            self.execute();
+            if self.cycles == before {
+                break;
+            }
+        }
+        self.emulator.take_memory_accesses();
+        self.reset_conditions();
+    }
+
+    /// Restores the snapshot taken at `cycle`
+    fn restore(&mut self, cycle: u64) {
+        let snapshot = self.history.nearest(cycle).expect("no snapshot to restore");
+        // Snapshots are states this emulator saved, so they always load
+        self.emulator.load_state(&snapshot.state).expect("snapshot failed to load");
+        self.cycles = snapshot.cycle;
+        self.frame_cycles = snapshot.frame_cycles;
+        self.modified = false;
+        self.reset_conditions();
+    }
+
+    /// Re-evaluates condition watchpoints so only later changes stop
+    fn reset_conditions(&mut self) {
+        for (&number, watchpoint) in &self.watchpoints {
+            if let Watchpoint::Condition(condition) = watchpoint {
+                self.condition_states.insert(number, condition.is_true(&self.emulator));
+            }
+        }
+    }
+
+    fn count_cycle(&mut self) {
+        self.cycles += 1;
+        self.frame_cycles += 1;
+        if self.frame_cycles >= self.emulator.get_config().instructions_per_frame {
+            self.end_frame();
+        }
+    }
+
+    fn end_frame(&mut self) {
+        self.emulator.end_frame();
+        self.frame_cycles = 0;
+    }
+
+    /// Program counter followed by the return address of every active
+    /// subroutine call, innermost first
+    pub fn backtrace(&self) -> Vec<u16> {
+        let state = self.emulator.get_cpu_state();
+        let depth = (state.stack_pointer as usize).min(state.stack.len());
+        let mut frames = vec![state.program_counter];
+        frames.extend(state.stack[..depth].iter().rev());
+        frames
+    }
+
+    /// Decodes `count` instructions starting at `address`. Four byte
+    /// XO-CHIP instructions are listed once, with the following word as
+    /// their operand.
+    pub fn disassemble(&self, address: u16, count: usize) -> Vec<(u16, Instruction)> {
+        let memory = self.emulator.get_memory();
+        let mode = self.emulator.get_config().mode;
+        let mut lines = Vec::with_capacity(count);
+        let mut address = address;
+        for _ in 0..count {
+            let Ok(operation) = memory.fetch_word(address) else {
+                break;
+            };
+            let instruction = decode_for(operation, mode);
+            lines.push((address, instruction));
+            address = address.wrapping_add(instruction.size());
+        }
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,271 @@
+//! Expressions over machine state for conditional breakpoints and
+//! watchpoints, such as `V3 == 0x10 && I > 0x300`.
+//!
+//! Operands are numbers, the registers `V0` - `VF`, `I`, `PC`, `SP`, `DT`
+//! and `ST`, and `[addr]` for a byte of memory. Operators follow C
+//! precedence and a non-zero value is true.
+
+use std::fmt;
+use std::str::FromStr;
+use crate::emulator::Emulator;
+
+/// Parsed expression, displayed as the text it was parsed from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Expression {
+    source: String,
+    root: Node,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Node {
+    Number(i64),
+    Register(u8),
+    I,
+    Pc,
+    Sp,
+    Dt,
+    St,
+    Memory(Box<Node>),
+    Unary(&'static str, Box<Node>),
+    Binary(&'static str, Box<Node>, Box<Node>),
+}
+
+/// Binary operators from lowest to highest precedence
+const BINARY_OPERATORS: [&[&str]; 10] = [
+    &["||"],
+    &["&&"],
+    &["|"],
+    &["^"],
+    &["&"],
+    &["==", "!="],
+    &["<=", ">=", "<", ">"],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+/// Deepest nesting of parentheses, operators and memory reads, so parsing,
+/// evaluating and dropping an expression can't overflow the stack
+const MAX_DEPTH: usize = 256;
+
+/// Symbols split off as tokens, longest first
+const SYMBOLS: [&str; 24] = [
+    "||", "&&", "==", "!=", "<=", ">=", "<<", ">>", "|", "^", "&", "<", ">", "+", "-", "*", "/", "%", "!", "~", "(", ")", "[",
+    "]",
+];
+
+impl Expression {
+    /// Evaluates the expression against the current machine state
+    pub fn evaluate(&self, emulator: &Emulator) -> i64 {
+        self.root.evaluate(emulator)
+    }
+
+    /// Whether the expression evaluates to a non-zero value
+    pub fn is_true(&self, emulator: &Emulator) -> bool {
+        self.evaluate(emulator) != 0
+    }
+}
+
+impl Node {
+    fn evaluate(&self, emulator: &Emulator) -> i64 {
+        let state = emulator.get_cpu_state();
+        match self {
+            Node::Number(value) => *value,
+            Node::Register(x) => state.v_registers[*x as usize].into(),
+            Node::I => state.i_register.into(),
+            Node::Pc => state.program_counter.into(),
+            Node::Sp => state.stack_pointer.into(),
+            Node::Dt => emulator.get_memory().get_delay_timer().into(),
+            Node::St => emulator.get_memory().get_sound_timer().into(),
+            Node::Memory(address) => usize::try_from(address.evaluate(emulator))
+                .ok()
+                .and_then(|address| emulator.get_ram().get(address))
+                .map_or(0, |&byte| byte.into()),
+            Node::Unary(op, operand) => {
+                let value = operand.evaluate(emulator);
+                match *op {
+                    "-" => value.wrapping_neg(),
+                    "~" => !value,
+                    _ => (value == 0).into(),
+                }
+            }
+            Node::Binary(op, lhs, rhs) => {
+                let lhs = lhs.evaluate(emulator);
+                // Logical operators short circuit
+                match *op {
+                    "&&" => return (lhs != 0 && rhs.evaluate(emulator) != 0).into(),
+                    "||" => return (lhs != 0 || rhs.evaluate(emulator) != 0).into(),
+                    _ => {}
+                }
+                let rhs = rhs.evaluate(emulator);
+                match *op {
+                    "|" => lhs | rhs,
+                    "^" => lhs ^ rhs,
+                    "&" => lhs & rhs,
+                    "==" => (lhs == rhs).into(),
+                    "!=" => (lhs != rhs).into(),
+                    "<=" => (lhs <= rhs).into(),
+                    ">=" => (lhs >= rhs).into(),
+                    "<" => (lhs < rhs).into(),
+                    ">" => (lhs > rhs).into(),
+                    "<<" => lhs.checked_shl(rhs as u32).unwrap_or_default(),
+                    ">>" => lhs.checked_shr(rhs as u32).unwrap_or_default(),
+                    "+" => lhs.wrapping_add(rhs),
+                    "-" => lhs.wrapping_sub(rhs),
+                    "*" => lhs.wrapping_mul(rhs),
+                    // Division by zero evaluates to zero rather than stopping the machine
+                    "/" => lhs.checked_div(rhs).unwrap_or_default(),
+                    _ => lhs.checked_rem(rhs).unwrap_or_default(),
+                }
+            }
+        }
+    }
+}
+
+impl FromStr for Expression {
+    type Err = String;
+
+    fn from_str(source: &str) -> Result<Self, Self::Err> {
+        let tokens = tokenize(source)?;
+        let mut parser = Parser { tokens, pos: 0, depth: 0 };
+        let (root, _) = parser.binary(0)?;
+        if let Some(token) = parser.tokens.get(parser.pos) {
+            return Err(format!("unexpected '{token}'"));
+        }
+        Ok(Self { source: source.trim().to_string(), root })
+    }
+}
+
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+fn tokenize(source: &str) -> Result<Vec<String>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = source.trim_start();
+    while !rest.is_empty() {
+        let len = if let Some(symbol) = SYMBOLS.iter().find(|symbol| rest.starts_with(**symbol)) {
+            symbol.len()
+        } else {
+            let len = rest.find(|c: char| !c.is_ascii_alphanumeric() && c != '_').unwrap_or(rest.len());
+            if len == 0 {
+                return Err(format!("unexpected '{}'", rest.chars().next().unwrap()));
+            }
+            len
+        };
+        tokens.push(rest[..len].to_string());
+        rest = rest[len..].trim_start();
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<String>,
+    pos: usize,
+    /// Parentheses, brackets and unary operators currently open
+    depth: usize,
+}
+
+/// A parsed node and the height of its tree
+type Parsed = (Node, usize);
+
+fn checked_height(height: usize) -> Result<usize, String> {
+    if height > MAX_DEPTH {
+        return Err("expression nested too deeply".to_string());
+    }
+    Ok(height)
+}
+
+impl Parser {
+    fn next(&mut self) -> Result<&str, String> {
+        let token = self.tokens.get(self.pos).ok_or("unexpected end of expression")?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn expect(&mut self, text: &str) -> Result<(), String> {
+        match self.next() {
+            Ok(token) if token == text => Ok(()),
+            _ => Err(format!("expected '{text}'")),
+        }
+    }
+
+    /// Runs `parse` one level deeper, failing past `MAX_DEPTH`
+    fn nested<T>(&mut self, parse: impl FnOnce(&mut Self) -> Result<T, String>) -> Result<T, String> {
+        checked_height(self.depth + 1)?;
+        self.depth += 1;
+        let result = parse(self);
+        self.depth -= 1;
+        result
+    }
+
+    /// Parses operators of `level` and above, by precedence climbing so
+    /// that parentheses cost one level of recursion rather than one per
+    /// precedence level
+    fn binary(&mut self, level: usize) -> Result<Parsed, String> {
+        let (mut lhs, mut height) = self.unary()?;
+        while let Some((op_level, op)) = self.peek_operator().filter(|&(op_level, _)| op_level >= level) {
+            self.pos += 1;
+            let (rhs, rhs_height) = self.binary(op_level + 1)?;
+            // Chains like `1 + 1 + 1` grow the tree without nesting
+            height = checked_height(height.max(rhs_height) + 1)?;
+            lhs = Node::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+        Ok((lhs, height))
+    }
+
+    /// Binary operator at the current position and its precedence level
+    fn peek_operator(&self) -> Option<(usize, &'static str)> {
+        let token = self.tokens.get(self.pos)?;
+        BINARY_OPERATORS.iter().enumerate().find_map(|(level, operators)| {
+            operators.iter().find(|&&op| op == token).map(|&op| (level, op))
+        })
+    }
+
+    fn unary(&mut self) -> Result<Parsed, String> {
+        let token = self.next()?.to_string();
+        match token.as_str() {
+            "-" | "~" | "!" => {
+                let op = match token.as_str() {
+                    "-" => "-",
+                    "~" => "~",
+                    _ => "!",
+                };
+                let (node, height) = self.nested(Self::unary)?;
+                Ok((Node::Unary(op, Box::new(node)), checked_height(height + 1)?))
+            }
+            "(" => self.nested(|parser| {
+                let parsed = parser.binary(0)?;
+                parser.expect(")")?;
+                Ok(parsed)
+            }),
+            "[" => self.nested(|parser| {
+                let (node, height) = parser.binary(0)?;
+                parser.expect("]")?;
+                Ok((Node::Memory(Box::new(node)), checked_height(height + 1)?))
+            }),
+            _ => parse_operand(&token).map(|node| (node, 0)).ok_or_else(|| format!("unknown operand '{token}'")),
+        }
+    }
+}
+
+fn parse_operand(token: &str) -> Option<Node> {
+    let upper = token.to_ascii_uppercase();
+    match upper.as_str() {
+        "I" => return Some(Node::I),
+        "PC" => return Some(Node::Pc),
+        "SP" => return Some(Node::Sp),
+        "DT" => return Some(Node::Dt),
+        "ST" => return Some(Node::St),
+        _ => {}
+    }
+    if let Some(hex) = upper.strip_prefix("0X") {
+        return i64::from_str_radix(hex, 16).ok().map(Node::Number);
+    }
+    if let Some(digit) = upper.strip_prefix('V').filter(|digit| digit.len() == 1) {
+        return u8::from_str_radix(digit, 16).ok().map(Node::Register);
+    }
+    token.parse().ok().map(Node::Number)
+}
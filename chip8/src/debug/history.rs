@@ -0,0 +1,83 @@
+//! Snapshots of earlier machine states for reverse execution.
+//!
+//! A save state is taken every few thousand instructions, and whenever the
+//! machine was changed from outside, such as a key press or a register
+//! edit. Any earlier instruction is reached by restoring the nearest
+//! snapshot before it and executing forward, which replays exactly because
+//! the CPU, memory, timers, input and random number generator are all part
+//! of the save state.
+
+use std::collections::VecDeque;
+
+/// Default instructions between snapshots
+pub const DEFAULT_SNAPSHOT_INTERVAL: u64 = 1000;
+
+/// Snapshots kept before the oldest is dropped
+pub const MAX_SNAPSHOTS: usize = 1000;
+
+/// Machine state before an instruction
+pub struct Snapshot {
+    /// Instructions executed before the snapshot
+    pub cycle: u64,
+    /// Instructions executed in the frame the snapshot lies in
+    pub frame_cycles: u32,
+    pub state: Vec<u8>,
+    /// Whether the machine was changed from outside just before, so
+    /// running forward past this cycle has to restore the snapshot
+    pub edited: bool,
+}
+
+/// Snapshots in ascending cycle order
+pub struct History {
+    snapshots: VecDeque<Snapshot>,
+    interval: u64,
+}
+
+impl History {
+    pub fn new() -> Self {
+        Self { snapshots: VecDeque::new(), interval: DEFAULT_SNAPSHOT_INTERVAL }
+    }
+
+    pub fn set_interval(&mut self, interval: u64) {
+        self.interval = interval.max(1);
+    }
+
+    /// Whether a snapshot should be taken before executing instruction
+    /// `cycle`. A modified machine always needs one, as the recorded future
+    /// no longer follows from it.
+    pub fn is_due(&self, cycle: u64, modified: bool) -> bool {
+        if modified {
+            return true;
+        }
+        match self.snapshots.back() {
+            Some(last) => cycle > last.cycle && cycle - last.cycle >= self.interval,
+            None => true,
+        }
+    }
+
+    /// Records a snapshot, replacing any taken at or after its cycle
+    pub fn push(&mut self, snapshot: Snapshot) {
+        let index = self.snapshots.partition_point(|old| old.cycle < snapshot.cycle);
+        self.snapshots.truncate(index);
+        self.snapshots.push_back(snapshot);
+        if self.snapshots.len() > MAX_SNAPSHOTS {
+            self.snapshots.pop_front();
+        }
+    }
+
+    /// Newest snapshot taken at or before `cycle`
+    pub fn nearest(&self, cycle: u64) -> Option<&Snapshot> {
+        let index = self.snapshots.partition_point(|snapshot| snapshot.cycle <= cycle);
+        index.checked_sub(1).map(|index| &self.snapshots[index])
+    }
+
+    /// Snapshot of an edit made before instruction `cycle`
+    pub fn edit_at(&self, cycle: u64) -> Option<&Snapshot> {
+        self.nearest(cycle).filter(|snapshot| snapshot.cycle == cycle && snapshot.edited)
+    }
+
+    /// Cycle of the oldest state that can be returned to
+    pub fn start(&self) -> Option<u64> {
+        self.snapshots.front().map(|snapshot| snapshot.cycle)
+    }
+}
@@ -0,0 +1,399 @@
+use super::*;
+use crate::asm::assemble;
+use crate::config::Config;
+use crate::memory::{Access, MemoryAccess};
+
+/// Main program calls `sub`, which calls `inner`, then loops forever
+const PROGRAM: &str = "
+main:   LD V0, 1        ; 200
+        CALL sub        ; 202
+        LD V1, 2        ; 204
+        LD V2, 3        ; 206
+end:    JP end          ; 208
+sub:    ADD V0, 1       ; 20A
+        CALL inner      ; 20C
+        RET             ; 20E
+inner:  ADD V0, 10      ; 210
+        RET             ; 212
+";
+
+fn load(source: &str) -> Debugger {
+    let mut emulator = Emulator::with_config(Config::default());
+    emulator.load_rom(&assemble(source).unwrap()).unwrap();
+    Debugger::new(emulator)
+}
+
+fn pc(debugger: &Debugger) -> u16 {
+    debugger.get_emulator().get_cpu_state().program_counter
+}
+
+#[test]
+fn step_executes_one_instruction() {
+    let mut debugger = load(PROGRAM);
+    debugger.add_breakpoint(0x202);
+    assert!(matches!(debugger.step(), Stop::Done));
+    assert!(matches!(debugger.step(), Stop::Done));
+    assert_eq!(pc(&debugger), 0x20A);
+    assert_eq!(debugger.get_cycles(), 2);
+}
+
+#[test]
+fn breakpoints_stop_and_resume_past_them() {
+    let mut debugger = load(PROGRAM);
+    assert!(debugger.add_breakpoint(0x210));
+    assert!(!debugger.add_breakpoint(0x210));
+    assert!(matches!(debugger.resume(), Stop::Breakpoint(0x210)));
+    assert_eq!(pc(&debugger), 0x210);
+    debugger.add_breakpoint(0x206);
+    assert!(matches!(debugger.resume(), Stop::Breakpoint(0x206)));
+    assert!(debugger.remove_breakpoint(0x210));
+    assert_eq!(debugger.get_breakpoints().iter().copied().collect::<Vec<_>>(), [0x206]);
+}
+
+#[test]
+fn step_over_runs_the_whole_call() {
+    let mut debugger = load(PROGRAM);
+    debugger.step();
+    assert!(matches!(debugger.step_over(), Stop::Done));
+    assert_eq!(pc(&debugger), 0x204);
+    assert_eq!(debugger.get_emulator().get_cpu_state().v_registers[0], 12);
+    // Other instructions are a plain step
+    assert!(matches!(debugger.step_over(), Stop::Done));
+    assert_eq!(pc(&debugger), 0x206);
+}
+
+#[test]
+fn step_over_stops_at_breakpoints_inside_the_call() {
+    let mut debugger = load(PROGRAM);
+    debugger.add_breakpoint(0x210);
+    debugger.step();
+    assert!(matches!(debugger.step_over(), Stop::Breakpoint(0x210)));
+}
+
+#[test]
+fn finish_returns_to_the_caller() {
+    let mut debugger = load(PROGRAM);
+    assert!(debugger.finish().is_none());
+    debugger.add_breakpoint(0x210);
+    debugger.resume();
+    assert_eq!(debugger.backtrace(), [0x210, 0x20E, 0x204]);
+    assert!(matches!(debugger.finish(), Some(Stop::Done)));
+    assert_eq!(pc(&debugger), 0x20E);
+    assert!(matches!(debugger.finish(), Some(Stop::Done)));
+    assert_eq!(pc(&debugger), 0x204);
+    assert_eq!(debugger.backtrace(), [0x204]);
+}
+
+#[test]
+fn run_until_an_address() {
+    let mut debugger = load(PROGRAM);
+    assert!(matches!(debugger.run_until(0x206), Stop::Done));
+    assert_eq!(pc(&debugger), 0x206);
+    // Leaving the subroutine stops short of the address
+    let mut debugger = load(PROGRAM);
+    debugger.add_breakpoint(0x20A);
+    debugger.resume();
+    assert!(matches!(debugger.run_until(0x300), Stop::Done));
+    assert_eq!(pc(&debugger), 0x204);
+}
+
+#[test]
+fn endless_loops_hit_the_limit() {
+    let mut debugger = load(PROGRAM);
+    debugger.set_instruction_limit(1000);
+    assert!(matches!(debugger.resume(), Stop::LimitReached));
+    assert_eq!(pc(&debugger), 0x208);
+    assert_eq!(debugger.get_cycles(), 1000);
+}
+
+#[test]
+fn timers_run_per_frame() {
+    let mut debugger = load("LD V0, 30\nLD DT, V0\nloop: JP loop");
+    let per_frame = debugger.get_emulator().get_config().instructions_per_frame as u64;
+    debugger.set_instruction_limit(per_frame * 10);
+    debugger.resume();
+    assert_eq!(debugger.get_emulator().get_frame_count(), 10);
+    assert_eq!(debugger.get_emulator().get_memory().get_delay_timer(), 20);
+}
+
+#[test]
+fn faults_and_exits_stop() {
+    let mut debugger = load("RET");
+    assert!(matches!(debugger.resume(), Stop::Fault(Chip8Error::StackUnderflow)));
+    assert!(matches!(debugger.step(), Stop::Halted));
+    let mut emulator = Emulator::with_config(Config::for_mode(crate::config::Mode::SuperChip));
+    emulator.load_rom(&assemble("LD V0, 1\nEXIT").unwrap()).unwrap();
+    assert!(matches!(Debugger::new(emulator).resume(), Stop::Exited));
+}
+
+#[test]
+fn waiting_for_a_key_stops() {
+    let mut debugger = load("LD V0, K\nLD V1, 1");
+    assert!(matches!(debugger.resume(), Stop::WaitingForKey));
+    debugger.get_emulator_mut().key_down(5);
+    debugger.get_emulator_mut().key_up(5);
+    assert!(matches!(debugger.step(), Stop::Done));
+    assert_eq!(debugger.get_emulator().get_cpu_state().v_registers[0], 5);
+}
+
+#[test]
+fn disassembles_from_an_address() {
+    let debugger = load(PROGRAM);
+    let lines = debugger.disassemble(0x200, 3);
+    assert_eq!(
+        lines,
+        [
+            (0x200, Instruction::SetImm { x: 0, nn: 1 }),
+            (0x202, Instruction::Call(0x20A)),
+            (0x204, Instruction::SetImm { x: 1, nn: 2 }),
+        ]
+    );
+}
+
+fn expression(source: &str) -> Expression {
+    source.parse().unwrap_or_else(|err| panic!("{source}: {err}"))
+}
+
+/// Writes with FX55, reads back with FX65, draws from the same bytes, then
+/// copies with BCD
+const MEMORY_PROGRAM: &str = "
+        LD V0, 0x12     ; 200
+        LD V1, 0x34     ; 202
+        LD I, 0x300     ; 204
+        LD [I], V1      ; 206
+        LD I, 0x300     ; 208
+        LD V1, [I]      ; 20A
+        LD I, 0x300     ; 20C
+        DRW V0, V0, 2   ; 20E
+        LD I, 0x310     ; 210
+        LD B, V0        ; 212
+end:    JP end          ; 214
+";
+
+#[test]
+fn memory_watchpoints_report_each_kind_of_access() {
+    let mut debugger = load(MEMORY_PROGRAM);
+    let write = debugger.add_watchpoint(Watchpoint::Memory { range: 0x301..=0x301, accesses: vec![Access::Write] });
+    let read = debugger.add_watchpoint(Watchpoint::Memory { range: 0x300..=0x301, accesses: vec![Access::Read] });
+    let bcd = debugger.add_watchpoint(Watchpoint::Memory { range: 0x312..=0x312, accesses: vec![Access::Write] });
+    let fetch = debugger.add_watchpoint(Watchpoint::Memory { range: 0x214..=0x214, accesses: vec![Access::Fetch] });
+
+    let access = MemoryAccess { address: 0x301, access: Access::Write, value: 0x34 };
+    assert!(matches!(debugger.resume(), Stop::MemoryWatch { number, access: hit } if number == write && hit == access));
+    assert_eq!(pc(&debugger), 0x208);
+    let access = MemoryAccess { address: 0x300, access: Access::Read, value: 0x12 };
+    assert!(matches!(debugger.resume(), Stop::MemoryWatch { number, access: hit } if number == read && hit == access));
+    // Sprite data is read by DXYN
+    assert_eq!(pc(&debugger), 0x20C);
+    assert!(matches!(debugger.resume(), Stop::MemoryWatch { number, .. } if number == read));
+    assert_eq!(pc(&debugger), 0x210);
+    let access = MemoryAccess { address: 0x312, access: Access::Write, value: 8 };
+    assert!(matches!(debugger.resume(), Stop::MemoryWatch { number, access: hit } if number == bcd && hit == access));
+    assert!(matches!(debugger.resume(), Stop::MemoryWatch { number, .. } if number == fetch));
+    assert_eq!(pc(&debugger), 0x214);
+}
+
+#[test]
+fn removed_watchpoints_no_longer_stop() {
+    let mut debugger = load(MEMORY_PROGRAM);
+    debugger.set_instruction_limit(100);
+    let number = debugger.add_watchpoint(Watchpoint::Memory { range: 0x300..=0x301, accesses: vec![Access::Write] });
+    let other = debugger.add_watchpoint(Watchpoint::Memory { range: 0x310..=0x312, accesses: vec![Access::Write] });
+    assert!(debugger.remove_watchpoint(number));
+    assert!(!debugger.remove_watchpoint(number));
+    assert!(matches!(debugger.resume(), Stop::MemoryWatch { number, .. } if number == other));
+    assert_eq!(pc(&debugger), 0x214);
+    assert_eq!(debugger.get_watchpoints().keys().copied().collect::<Vec<_>>(), [other]);
+}
+
+#[test]
+fn memory_is_not_recorded_without_watches() {
+    let mut debugger = load(MEMORY_PROGRAM);
+    debugger.set_instruction_limit(20);
+    debugger.resume();
+    assert!(debugger.get_emulator().take_memory_accesses().is_empty());
+}
+
+#[test]
+fn watches_survive_loading_a_save_state() {
+    let mut debugger = load(MEMORY_PROGRAM);
+    let state = debugger.get_emulator().save_state();
+    debugger.add_watchpoint(Watchpoint::Memory { range: 0x300..=0x300, accesses: vec![Access::Write] });
+    debugger.get_emulator_mut().load_state(&state).unwrap();
+    assert!(matches!(debugger.resume(), Stop::MemoryWatch { .. }));
+}
+
+#[test]
+fn condition_watchpoints_stop_when_they_turn_true() {
+    let mut debugger = load("loop: ADD V0, 1\nJP loop");
+    debugger.set_instruction_limit(10_000);
+    let number = debugger.add_watchpoint(Watchpoint::Condition(expression("v0 == 5")));
+    assert!(matches!(debugger.resume(), Stop::Condition { number: hit } if hit == number));
+    assert_eq!(debugger.get_emulator().get_cpu_state().v_registers[0], 5);
+    // Still true after the next instruction, so it doesn't stop again until
+    // V0 wraps back round to 5
+    assert!(matches!(debugger.resume(), Stop::Condition { .. }));
+    assert_eq!(debugger.get_cycles(), 9 + 256 * 2);
+}
+
+#[test]
+fn conditional_breakpoints() {
+    let source = "
+loop:   ADD V0, 1
+        JP loop
+";
+    let mut debugger = load(source);
+    debugger.add_conditional_breakpoint(0x200, expression("V0 == 3 && I == 0"));
+    assert!(matches!(debugger.resume(), Stop::Breakpoint(0x200)));
+    assert_eq!(debugger.get_emulator().get_cpu_state().v_registers[0], 3);
+    assert_eq!(debugger.get_breakpoint_condition(0x200).unwrap().to_string(), "V0 == 3 && I == 0");
+    // A plain breakpoint replaces the condition
+    debugger.add_breakpoint(0x200);
+    assert!(debugger.get_breakpoint_condition(0x200).is_none());
+    assert!(matches!(debugger.resume(), Stop::Breakpoint(0x200)));
+    assert_eq!(debugger.get_emulator().get_cpu_state().v_registers[0], 4);
+}
+
+#[test]
+fn expressions() {
+    let mut debugger = load("LD V3, 0x10\nLD I, 0x301\nLD [I], V3\nend: JP end");
+    debugger.set_instruction_limit(3);
+    debugger.resume();
+    let emulator = debugger.get_emulator();
+    let cases = [
+        ("V3 == 0x10 && I > 0x300", 1),
+        ("1 + 2 * 3", 7),
+        ("(1 + 2) * 3", 9),
+        ("1 | 2 == 2", 1),
+        ("-v3 + ~0", -17),
+        ("!SP && PC == 0x206", 1),
+        ("[I - 1] + [0x300]", 0x10),
+        ("[0x10000]", 0),
+        ("DT + ST", 0),
+        ("8 / 0 + 7 % 0", 0),
+        ("1 << 4 >> 2", 4),
+        ("vf != 0 || 5 >= 5", 1),
+    ];
+    for (source, value) in cases {
+        assert_eq!(expression(source).evaluate(emulator), value, "{source}");
+    }
+    assert_eq!("V3 ==".parse::<Expression>().unwrap_err(), "unexpected end of expression");
+    assert_eq!("V3 = 1".parse::<Expression>().unwrap_err(), "unexpected '='");
+    assert_eq!("VG".parse::<Expression>().unwrap_err(), "unknown operand 'VG'");
+    assert_eq!("(1".parse::<Expression>().unwrap_err(), "expected ')'");
+    assert_eq!("1 2".parse::<Expression>().unwrap_err(), "unexpected '2'");
+}
+
+#[test]
+fn expression_nesting_is_limited() {
+    let nested = |depth: usize| format!("{}1{}", "(".repeat(depth), ")".repeat(depth));
+    assert!(nested(256).parse::<Expression>().is_ok());
+    assert_eq!(nested(257).parse::<Expression>().unwrap_err(), "expression nested too deeply");
+    assert_eq!(nested(200_000).parse::<Expression>().unwrap_err(), "expression nested too deeply");
+    assert_eq!("-".repeat(300).parse::<Expression>().unwrap_err(), "expression nested too deeply");
+
+    // Long chains of operators deepen the tree without any nesting
+    let chain = |terms: usize| vec!["1"; terms].join(" + ");
+    assert_eq!(expression(&chain(257)).evaluate(&Emulator::new()), 257);
+    assert_eq!(chain(200_000).parse::<Expression>().unwrap_err(), "expression nested too deeply");
+}
+
+/// Counts in V0 forever, mixing in random numbers, timers and a key check
+const REPLAY_PROGRAM: &str = "
+loop:   ADD V0, 1       ; 200
+        RND V1, 0xFF    ; 202
+        LD DT, V1       ; 204
+        LD V2, DT       ; 206
+        SKNP V3         ; 208
+        ADD V4, 1       ; 20A
+        LD I, 0x300     ; 20C
+        LD [I], V1      ; 20E
+        JP loop         ; 210
+";
+
+#[test]
+fn reverse_step_replays_exactly() {
+    let mut debugger = load(REPLAY_PROGRAM);
+    debugger.set_snapshot_interval(7);
+    let mut states = Vec::new();
+    for cycle in 0..100 {
+        if cycle == 40 {
+            debugger.get_emulator_mut().key_down(0);
+        }
+        states.push(debugger.get_emulator().save_state());
+        debugger.step();
+    }
+    assert_ne!(debugger.get_emulator().get_cpu_state().v_registers[4], 0);
+    for cycle in (60..100).rev() {
+        assert!(matches!(debugger.reverse_step(), Stop::Done));
+        assert_eq!(debugger.get_cycles(), cycle);
+        assert_eq!(debugger.get_emulator().save_state(), states[cycle as usize], "cycle {cycle}");
+    }
+    // Stepping forward again retraces the same path
+    for _ in 0..10 {
+        debugger.step();
+    }
+    assert_eq!(debugger.get_emulator().save_state(), states[70]);
+    // Going back before the key press replays it at the same instruction
+    for _ in 0..40 {
+        debugger.reverse_step();
+    }
+    assert_eq!(debugger.get_emulator().save_state(), states[30]);
+    for _ in 0..20 {
+        debugger.step();
+    }
+    assert_eq!(debugger.get_emulator().save_state(), states[50]);
+}
+
+#[test]
+fn reverse_step_stops_at_the_start_of_history() {
+    let mut debugger = load(PROGRAM);
+    assert!(matches!(debugger.reverse_step(), Stop::HistoryStart));
+    debugger.step();
+    assert!(matches!(debugger.reverse_step(), Stop::Done));
+    assert_eq!(pc(&debugger), 0x200);
+    assert!(matches!(debugger.reverse_step(), Stop::HistoryStart));
+    assert_eq!(debugger.get_history_start(), Some(0));
+}
+
+#[test]
+fn reverse_continue_returns_to_the_previous_breakpoint() {
+    let mut debugger = load(REPLAY_PROGRAM);
+    debugger.set_snapshot_interval(5);
+    debugger.add_breakpoint(0x20C);
+    for _ in 0..3 {
+        debugger.resume();
+    }
+    let v0 = |debugger: &Debugger| debugger.get_emulator().get_cpu_state().v_registers[0];
+    assert_eq!(v0(&debugger), 3);
+    assert!(matches!(debugger.reverse_continue(), Stop::Breakpoint(0x20C)));
+    assert_eq!(v0(&debugger), 2);
+    assert_eq!(pc(&debugger), 0x20C);
+    assert!(matches!(debugger.reverse_continue(), Stop::Breakpoint(0x20C)));
+    assert_eq!(v0(&debugger), 1);
+    assert!(matches!(debugger.reverse_continue(), Stop::HistoryStart));
+    assert_eq!(debugger.get_cycles(), 0);
+    assert!(matches!(debugger.resume(), Stop::Breakpoint(0x20C)));
+    assert_eq!(v0(&debugger), 1);
+}
+
+#[test]
+fn reverse_continue_returns_to_the_previous_watchpoint_hit() {
+    let mut debugger = load(REPLAY_PROGRAM);
+    debugger.set_snapshot_interval(4);
+    debugger.add_watchpoint(Watchpoint::Memory { range: 0x300..=0x300, accesses: vec![Access::Write] });
+    let number = debugger.add_watchpoint(Watchpoint::Condition(expression("V0 == 2")));
+    debugger.set_instruction_limit(30);
+    debugger.resume();
+    debugger.resume();
+    debugger.resume();
+    let cycles = debugger.get_cycles();
+    assert!(matches!(debugger.reverse_continue(), Stop::Condition { number: n } if n == number));
+    assert!(debugger.get_cycles() < cycles);
+    assert_eq!(pc(&debugger), 0x202);
+    match debugger.reverse_continue() {
+        Stop::MemoryWatch { access, .. } => assert_eq!(access.address, 0x300),
+        stop => panic!("unexpected {stop:?}"),
+    }
+    assert_eq!(pc(&debugger), 0x210);
+}
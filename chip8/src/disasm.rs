@@ -0,0 +1,358 @@
+//! Disassembler producing readable listings of ROMs.
+//!
+//! Code is found by following control flow from the program start, so
+//! sprites and other data mixed into a program are listed as bytes rather
+//! than as nonsense instructions. Jump, call and I register targets get
+//! generated labels.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use crate::config::Mode;
+use crate::cpu::START_ADDRESS;
+use crate::error::Chip8Error;
+use crate::instruction::{decode_for, Instruction};
+
+/// Most bytes listed on a single data line
+const DATA_BYTES_PER_LINE: usize = 8;
+/// Column the annotations start in
+const ANNOTATION_COLUMN: usize = 28;
+
+/// Assembly dialect of a listing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Syntax {
+    /// Classic mnemonics, e.g. `LD V0, 0x12`
+    #[default]
+    Classic,
+    /// Octo, e.g. `v0 := 0x12`
+    Octo,
+}
+
+/// Disassembler settings
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DisasmOptions {
+    /// Instruction set the ROM was written for
+    pub mode: Mode,
+    pub syntax: Syntax,
+    /// Append the address and raw bytes of every line as a comment
+    pub annotate: bool,
+}
+
+impl Default for DisasmOptions {
+    fn default() -> Self {
+        Self {
+            mode: Mode::default(),
+            syntax: Syntax::default(),
+            annotate: true,
+        }
+    }
+}
+
+/// One line of a listing, either an instruction or a run of data bytes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Line {
+    pub address: u16,
+    pub bytes: Vec<u8>,
+    /// Label defined at this address
+    pub label: Option<String>,
+    /// Decoded instruction, `None` for data
+    pub instruction: Option<Instruction>,
+    /// Source text without label or annotation
+    pub text: String,
+}
+
+/// A disassembled ROM
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Listing {
+    pub lines: Vec<Line>,
+    syntax: Syntax,
+    annotate: bool,
+}
+
+/// Why an address is referenced, in order of label precedence
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Reference {
+    Data,
+    Jump,
+    Call,
+}
+
+/// Largest ROM that fits in the 16-bit address space above the start
+/// address
+pub const MAX_ROM_SIZE: usize = 0x10000 - START_ADDRESS as usize;
+
+/// Disassembles a ROM loaded at the program start address
+pub fn disassemble(rom: &[u8], options: &DisasmOptions) -> Result<Listing, Chip8Error> {
+    if rom.len() > MAX_ROM_SIZE {
+        return Err(Chip8Error::RomTooLarge { size: rom.len(), max: MAX_ROM_SIZE });
+    }
+    let analysis = Analysis::run(rom, options.mode);
+    let labels = analysis.labels();
+    let operand = |address: u16| match labels.get(&address) {
+        Some(name) => name.clone(),
+        None if options.syntax == Syntax::Octo => format!("0x{address:03x}"),
+        None => format!("0x{address:03X}"),
+    };
+
+    let mut lines = Vec::new();
+    let mut offset = 0;
+    while offset < rom.len() {
+        let address = START_ADDRESS + offset as u16;
+        let label = labels.get(&address).cloned();
+        let line = if let Some(&instruction) = analysis.code.get(&address) {
+            let size = instruction.size() as usize;
+            let long_target = (instruction == Instruction::SetILong)
+                .then(|| u16::from_be_bytes([rom[offset + 2], rom[offset + 3]]));
+            Line {
+                address,
+                bytes: rom[offset..offset + size].to_vec(),
+                label,
+                instruction: Some(instruction),
+                text: format_instruction(instruction, options.syntax, long_target, &operand),
+            }
+        } else {
+            // Data runs up to the next line that needs a label or holds code
+            let mut end = offset + 1;
+            while end < rom.len()
+                && end - offset < DATA_BYTES_PER_LINE
+                && !analysis.code.contains_key(&(START_ADDRESS + end as u16))
+                && !labels.contains_key(&(START_ADDRESS + end as u16))
+            {
+                end += 1;
+            }
+            let bytes = rom[offset..end].to_vec();
+            let text = format_data(&bytes, options.syntax);
+            Line { address, bytes, label, instruction: None, text }
+        };
+        offset += line.bytes.len();
+        lines.push(line);
+    }
+    Ok(Listing { lines, syntax: options.syntax, annotate: options.annotate })
+}
+
+/// Control flow analysis of a ROM
+struct Analysis {
+    /// Instructions reached from the program start, by address
+    code: BTreeMap<u16, Instruction>,
+    references: BTreeMap<u16, Reference>,
+    end: u32,
+}
+
+impl Analysis {
+    fn run(rom: &[u8], mode: Mode) -> Self {
+        let mut analysis = Analysis {
+            code: BTreeMap::new(),
+            references: BTreeMap::new(),
+            end: START_ADDRESS as u32 + rom.len() as u32,
+        };
+        let word = |address: u16| {
+            let offset = address.checked_sub(START_ADDRESS)? as usize;
+            Some(u16::from_be_bytes([*rom.get(offset)?, *rom.get(offset + 1)?]))
+        };
+        // Bytes covered by reached instructions, to reject overlapping decodes
+        let mut covered = vec![false; rom.len()];
+        let mut pending = vec![START_ADDRESS];
+        while let Some(address) = pending.pop() {
+            let Some(operation) = word(address) else { continue };
+            let instruction = decode_for(operation, mode);
+            let offset = (address - START_ADDRESS) as usize;
+            let size = instruction.size() as usize;
+            // Zero words and machine code calls are almost always data
+            if matches!(instruction, Instruction::Invalid(_) | Instruction::Nop | Instruction::Sys(_))
+                || offset + size > rom.len()
+                || covered[offset..offset + size].iter().any(|&byte| byte)
+            {
+                continue;
+            }
+            covered[offset..offset + size].fill(true);
+            analysis.code.insert(address, instruction);
+
+            // Code running off the end of the address space isn't followed
+            let next = address.checked_add(size as u16);
+            match instruction {
+                Instruction::Jump(target) | Instruction::JumpOffset(target) => {
+                    analysis.reference(target, Reference::Jump);
+                    pending.push(target);
+                }
+                Instruction::Call(target) => {
+                    analysis.reference(target, Reference::Call);
+                    pending.extend(next);
+                    pending.push(target);
+                }
+                Instruction::Return | Instruction::Exit => {}
+                Instruction::SetI(target) => {
+                    analysis.reference(target, Reference::Data);
+                    pending.extend(next);
+                }
+                Instruction::SetILong => {
+                    let target = address.checked_add(2).and_then(word);
+                    analysis.reference(target.unwrap_or_default(), Reference::Data);
+                    pending.extend(next);
+                }
+                _ if instruction.is_skip() => {
+                    let Some(next) = next else { continue };
+                    let skipped = match word(next).map(|operation| decode_for(operation, mode)) {
+                        Some(Instruction::SetILong) => next.checked_add(4),
+                        _ => next.checked_add(2),
+                    };
+                    pending.push(next);
+                    pending.extend(skipped);
+                }
+                _ => pending.extend(next),
+            }
+        }
+        analysis
+    }
+
+    fn reference(&mut self, address: u16, reference: Reference) {
+        if (START_ADDRESS as u32..self.end).contains(&(address as u32)) {
+            let entry = self.references.entry(address).or_insert(reference);
+            *entry = (*entry).max(reference);
+        }
+    }
+
+    /// Names for every referenced address, e.g. `sub_2A4`
+    fn labels(&self) -> BTreeMap<u16, String> {
+        self.references
+            .iter()
+            // A label inside an instruction can't be placed on a line
+            .filter(|(&address, _)| {
+                let inside = self
+                    .code
+                    .range(..address)
+                    .next_back()
+                    .is_some_and(|(&start, instruction)| (address as u32) < start as u32 + instruction.size() as u32);
+                !inside
+            })
+            .map(|(&address, reference)| {
+                let prefix = match reference {
+                    Reference::Call => "sub",
+                    Reference::Jump => "label",
+                    Reference::Data => "data",
+                };
+                (address, format!("{prefix}_{address:03X}"))
+            })
+            .collect()
+    }
+}
+
+/// Formats an instruction, naming addresses through `operand`
+fn format_instruction(
+    instruction: Instruction,
+    syntax: Syntax,
+    long_target: Option<u16>,
+    operand: &dyn Fn(u16) -> String,
+) -> String {
+    match syntax {
+        Syntax::Classic => match instruction {
+            Instruction::Jump(nnn) => format!("JP {}", operand(nnn)),
+            Instruction::Call(nnn) => format!("CALL {}", operand(nnn)),
+            Instruction::SetI(nnn) => format!("LD I, {}", operand(nnn)),
+            Instruction::JumpOffset(nnn) => format!("JP V0, {}", operand(nnn)),
+            Instruction::SetILong => format!("LD I, LONG {}", operand(long_target.unwrap_or_default())),
+            _ => instruction.to_string(),
+        },
+        Syntax::Octo => format_octo(instruction, long_target, operand),
+    }
+}
+
+fn format_octo(instruction: Instruction, long_target: Option<u16>, operand: &dyn Fn(u16) -> String) -> String {
+    let v = |register: u8| format!("v{register:x}");
+    match instruction {
+        Instruction::Nop => "0x00 0x00".to_string(),
+        Instruction::ScrollDown(n) => format!("scroll-down {n}"),
+        Instruction::ScrollUp(n) => format!("scroll-up {n}"),
+        Instruction::Clear => "clear".to_string(),
+        Instruction::Return => "return".to_string(),
+        Instruction::ScrollRight => "scroll-right".to_string(),
+        Instruction::ScrollLeft => "scroll-left".to_string(),
+        Instruction::Exit => "exit".to_string(),
+        Instruction::Lores => "lores".to_string(),
+        Instruction::Hires => "hires".to_string(),
+        Instruction::Sys(nnn) => format!("0x{:02x} 0x{:02x}", nnn >> 8, nnn & 0xFF),
+        Instruction::Jump(nnn) => format!("jump {}", operand(nnn)),
+        Instruction::Call(nnn) => format!(":call {}", operand(nnn)),
+        // Octo conditionals name the case that runs the next instruction
+        Instruction::SkipEqImm { x, nn } => format!("if {} != 0x{nn:02x} then", v(x)),
+        Instruction::SkipNeImm { x, nn } => format!("if {} == 0x{nn:02x} then", v(x)),
+        Instruction::SkipEq { x, y } => format!("if {} != {} then", v(x), v(y)),
+        Instruction::SkipNe { x, y } => format!("if {} == {} then", v(x), v(y)),
+        Instruction::SkipKey(x) => format!("if {} -key then", v(x)),
+        Instruction::SkipNotKey(x) => format!("if {} key then", v(x)),
+        Instruction::SaveRange { x, y } => format!("save {} - {}", v(x), v(y)),
+        Instruction::LoadRange { x, y } => format!("load {} - {}", v(x), v(y)),
+        Instruction::SetImm { x, nn } => format!("{} := 0x{nn:02x}", v(x)),
+        Instruction::AddImm { x, nn } => format!("{} += 0x{nn:02x}", v(x)),
+        Instruction::Set { x, y } => format!("{} := {}", v(x), v(y)),
+        Instruction::Or { x, y } => format!("{} |= {}", v(x), v(y)),
+        Instruction::And { x, y } => format!("{} &= {}", v(x), v(y)),
+        Instruction::Xor { x, y } => format!("{} ^= {}", v(x), v(y)),
+        Instruction::Add { x, y } => format!("{} += {}", v(x), v(y)),
+        Instruction::Sub { x, y } => format!("{} -= {}", v(x), v(y)),
+        Instruction::ShiftRight { x, y } => format!("{} >>= {}", v(x), v(y)),
+        Instruction::SubReverse { x, y } => format!("{} =- {}", v(x), v(y)),
+        Instruction::ShiftLeft { x, y } => format!("{} <<= {}", v(x), v(y)),
+        Instruction::SetI(nnn) => format!("i := {}", operand(nnn)),
+        Instruction::JumpOffset(nnn) => format!("jump0 {}", operand(nnn)),
+        Instruction::Random { x, nn } => format!("{} := random 0x{nn:02x}", v(x)),
+        Instruction::Draw { x, y, n } => format!("sprite {} {} {n}", v(x), v(y)),
+        Instruction::SetILong => format!("i := long {}", operand(long_target.unwrap_or_default())),
+        Instruction::Plane(n) => format!("plane {n}"),
+        Instruction::Audio => "audio".to_string(),
+        Instruction::GetDelay(x) => format!("{} := delay", v(x)),
+        Instruction::WaitKey(x) => format!("{} := key", v(x)),
+        Instruction::SetDelay(x) => format!("delay := {}", v(x)),
+        Instruction::SetSound(x) => format!("buzzer := {}", v(x)),
+        Instruction::AddI(x) => format!("i += {}", v(x)),
+        Instruction::Font(x) => format!("i := hex {}", v(x)),
+        Instruction::BigFont(x) => format!("i := bighex {}", v(x)),
+        Instruction::Bcd(x) => format!("bcd {}", v(x)),
+        Instruction::Pitch(x) => format!("pitch := {}", v(x)),
+        Instruction::Store(x) => format!("save {}", v(x)),
+        Instruction::Load(x) => format!("load {}", v(x)),
+        Instruction::StoreFlags(x) => format!("saveflags {}", v(x)),
+        Instruction::LoadFlags(x) => format!("loadflags {}", v(x)),
+        Instruction::Invalid(operation) => format!("0x{:02x} 0x{:02x}", operation >> 8, operation & 0xFF),
+    }
+}
+
+fn format_data(bytes: &[u8], syntax: Syntax) -> String {
+    match syntax {
+        Syntax::Classic => {
+            let bytes: Vec<String> = bytes.iter().map(|byte| format!("0x{byte:02X}")).collect();
+            format!("DB {}", bytes.join(", "))
+        }
+        Syntax::Octo => {
+            let bytes: Vec<String> = bytes.iter().map(|byte| format!("0x{byte:02x}")).collect();
+            bytes.join(" ")
+        }
+    }
+}
+
+impl fmt::Display for Listing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for line in &self.lines {
+            if let Some(label) = &line.label {
+                match self.syntax {
+                    Syntax::Classic => writeln!(f, "{label}:")?,
+                    Syntax::Octo => writeln!(f, ": {label}")?,
+                }
+            }
+            let text = format!("    {}", line.text);
+            if self.annotate {
+                let comment = match self.syntax {
+                    Syntax::Classic => ';',
+                    Syntax::Octo => '#',
+                };
+                let bytes: Vec<String> = line.bytes.iter().map(|byte| format!("{byte:02X}")).collect();
+                let width = ANNOTATION_COLUMN - 1;
+                writeln!(f, "{text:<width$} {comment} {:03X}: {}", line.address, bytes.join(" "))?;
+            } else {
+                writeln!(f, "{text}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests;
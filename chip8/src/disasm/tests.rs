@@ -0,0 +1,96 @@
+use super::*;
+
+fn rom(words: &[u16]) -> Vec<u8> {
+    words.iter().flat_map(|word| word.to_be_bytes()).collect()
+}
+
+fn texts(listing: &Listing) -> Vec<&str> {
+    listing.lines.iter().map(|line| line.text.as_str()).collect()
+}
+
+#[test]
+fn separates_code_from_data() {
+    // Draw the sprite at 0x208 forever
+    let program = rom(&[0xA208, 0xD015, 0x1204, 0x0000, 0xF090, 0x9090, 0xF000]);
+    let listing = disassemble(&program, &DisasmOptions::default()).unwrap();
+    assert_eq!(
+        texts(&listing),
+        ["LD I, data_208", "DRW V0, V1, 5", "JP label_204", "DB 0x00, 0x00", "DB 0xF0, 0x90, 0x90, 0x90, 0xF0, 0x00"]
+    );
+    assert_eq!(listing.lines[2].label.as_deref(), Some("label_204"));
+    assert_eq!(listing.lines[4].label.as_deref(), Some("data_208"));
+    assert_eq!(listing.lines[4].instruction, None);
+}
+
+#[test]
+fn follows_calls_and_skips() {
+    let program = rom(&[0x220A, 0x3000, 0x1200, 0x00FD, 0xFFFF, 0x6001, 0x00EE]);
+    let options = DisasmOptions { mode: Mode::SuperChip, ..DisasmOptions::default() };
+    let listing = disassemble(&program, &options).unwrap();
+    assert_eq!(
+        texts(&listing),
+        ["CALL sub_20A", "SE V0, 0x00", "JP label_200", "EXIT", "DB 0xFF, 0xFF", "LD V0, 0x01", "RET"]
+    );
+}
+
+#[test]
+fn extensions_depend_on_mode() {
+    let program = rom(&[0x00FF, 0x1200]);
+    let listing = disassemble(&program, &DisasmOptions::default()).unwrap();
+    assert_eq!(texts(&listing), ["DB 0x00, 0xFF, 0x12, 0x00"]);
+
+    let options = DisasmOptions { mode: Mode::SuperChip, ..DisasmOptions::default() };
+    assert_eq!(texts(&disassemble(&program, &options).unwrap()), ["HIGH", "JP label_200"]);
+}
+
+#[test]
+fn long_load_spans_four_bytes() {
+    let program = rom(&[0xF000, 0x0206, 0x1200, 0x55AA]);
+    let options = DisasmOptions { mode: Mode::XoChip, syntax: Syntax::Octo, annotate: false };
+    let listing = disassemble(&program, &options).unwrap();
+    assert_eq!(texts(&listing), ["i := long data_206", "jump label_200", "0x55 0xaa"]);
+    assert_eq!(listing.lines[0].bytes, [0xF0, 0x00, 0x02, 0x06]);
+}
+
+#[test]
+fn octo_listing() {
+    let program = rom(&[0x6A05, 0x4A05, 0x7A01, 0x8AB4, 0xFA29, 0x1200]);
+    let options = DisasmOptions { syntax: Syntax::Octo, annotate: false, ..DisasmOptions::default() };
+    let listing = disassemble(&program, &options).unwrap();
+    assert_eq!(
+        listing.to_string(),
+        ": label_200\n    va := 0x05\n    if va == 0x05 then\n    va += 0x01\n    va += vb\n    i := hex va\n    jump label_200\n"
+    );
+}
+
+#[test]
+fn annotated_listing() {
+    let listing = disassemble(&rom(&[0x00E0, 0x1202]), &DisasmOptions::default()).unwrap();
+    assert_eq!(
+        listing.to_string(),
+        "    CLS                     ; 200: 00 E0\nlabel_202:\n    JP label_202            ; 202: 12 02\n"
+    );
+}
+
+#[test]
+fn full_size_rom_stops_at_the_end_of_memory() {
+    let mut words = vec![0x6000; MAX_ROM_SIZE / 2];
+    let len = words.len();
+    // A skip over a long load that ends at the last address
+    words[len - 3..].copy_from_slice(&[0x3000, 0xF000, 0x0200]);
+    let options = DisasmOptions { mode: Mode::XoChip, ..DisasmOptions::default() };
+    let listing = disassemble(&rom(&words), &options).unwrap();
+    let last = listing.lines.last().unwrap();
+    assert_eq!(last.address, 0xFFFC);
+    assert_eq!(last.text, "LD I, LONG data_200");
+    assert!(listing.lines.iter().all(|line| line.instruction.is_some()));
+}
+
+#[test]
+fn rejects_roms_past_the_address_space() {
+    let program = vec![0; MAX_ROM_SIZE + 1];
+    assert!(matches!(
+        disassemble(&program, &DisasmOptions::default()),
+        Err(Chip8Error::RomTooLarge { size, max: MAX_ROM_SIZE }) if size == MAX_ROM_SIZE + 1
+    ));
+}
@@ -1,9 +1,13 @@
-use std::cell::RefCell;
-use std::rc::Rc;
-use crate::emulator::{Emulator, EmulatorComponent};
+use crate::cpu::CPU;
+use crate::emulator::EmulatorComponent;
+use crate::error::Chip8Error;
+use crate::memory::Memory;
+use crate::state::{invalid, StateReader, StateWriter};
 
-pub const SCREEN_WIDTH: usize = 64;
-pub const SCREEN_HEIGHT: usize = 32;
+pub const LORES_WIDTH: usize = 64;
+pub const LORES_HEIGHT: usize = 32;
+pub const HIRES_WIDTH: usize = 128;
+pub const HIRES_HEIGHT: usize = 64;
 pub const FONT_SET_SIZE: usize = 80;
 pub const FONT_SET: [u8; FONT_SET_SIZE] = [
     0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
@@ -24,60 +28,285 @@ pub const FONT_SET: [u8; FONT_SET_SIZE] = [
     0xF0, 0x80, 0xF0, 0x80, 0x80, // F
 ];
 
+/// SUPER-CHIP 8x10 font, stored in memory right after the small font
+pub const BIG_FONT_ADDRESS: usize = FONT_SET_SIZE;
+pub const BIG_FONT_SET_SIZE: usize = 160;
+pub const BIG_FONT_SET: [u8; BIG_FONT_SET_SIZE] = [
+    0x3C, 0x7E, 0xE7, 0xC3, 0xC3, 0xC3, 0xC3, 0xE7, 0x7E, 0x3C, // 0
+    0x18, 0x38, 0x58, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3C, // 1
+    0x3E, 0x7F, 0xC3, 0x06, 0x0C, 0x18, 0x30, 0x60, 0xFF, 0xFF, // 2
+    0x3C, 0x7E, 0xC3, 0x03, 0x0E, 0x0E, 0x03, 0xC3, 0x7E, 0x3C, // 3
+    0x06, 0x0E, 0x1E, 0x36, 0x66, 0xC6, 0xFF, 0xFF, 0x06, 0x06, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFE, 0x03, 0xC3, 0x7E, 0x3C, // 5
+    0x3E, 0x7C, 0xE0, 0xC0, 0xFC, 0xFE, 0xC3, 0xC3, 0x7E, 0x3C, // 6
+    0xFF, 0xFF, 0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x60, 0x60, // 7
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7E, 0x7E, 0xC3, 0xC3, 0x7E, 0x3C, // 8
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7F, 0x3F, 0x03, 0x03, 0x3E, 0x7C, // 9
+    0x3C, 0x7E, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, // A
+    0xFC, 0xFE, 0xC3, 0xC3, 0xFE, 0xFE, 0xC3, 0xC3, 0xFE, 0xFC, // B
+    0x3C, 0x7E, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0x7E, 0x3C, // C
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFC, 0xC0, 0xC0, 0xFF, 0xFF, // E
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFC, 0xC0, 0xC0, 0xC0, 0xC0, // F
+];
+
+/// Number of XO-CHIP bitplanes
+pub const NUMBER_OF_PLANES: usize = 2;
+
+/// Read-only view of the visible part of the framebuffer
+#[derive(Debug, Clone, Copy)]
+pub struct Framebuffer<'a> {
+    pixels: &'a [u8],
+    width: usize,
+    height: usize,
+}
+
+impl<'a> Framebuffer<'a> {
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Plane bits of the pixel at X Y, zero when unlit
+    pub fn get(&self, x: usize, y: usize) -> u8 {
+        self.pixels[x + self.width * y]
+    }
+
+    pub fn is_lit(&self, x: usize, y: usize) -> bool {
+        self.get(x, y) != 0
+    }
+
+    /// Pixels in row-major order, one byte of plane bits each
+    pub fn as_slice(&self) -> &'a [u8] {
+        self.pixels
+    }
+
+    /// Iterates over the rows from top to bottom
+    pub fn rows(&self) -> impl Iterator<Item = &'a [u8]> {
+        self.pixels.chunks(self.width)
+    }
+
+    /// Iterates over every pixel as (x, y, plane bits)
+    pub fn pixels(&self) -> impl Iterator<Item = (usize, usize, u8)> + 'a {
+        let width = self.width;
+        self.pixels.iter().enumerate().map(move |(idx, &pixel)| (idx % width, idx / width, pixel))
+    }
+}
+
+/// Framebuffer, either 64x32 (lores) or 128x64 (hires).
+///
+/// Each pixel holds one bit per bitplane. Only XO-CHIP programs select
+/// planes other than the first, so classic programs see a monochrome screen.
 pub struct Display {
-    emulator: Rc<RefCell<Emulator>>,
-    screen: [bool; SCREEN_WIDTH * SCREEN_HEIGHT],
+    screen: [u8; HIRES_WIDTH * HIRES_HEIGHT],
+    hires: bool,
+    planes: u8,
 }
 
 impl Display {
-    pub fn new(emulator: Rc<RefCell<Emulator>>) -> Self {
-        let display = Self {
-            emulator,
-            screen: [false; SCREEN_WIDTH * SCREEN_HEIGHT],
-        };
-        display
+    pub fn new() -> Self {
+        Self {
+            screen: [0; HIRES_WIDTH * HIRES_HEIGHT],
+            hires: false,
+            planes: 1,
+        }
+    }
+
+    /// Width of the current resolution in pixels
+    pub fn get_width(&self) -> usize {
+        if self.hires { HIRES_WIDTH } else { LORES_WIDTH }
+    }
+
+    /// Height of the current resolution in pixels
+    pub fn get_height(&self) -> usize {
+        if self.hires { HIRES_HEIGHT } else { LORES_HEIGHT }
+    }
+
+    pub fn is_hires(&self) -> bool {
+        self.hires
+    }
+
+    /// Bitmask of the planes that drawing, clearing and scrolling affect
+    pub fn get_selected_planes(&self) -> u8 {
+        self.planes
+    }
+
+    /// View of the pixels at the current resolution
+    pub fn get_framebuffer(&self) -> Framebuffer<'_> {
+        let (width, height) = (self.get_width(), self.get_height());
+        Framebuffer {
+            pixels: &self.screen[..width * height],
+            width,
+            height,
+        }
+    }
+
+    /// Whether the pixel at X Y is lit on any plane
+    pub fn get_pixel(&self, x: usize, y: usize) -> bool {
+        self.get_pixel_planes(x, y) != 0
+    }
+
+    /// Plane bits of the pixel at X Y, used as a palette index
+    pub fn get_pixel_planes(&self, x: usize, y: usize) -> u8 {
+        self.screen[x + self.get_width() * y]
     }
 
-    /// Clear screen buffer
+    /// Serializes the resolution, plane selection and every pixel
+    pub(crate) fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bool(self.hires);
+        writer.write_u8(self.planes);
+        writer.write_bytes(&self.screen);
+    }
+
+    pub(crate) fn load_state(&mut self, reader: &mut StateReader) -> Result<(), Chip8Error> {
+        self.hires = reader.read_bool()?;
+        self.planes = reader.read_u8()?;
+        if self.planes >= 1 << NUMBER_OF_PLANES {
+            return Err(invalid("plane selection out of range"));
+        }
+        self.screen.copy_from_slice(reader.read_bytes(HIRES_WIDTH * HIRES_HEIGHT)?);
+        if self.screen.iter().any(|&pixel| pixel >= 1 << NUMBER_OF_PLANES) {
+            return Err(invalid("pixel out of range"));
+        }
+        Ok(())
+    }
+
+    /// Clear screen buffer on the selected planes
     pub fn op_cls(&mut self) {
-        self.reset();
+        let mask = !self.planes;
+        self.screen.iter_mut().for_each(|pixel| *pixel &= mask);
+    }
+
+    /// LOW/HIGH - Switch resolution, clearing every plane
+    pub fn op_set_hires(&mut self, hires: bool) {
+        self.hires = hires;
+        self.screen = [0; HIRES_WIDTH * HIRES_HEIGHT];
+    }
+
+    /// PLANE N - Select the bitplanes affected by later drawing
+    pub fn op_select_planes(&mut self, planes: u8) {
+        self.planes = planes & ((1 << NUMBER_OF_PLANES) - 1);
+    }
+
+    /// SCD N - Scroll the screen down N pixels
+    pub fn op_scroll_down(&mut self, n: usize) {
+        self.scroll(0, n as isize);
+    }
+
+    /// SCU N - Scroll the screen up N pixels
+    pub fn op_scroll_up(&mut self, n: usize) {
+        self.scroll(0, -(n as isize));
+    }
+
+    /// SCR - Scroll the screen right 4 pixels
+    pub fn op_scroll_right(&mut self) {
+        self.scroll(4, 0);
+    }
+
+    /// SCL - Scroll the screen left 4 pixels
+    pub fn op_scroll_left(&mut self) {
+        self.scroll(-4, 0);
     }
 
-    /// Draws sprite at X Y location
-    pub fn op_drw(&mut self, x_coord: usize, y_coord: usize, num_rows: usize) {
+    /// Moves the selected planes by the given offset, filling with unlit pixels
+    fn scroll(&mut self, dx: isize, dy: isize) {
+        let (width, height) = (self.get_width() as isize, self.get_height() as isize);
+        let mask = self.planes;
+        let old = self.screen;
+        for y in 0..height {
+            for x in 0..width {
+                let (src_x, src_y) = (x - dx, y - dy);
+                let src = if (0..width).contains(&src_x) && (0..height).contains(&src_y) {
+                    old[(src_x + width * src_y) as usize]
+                } else {
+                    0
+                };
+                let idx = (x + width * y) as usize;
+                self.screen[idx] = (old[idx] & !mask) | (src & mask);
+            }
+        }
+    }
+
+    /// Draws an 8 pixel wide sprite of N rows at X Y location
+    pub fn op_drw(&mut self, cpu: &mut CPU, memory: &Memory, x_coord: usize, y_coord: usize, num_rows: usize, clip: bool) -> Result<(), Chip8Error> {
+        self.draw(cpu, memory, x_coord, y_coord, num_rows, 1, clip)
+    }
+
+    /// Draws a 16x16 sprite at X Y location (DXY0)
+    pub fn op_drw_large(&mut self, cpu: &mut CPU, memory: &Memory, x_coord: usize, y_coord: usize, clip: bool) -> Result<(), Chip8Error> {
+        self.draw(cpu, memory, x_coord, y_coord, 16, 2, clip)
+    }
+
+    /// Draws one sprite per selected plane, stored back to back at I
+    #[allow(clippy::too_many_arguments)]
+    fn draw(&mut self, cpu: &mut CPU, memory: &Memory, x_coord: usize, y_coord: usize, num_rows: usize, bytes_per_row: usize, clip: bool) -> Result<(), Chip8Error> {
+        let sprite_len = num_rows * bytes_per_row;
+        let selected: Vec<u8> = (0..NUMBER_OF_PLANES)
+            .map(|plane| 1 << plane)
+            .filter(|plane| self.planes & plane != 0)
+            .collect();
+        // Read the whole sprite first so a fault leaves the screen untouched
+        let i = cpu.get_i_register() as usize;
+        let sprite = (0..sprite_len * selected.len())
+            .map(|offset| memory.fetch_byte(i + offset))
+            .collect::<Result<Vec<u8>, Chip8Error>>()?;
+        let mut flipped = false;
+        for (plane_idx, &plane) in selected.iter().enumerate() {
+            let data = &sprite[plane_idx * sprite_len..(plane_idx + 1) * sprite_len];
+            flipped |= self.draw_sprite(data, plane, bytes_per_row, x_coord, y_coord, clip);
+        }
+        cpu.set_register_value(0xF, flipped as u8);
+        Ok(())
+    }
+
+    /// XORs a sprite onto one plane, returning whether any lit pixel was flipped off.
+    ///
+    /// The starting position always wraps around the screen. Pixels past the
+    /// edges are either clipped or wrapped, depending on `clip`.
+    fn draw_sprite(&mut self, sprite: &[u8], plane: u8, bytes_per_row: usize, x_coord: usize, y_coord: usize, clip: bool) -> bool {
+        let (width, height) = (self.get_width(), self.get_height());
+        let x_coord = x_coord % width;
+        let y_coord = y_coord % height;
         // Keep track if any pixels were flipped
         let mut flipped = false;
         // Iterate over each row of our sprite
-        for y_line in 0..num_rows {
-            // Determine which memory address our row's data is stored
-            let addr = self.emulator.get_mut().get_cpu().get_i_register() + y_line as u16;
-            let pixels = self.emulator.get_mut().get_memory().fetch_byte(addr);
+        for (y_line, row) in sprite.chunks(bytes_per_row).enumerate() {
             // Iterate over each column in our row
-            for x_line in 0..8 {
+            for x_line in 0..bytes_per_row * 8 {
                 // Use a mask to fetch current pixel's bit. Only flip if a 1
-                if (pixels & (0b1000_0000 >> x_line)) != 0 {
-                    // Sprites should wrap around screen, so apply modulo
-                    let x = (x_coord + x_line) % SCREEN_WIDTH;
-                    let y = (y_coord + y_line) % SCREEN_HEIGHT;
+                if (row[x_line / 8] & (0b1000_0000 >> (x_line % 8))) != 0 {
+                    let (x, y) = (x_coord + x_line, y_coord + y_line);
+                    if clip && (x >= width || y >= height) {
+                        continue;
+                    }
+                    // Wrapping sprites continue on the opposite edge, so apply modulo
+                    let x = x % width;
+                    let y = y % height;
                     // Get our pixel's index for our 1D screen array
-                    let idx = x + SCREEN_WIDTH * y;
+                    let idx = x + width * y;
                     // Check if we're about to flip the pixel and set
-                    flipped |= self.screen[idx];
-                    self.screen[idx] = self.screen[idx] ^ true;
+                    flipped |= self.screen[idx] & plane != 0;
+                    self.screen[idx] ^= plane;
                 }
             }
         }
-        // Populate VF register
-        if flipped {
-            self.emulator.get_mut().get_cpu().set_register_value(0xF, 1);
-        } else {
-            self.emulator.get_mut().get_cpu().set_register_value(0xF, 0);
-        }
+        flipped
+    }
+}
+
+impl Default for Display {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 impl EmulatorComponent for Display {
     fn reset(&mut self) {
-        self.screen = [false; SCREEN_WIDTH * SCREEN_HEIGHT];
+        self.screen = [0; HIRES_WIDTH * HIRES_HEIGHT];
+        self.hires = false;
+        self.planes = 1;
     }
-}
\ No newline at end of file
+}
@@ -1,143 +1,553 @@
-use std::cell::RefCell;
-use std::rc::Rc;
-use crate::cpu::CPU;
-use crate::display::Display;
-use crate::input::Input;
-use crate::memory::Memory;
+use std::fs::File;
+use std::io::Read;
+use std::ops::RangeInclusive;
+use std::path::Path;
+use crate::audio::AudioState;
+use crate::config::{Config, FaultPolicy, Mode};
+use crate::cpu::{CpuState, CPU};
+use crate::display::{Display, Framebuffer};
+use crate::error::Chip8Error;
+use crate::input::{Input, NUMBER_OF_KEYS};
+use crate::instruction::{decode, decode_for, Instruction};
+use crate::memory::{Access, Memory, MemoryAccess};
+use crate::rewind::RewindBuffer;
+use crate::rng::{Rng, XorShiftRng};
+use crate::rom::RomInfo;
+use crate::state::{invalid, StateReader, StateWriter};
+use crate::trace::{TraceRecord, Tracer};
 
 /// Represents the CHIP-8 emulator itself and its internal components
 ///
 /// Constructor will initiate memory with default font set.
+///
+/// The emulator is the single owner of the machine state. Opcode handlers on
+/// each component borrow whichever other components they need, so the whole
+/// machine is `Send` and can be moved to a worker thread.
 pub struct Emulator {
-    cpu: Option<Box<CPU>>,
-    memory: Option<Box<Memory>>,
-    display: Option<Box<Display>>,
-    input: Option<Box<Input>>,
+    cpu: CPU,
+    memory: Memory,
+    display: Display,
+    input: Input,
+    config: Config,
+    rom_info: Option<RomInfo>,
+    halted: bool,
+    vblank_wait: bool,
+    frame_count: u64,
+    rewind: Option<RewindBuffer>,
+    rng: Box<dyn Rng>,
+    tracer: Option<Tracer>,
+}
+
+/// Result of executing a single instruction
+#[derive(Debug)]
+pub enum StepOutcome {
+    /// Instruction executed normally
+    Executed,
+    /// Instruction faulted and was skipped according to the fault policy
+    Skipped(Chip8Error),
+    /// Machine is halted after an earlier fault, nothing was executed
+    Halted,
+    /// Machine is waiting for the next frame after a draw, nothing was executed
+    WaitingForVblank,
+    /// FX0A is blocked until a key is pressed or released
+    WaitingForKey,
+    /// Program exited with 00FD, the machine is now halted
+    Exited,
 }
 
+// The emulator must stay movable across threads.
+const _: fn() = || {
+    fn assert_send<T: Send>() {}
+    assert_send::<Emulator>();
+};
+
 pub trait EmulatorComponent {
     fn reset(&mut self);
 }
 
 impl Emulator {
     /// Constructor
-    pub fn new(&mut self) -> Self {
-        let emulator = Rc::new(RefCell::new(Emulator { cpu: None, memory: None, display: None, input: None }));
-        let emulator_mut = emulator.borrow_mut();
-        emulator_mut.cpu = Some(Box::new(CPU::new(emulator.clone())));
-        emulator_mut.memory = Some(Box::new(Memory::new(emulator.clone())));
-        emulator_mut.display = Some(Box::new(Display::new(emulator.clone())));
-        emulator_mut.input = Some(Box::new(Input::new(emulator.clone())));
+    pub fn new() -> Self {
+        Self::with_config(Config::default())
+    }
+
+    /// Constructor with custom settings
+    ///
+    /// Without a seed in the config one is picked at random and stored back
+    /// into the config, so the run can be reproduced.
+    pub fn with_config(mut config: Config) -> Self {
+        let seed = *config.seed.get_or_insert_with(rand::random);
+        let mut emulator = Self {
+            cpu: CPU::new(),
+            memory: Memory::new(config.mode.ram_size()),
+            display: Display::new(),
+            input: Input::new(),
+            rewind: config.rewind.map(RewindBuffer::new),
+            rng: Box::new(XorShiftRng::new(seed)),
+            config,
+            rom_info: None,
+            halted: false,
+            vblank_wait: false,
+            frame_count: 0,
+            tracer: None,
+        };
+        let wrap = emulator.config.fault_policy == FaultPolicy::Wrap;
+        emulator.cpu.set_wrap_stack(wrap);
+        emulator.memory.set_wrap(wrap);
         emulator
     }
 
-    pub fn get_cpu(&mut self) -> &mut CPU {
-        &mut self.cpu
+    pub fn get_config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Replaces the CXNN generator, for example with a `VipRng`.
+    ///
+    /// The generator is seeded from the config and reseeded on every reset.
+    pub fn set_rng(&mut self, mut rng: Box<dyn Rng>) {
+        rng.seed(self.get_seed());
+        self.rng = rng;
+    }
+
+    /// Seed the CXNN generator starts from after a reset
+    pub fn get_seed(&self) -> u64 {
+        self.config.seed.unwrap_or_default()
+    }
+
+    /// Loads a ROM into memory at the program start address.
+    ///
+    /// The machine is reset before loading. A ROM too large for memory is
+    /// rejected without touching the running program.
+    pub fn load_rom(&mut self, rom: &[u8]) -> Result<(), Chip8Error> {
+        self.memory.load_program(rom)?;
+        self.reset_machine();
+        self.rom_info = Some(RomInfo::new(rom));
+        Ok(())
+    }
+
+    /// Resets everything but memory, which `load_rom` resets while loading
+    fn reset_machine(&mut self) {
+        self.cpu.reset();
+        self.display.reset();
+        self.input.reset();
+        self.rom_info = None;
+        self.halted = false;
+        self.vblank_wait = false;
+        self.frame_count = 0;
+        if let Some(buffer) = &mut self.rewind {
+            buffer.clear();
+        }
+        self.rng.seed(self.get_seed());
+    }
+
+    /// Loads a ROM from a file on disk
+    pub fn load_rom_file<P: AsRef<Path>>(&mut self, path: P) -> Result<(), Chip8Error> {
+        self.load_rom_reader(File::open(path)?)
+    }
+
+    /// Loads a ROM from any reader, consuming it to the end
+    pub fn load_rom_reader<R: Read>(&mut self, mut reader: R) -> Result<(), Chip8Error> {
+        let mut rom = Vec::new();
+        reader.read_to_end(&mut rom)?;
+        self.load_rom(&rom)
+    }
+
+    /// Length and hash of the currently loaded ROM, if any
+    pub fn get_rom_info(&self) -> Option<&RomInfo> {
+        self.rom_info.as_ref()
+    }
+
+    pub fn get_cpu(&self) -> &CPU {
+        &self.cpu
     }
 
     pub fn get_memory(&self) -> &Memory {
         &self.memory
     }
 
-    /// Split an opcode into 4 nibbles
-    fn split_operation(operation: u16) -> (u16, u16, u16, u16) {
-        let digit1 = (operation & 0xF000) >> 12;
-        let digit2 = (operation & 0x0F00) >> 8;
-        let digit3 = (operation & 0x00F0) >> 4;
-        let digit4 = operation & 0x000F;
-        (digit1, digit2, digit3, digit4)
-    }
-
-    fn fetch(&mut self) -> u16 {
-        let operation = self.memory.as_ref().fetch_word(self.cpu.get_program_counter());
-        self.cpu.set_program_counter(self.cpu.get_program_counter() + 2);
-        operation
-    }
-
-    fn execute(&mut self, operation: u16) {
-        let (digit1, digit2, digit3, digit4) = Emulator::split_operation(operation);
-        match (digit1, digit2, digit3, digit4) {
-            // NOP - No Operation
-            (0, 0, 0, 0) => return,
-            // CLS - Clear Screen
-            (0, 0, 0xE, 0) => self.display.op_cls(),
-            // RET - Return from subroutine
-            (0, 0, 0xE, 0xE) => self.cpu.op_ret(),
-            // JMP NNN - Move program counter to given address
-            (1, _, _, _) => self.cpu.op_jmp(operation),
-            // CALL NNN - Goto subroutine
-            (2, _, _, _) => self.cpu.op_call(operation),
-            // SKIP VX == NN
-            (3, _, _, _) => self.cpu.op_se(operation, digit2.into()),
-            // SKIP VX != NN
-            (4, _, _, _) => self.cpu.op_sne(operation, digit2.into()),
-            // SKIP VX == VY
-            (5, _, _, 0) => self.cpu.op_reg_se(digit2.into(), digit3.into()),
-            // LD VX = NN
-            (6, _, _, _) => self.cpu.op_ld(operation, digit2.into()),
-            // ADD VX += NN
-            (7, _, _, _) => self.cpu.op_add(operation, digit2.into()),
-            // OR VX |= VY
-            (8, _, _, 1) => self.cpu.op_reg_or(digit2.into(), digit3.into()),
-            // ADD VX += VY
-            (8, _, _, 4) => self.cpu.op_reg_add(digit2.into(), digit3.into()),
-            // SUB VX -= VY
-            (8, _, _, 5) => self.cpu.op_reg_sub(digit2.into(), digit3.into(), false),
-            // SHR VX
-            (8, _, _, 6) => self.cpu.op_shift(digit2.into(), true),
-            // SUB VX = VY - VX
-            (8, _, _, 7) => self.cpu.op_reg_sub(digit2.into(), digit3.into(), true),
-            // SHL VX
-            (8, _, _, 0xE) => self.cpu.op_shift(digit2.into(), false),
-            // LD VX = VY
-            (8, _, _, _) => self.cpu.op_reg_ld(digit2.into(), digit3.into()),
-            // SKIP VX != VY
-            (9, _, _, _) => self.cpu.op_reg_sne(digit2.into(), digit3.into()),
-            // LD I = NNN
-            (0xA, _, _, _) => self.cpu.op_i_ld(operation),
-            // JMP V0 + NNN
-            (0xB, _, _, _) => self.cpu.op_reg_jmp(operation),
-            // RND Vx = Rand & NN
-            (0xC, _, _, _) => self.cpu.op_rnd(operation, digit2.into()),
-            // DRW Vx Vy
-            (0xD, _, _, _) => self.display.op_drw(self.cpu.get_register_value(digit2.into()).into(), self.cpu.get_register_value(digit3.into()).into(), digit4.into()),
-            // SKP Vx
-            (0xE, _, 9, 0xE) => self.input.op_skp(digit2.into(), false),
-            // SKNP Vx
-            (0xE, _, 0xA, 1) => self.input.op_skp(digit2.into(), true),
-            // LD Vx = DT
-            (0xF, _, 0, 7) => self.cpu.op_ld_dt(digit2.into()),
-            // LD Vx K **BLOCKING**
-            (0xF, _, 0, 0xA) => self.input.op_ld_wait(digit2.into()),
-            // LD DT = VX
-            (0xF, _, 1, 5) => self.memory.op_ld_dt(digit2.into()),
-            // LD ST = VX
-            (0xF, _, 1, 8) => self.memory.op_ld_st(digit2.into()),
-            // ADD I += VX
-            (0xF, _, 1, 0xE) => self.cpu.op_add_i(digit2.into()),
-            // LD I = Font
-            (0xF, _, 2, 9) => self.cpu.op_ld_font(digit2.into()),
-            // STR V0 - VX into I
-            (0xF, _, 5, 5) => self.memory.op_str(digit2.into()),
-            // LD I into V0 - VX
-            (0xF, _, 6, 5) => self.memory.op_ld(digit2.into()),
-            // Invalid opcode
-            (_, _, _, _) => unimplemented!("Unimplemented opcode: {operation}"),
+    pub fn get_display(&self) -> &Display {
+        &self.display
+    }
+
+    pub fn get_input(&self) -> &Input {
+        &self.input
+    }
+
+    /// Snapshot of V0 - VF, I, PC, SP and the stack
+    pub fn get_cpu_state(&self) -> CpuState {
+        self.cpu.get_state()
+    }
+
+    /// The whole address space, including the font and the loaded program
+    pub fn get_ram(&self) -> &[u8] {
+        self.memory.get_ram()
+    }
+
+    /// The visible framebuffer for rendering
+    pub fn get_framebuffer(&self) -> Framebuffer<'_> {
+        self.display.get_framebuffer()
+    }
+
+    /// Sets register VX, for debuggers
+    pub fn set_register(&mut self, x: usize, value: u8) {
+        self.cpu.set_register_value(x, value);
+    }
+
+    /// Sets the I register, for debuggers
+    pub fn set_i_register(&mut self, value: u16) {
+        self.cpu.set_i_register(value);
+    }
+
+    /// Moves execution to another address, for debuggers
+    pub fn set_program_counter(&mut self, value: u16) {
+        self.cpu.set_program_counter(value);
+    }
+
+    /// Sets the delay timer, for debuggers
+    pub fn set_delay_timer(&mut self, value: u8) {
+        self.memory.set_delay_timer(value);
+    }
+
+    /// Sets the sound timer, for debuggers
+    pub fn set_sound_timer(&mut self, value: u8) {
+        self.memory.set_sound_timer(value);
+    }
+
+    /// Writes bytes into RAM starting at `address`, for debuggers
+    pub fn write_memory(&mut self, address: usize, bytes: &[u8]) -> Result<(), Chip8Error> {
+        for (offset, &byte) in bytes.iter().enumerate() {
+            self.memory.store_byte(address + offset, byte)?;
+        }
+        Ok(())
+    }
+
+    /// Records RAM accesses of the given kinds to `range`, for debuggers.
+    /// Watches survive resets and loading save states.
+    pub fn watch_memory(&mut self, range: RangeInclusive<usize>, accesses: &[Access]) {
+        self.memory.watch(range, accesses);
+    }
+
+    /// Removes all memory watches
+    pub fn clear_memory_watches(&mut self) {
+        self.memory.clear_watches();
+    }
+
+    /// Watched RAM accesses since the last call, oldest first
+    pub fn take_memory_accesses(&self) -> Vec<MemoryAccess> {
+        self.memory.take_accesses()
+    }
+
+    /// Presses a hex keypad key (0x0 - 0xF)
+    pub fn key_down(&mut self, key: u8) {
+        self.input.set_key((key & 0xF) as usize, true);
+    }
+
+    /// Releases a hex keypad key (0x0 - 0xF)
+    pub fn key_up(&mut self, key: u8) {
+        self.input.set_key((key & 0xF) as usize, false);
+    }
+
+    /// Sets the whole keypad at once, bit N holds the state of key N
+    pub fn set_keypad(&mut self, keys: u16) {
+        for key in 0..NUMBER_OF_KEYS {
+            self.input.set_key(key, keys & (1 << key) != 0);
+        }
+    }
+
+    /// Whether the program is blocked on FX0A, frontends can idle until the
+    /// next key event
+    pub fn is_waiting_for_key(&self) -> bool {
+        self.input.is_waiting_for_key()
+    }
+
+    /// Serializes the whole machine into a versioned, checksummed save state
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut writer = StateWriter::new();
+        self.cpu.save_state(&mut writer);
+        self.memory.save_state(&mut writer);
+        self.display.save_state(&mut writer);
+        self.input.save_state(&mut writer);
+        writer.write_bool(self.halted);
+        writer.write_bool(self.vblank_wait);
+        writer.write_u64(self.frame_count);
+        writer.write_bool(self.rom_info.is_some());
+        let rom_info = self.rom_info.unwrap_or(RomInfo { len: 0, sha1: [0; 20] });
+        writer.write_u32(rom_info.len as u32);
+        writer.write_bytes(&rom_info.sha1);
+        let rng_state = self.rng.get_state();
+        writer.write_u8(rng_state.len() as u8);
+        writer.write_bytes(&rng_state);
+        writer.finish(self.config.mode)
+    }
+
+    /// Restores a state made by `save_state`.
+    ///
+    /// States from another mode or format version are rejected, and the
+    /// machine is left untouched if the state can't be restored.
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), Chip8Error> {
+        let mut reader = StateReader::open(state, self.config.mode)?;
+        let mut restored = Emulator::with_config(self.config.clone());
+        restored.cpu.load_state(&mut reader)?;
+        restored.memory.load_state(&mut reader)?;
+        restored.display.load_state(&mut reader)?;
+        restored.input.load_state(&mut reader)?;
+        restored.halted = reader.read_bool()?;
+        restored.vblank_wait = reader.read_bool()?;
+        restored.frame_count = reader.read_u64()?;
+        let has_rom = reader.read_bool()?;
+        let len = reader.read_u32()? as usize;
+        let sha1 = reader.read_bytes(20)?.try_into().unwrap();
+        restored.rom_info = has_rom.then_some(RomInfo { len, sha1 });
+        let rng_len = reader.read_u8()?.into();
+        let rng_state = reader.read_bytes(rng_len)?;
+        reader.finish()?;
+        // Keep whichever generator is plugged in, only its state is restored
+        if !self.rng.set_state(rng_state) {
+            return Err(invalid("random number generator state does not match"));
+        }
+        std::mem::swap(&mut restored.rng, &mut self.rng);
+        restored.rewind = self.rewind.take();
+        restored.tracer = self.tracer.take();
+        restored.memory.take_watches(&mut self.memory);
+        *self = restored;
+        Ok(())
+    }
+
+    /// Steps the machine back at least `frames` frames, or as far as the
+    /// rewind history reaches.
+    ///
+    /// Returns how many frames were rewound, zero when rewinding is disabled
+    /// or there is no history yet.
+    pub fn rewind(&mut self, frames: u32) -> Result<u64, Chip8Error> {
+        let Some(mut buffer) = self.rewind.take().filter(|_| frames > 0) else {
+            return Ok(0);
+        };
+        let result = match buffer.rewind(frames) {
+            Some((snapshot, rewound)) => self.load_state(snapshot).map(|()| rewound),
+            None => Ok(0),
+        };
+        self.rewind = Some(buffer);
+        result
+    }
+
+    /// Rewind history, when enabled in the config
+    pub fn get_rewind_buffer(&self) -> Option<&RewindBuffer> {
+        self.rewind.as_ref()
+    }
+
+    /// Starts recording every executed instruction, replacing any tracer
+    /// already plugged in
+    pub fn set_tracer(&mut self, tracer: Tracer) {
+        self.tracer = Some(tracer);
+    }
+
+    pub fn get_tracer(&self) -> Option<&Tracer> {
+        self.tracer.as_ref()
+    }
+
+    /// Stops tracing, handing back the tracer to flush or dump it
+    pub fn take_tracer(&mut self) -> Option<Tracer> {
+        self.tracer.take()
+    }
+
+    /// Whether the machine stopped after a fault or an 00FD exit
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    /// Whether the buzzer should currently be sounding
+    pub fn is_sound_active(&self) -> bool {
+        self.memory.get_sound_timer() > 0
+    }
+
+    /// Sound parameters for an `AudioGenerator`
+    pub fn get_audio_state(&self) -> AudioState {
+        AudioState {
+            active: self.is_sound_active(),
+            pattern: self.memory.get_audio_pattern().copied(),
+            pitch: self.memory.get_pitch(),
+        }
+    }
+
+    /// Number of frames run since the last reset
+    pub fn get_frame_count(&self) -> u64 {
+        self.frame_count
+    }
+
+    /// Reads the opcode at PC and advances PC by 2
+    fn fetch(&mut self) -> Result<u16, Chip8Error> {
+        let pc = self.cpu.get_program_counter();
+        self.cpu.set_program_counter(pc.wrapping_add(2));
+        self.memory.fetch_instruction(pc)
+    }
+
+    fn execute(&mut self, operation: u16) -> Result<(), Chip8Error> {
+        let instruction = decode_for(operation, self.config.mode);
+        let quirks = self.config.quirks;
+        // Address of the instruction following this one
+        let next = self.cpu.get_program_counter();
+        match instruction {
+            Instruction::Nop => {}
+            Instruction::Clear => self.display.op_cls(),
+            Instruction::Return => self.cpu.op_ret()?,
+            Instruction::ScrollDown(n) => self.display.op_scroll_down(n.into()),
+            Instruction::ScrollUp(n) => self.display.op_scroll_up(n.into()),
+            Instruction::ScrollRight => self.display.op_scroll_right(),
+            Instruction::ScrollLeft => self.display.op_scroll_left(),
+            Instruction::Exit => self.halted = true,
+            Instruction::Lores => self.display.op_set_hires(false),
+            Instruction::Hires => self.display.op_set_hires(true),
+            // Machine code routines are ignored by modern interpreters
+            Instruction::Sys(_) => {}
+            Instruction::Jump(_) => self.cpu.op_jmp(operation),
+            Instruction::Call(_) => self.cpu.op_call(operation)?,
+            Instruction::SkipEqImm { x, .. } => self.cpu.op_se(operation, x.into()),
+            Instruction::SkipNeImm { x, .. } => self.cpu.op_sne(operation, x.into()),
+            Instruction::SkipEq { x, y } => self.cpu.op_reg_se(x.into(), y.into()),
+            Instruction::SaveRange { x, y } => self.memory.op_str_range(&self.cpu, x.into(), y.into())?,
+            Instruction::LoadRange { x, y } => self.memory.op_ld_range(&mut self.cpu, x.into(), y.into())?,
+            Instruction::SetImm { x, .. } => self.cpu.op_ld(operation, x.into()),
+            Instruction::AddImm { x, .. } => self.cpu.op_add(operation, x.into()),
+            Instruction::Set { x, y } => self.cpu.op_reg_ld(x.into(), y.into()),
+            Instruction::Or { x, y } => self.cpu.op_reg_or(x.into(), y.into(), quirks.vf_reset),
+            Instruction::And { x, y } => self.cpu.op_reg_and(x.into(), y.into(), quirks.vf_reset),
+            Instruction::Xor { x, y } => self.cpu.op_reg_xor(x.into(), y.into(), quirks.vf_reset),
+            Instruction::Add { x, y } => self.cpu.op_reg_add(x.into(), y.into()),
+            Instruction::Sub { x, y } => self.cpu.op_reg_sub(x.into(), y.into(), false),
+            Instruction::ShiftRight { x, y } => self.cpu.op_shift(x.into(), y.into(), true, quirks.shift_uses_vy),
+            Instruction::SubReverse { x, y } => self.cpu.op_reg_sub(x.into(), y.into(), true),
+            Instruction::ShiftLeft { x, y } => self.cpu.op_shift(x.into(), y.into(), false, quirks.shift_uses_vy),
+            Instruction::SkipNe { x, y } => self.cpu.op_reg_sne(x.into(), y.into()),
+            Instruction::SetI(_) => self.cpu.op_i_ld(operation),
+            Instruction::JumpOffset(_) => self.cpu.op_reg_jmp(operation, quirks.jump_uses_vx),
+            Instruction::Random { x, .. } => self.cpu.op_rnd(self.rng.as_mut(), operation, x.into()),
+            Instruction::Draw { x, y, n } => {
+                let x_coord = self.cpu.get_register_value(x.into()).into();
+                let y_coord = self.cpu.get_register_value(y.into()).into();
+                if n == 0 && self.config.mode != Mode::Chip8 {
+                    // 16x16 sprite
+                    self.display.op_drw_large(&mut self.cpu, &self.memory, x_coord, y_coord, quirks.clip_sprites)?;
+                } else {
+                    self.display.op_drw(&mut self.cpu, &self.memory, x_coord, y_coord, n.into(), quirks.clip_sprites)?;
+                }
+                self.vblank_wait = quirks.display_wait;
+            }
+            Instruction::SkipKey(x) => self.input.op_skp(&mut self.cpu, x.into(), false),
+            Instruction::SkipNotKey(x) => self.input.op_skp(&mut self.cpu, x.into(), true),
+            Instruction::SetILong => {
+                let addr = self.fetch()?;
+                self.cpu.set_i_register(addr);
+            }
+            Instruction::Plane(n) => self.display.op_select_planes(n),
+            Instruction::Audio => self.memory.op_ld_audio(&self.cpu)?,
+            Instruction::GetDelay(x) => self.cpu.op_ld_dt(&self.memory, x.into()),
+            // Blocks until a key is pressed, or pressed and released
+            Instruction::WaitKey(x) => self.input.op_ld_wait(&mut self.cpu, x.into(), quirks.key_wait_release),
+            Instruction::SetDelay(x) => self.memory.op_ld_dt(&self.cpu, x.into()),
+            Instruction::SetSound(x) => self.memory.op_ld_st(&self.cpu, x.into()),
+            Instruction::AddI(x) => self.cpu.op_add_i(x.into()),
+            Instruction::Font(x) => self.cpu.op_ld_font(x.into()),
+            Instruction::BigFont(x) => self.cpu.op_ld_big_font(x.into()),
+            Instruction::Pitch(x) => self.memory.op_ld_pitch(&self.cpu, x.into()),
+            Instruction::Bcd(x) => self.memory.op_ld_bcd(&self.cpu, x.into())?,
+            Instruction::Store(x) => self.memory.op_str(&mut self.cpu, x.into(), quirks.load_store_increments_i)?,
+            Instruction::Load(x) => self.memory.op_ld(&mut self.cpu, x.into(), quirks.load_store_increments_i)?,
+            Instruction::StoreFlags(x) => self.cpu.op_str_flags(x.into()),
+            Instruction::LoadFlags(x) => self.cpu.op_ld_flags(x.into()),
+            Instruction::Invalid(_) => {
+                let pc = self.cpu.get_program_counter().wrapping_sub(2);
+                return Err(Chip8Error::InvalidOpcode { pc, opcode: operation });
+            }
         }
+        // XO-CHIP skips step over the whole four byte LD I = NNNN instruction
+        let skipped = self.cpu.get_program_counter() == next.wrapping_add(2);
+        if self.config.mode == Mode::XoChip
+            && instruction.is_skip()
+            && skipped
+            && decode(self.memory.fetch_word(next)?) == Instruction::SetILong
+        {
+            self.cpu.set_program_counter(next.wrapping_add(4));
+        }
+        Ok(())
     }
 
-    pub fn tick(&mut self) {
-        let operation = self.fetch();
-        self.execute(operation);
+    /// Fetches and executes a single instruction.
+    ///
+    /// Faults are handled according to the configured fault policy. When
+    /// halting, the program counter is left on the faulting instruction.
+    pub fn tick(&mut self) -> Result<StepOutcome, Chip8Error> {
+        if self.halted {
+            return Ok(StepOutcome::Halted);
+        }
+        if self.vblank_wait {
+            return Ok(StepOutcome::WaitingForVblank);
+        }
+        let pc = self.cpu.get_program_counter();
+        if let Some(tracer) = &mut self.tracer {
+            let (cpu, memory) = (&self.cpu, &self.memory);
+            let record = |cycle| {
+                let opcode = memory.fetch_word(pc).unwrap_or_default();
+                TraceRecord::new(cycle, opcode, &cpu.get_state(), memory.get_delay_timer(), memory.get_sound_timer())
+            };
+            tracer.trace(record, pc, self.config.mode);
+        }
+        match self.fetch().and_then(|operation| self.execute(operation)) {
+            Ok(()) if self.halted => Ok(StepOutcome::Exited),
+            Ok(()) if self.input.is_waiting_for_key() => Ok(StepOutcome::WaitingForKey),
+            Ok(()) => Ok(StepOutcome::Executed),
+            Err(error) => match self.config.fault_policy {
+                FaultPolicy::Halt => {
+                    self.halted = true;
+                    self.cpu.set_program_counter(pc);
+                    Err(error)
+                }
+                FaultPolicy::Skip | FaultPolicy::Wrap => Ok(StepOutcome::Skipped(error)),
+            },
+        }
+    }
+
+    /// Runs one 60 Hz frame: the configured number of instructions followed
+    /// by a single tick of the delay and sound timers.
+    ///
+    /// A halted machine executes nothing and its timers stay frozen.
+    pub fn run_frame(&mut self) -> Result<(), Chip8Error> {
+        for _ in 0..self.config.instructions_per_frame {
+            match self.tick()? {
+                StepOutcome::Halted => return Ok(()),
+                StepOutcome::WaitingForVblank | StepOutcome::WaitingForKey => break,
+                _ => {}
+            }
+        }
+        self.end_frame();
+        Ok(())
+    }
+
+    /// Finishes a frame of instructions run with `tick`: steps the timers,
+    /// ends the wait for vblank and records rewind history.
+    pub fn end_frame(&mut self) {
+        self.memory.tick_timers();
+        self.vblank_wait = false;
+        self.frame_count += 1;
+        if self.rewind.as_mut().is_some_and(|buffer| buffer.frame_finished()) {
+            let snapshot = self.save_state();
+            self.rewind.as_mut().unwrap().push(snapshot);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests;
+
+impl Default for Emulator {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 impl EmulatorComponent for Emulator {
     /// Resets the emulator to the initial starting state.
     fn reset(&mut self) {
-        self.cpu.reset();
         self.memory.reset();
-        self.display.reset();
-        self.input.reset();
+        self.reset_machine();
     }
 }
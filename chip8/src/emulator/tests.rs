@@ -0,0 +1,890 @@
+use super::*;
+use crate::cpu::START_ADDRESS;
+use crate::display::BIG_FONT_ADDRESS;
+use crate::quirks::Quirks;
+use crate::rewind::RewindConfig;
+use crate::rng::VipRng;
+
+/// Loads the opcodes as a ROM at the start address and executes `steps` instructions
+fn run(program: &[u16], steps: usize) -> Emulator {
+    run_with(Config::default(), program, steps)
+}
+
+fn run_with(config: Config, program: &[u16], steps: usize) -> Emulator {
+    let mut emulator = Emulator::with_config(config);
+    load(&mut emulator, program);
+    for _ in 0..steps {
+        emulator.tick().unwrap();
+    }
+    emulator
+}
+
+/// Default configuration without the display wait, so draws can be stepped through
+fn no_display_wait() -> Config {
+    let mut config = Config::default();
+    config.quirks.display_wait = false;
+    config
+}
+
+fn schip() -> Config {
+    Config::for_mode(Mode::SuperChip)
+}
+
+fn xochip() -> Config {
+    Config::for_mode(Mode::XoChip)
+}
+
+fn load(emulator: &mut Emulator, program: &[u16]) {
+    let rom: Vec<u8> = program.iter().flat_map(|op| op.to_be_bytes()).collect();
+    emulator.load_rom(&rom).unwrap();
+}
+
+fn v(emulator: &Emulator, x: usize) -> u8 {
+    emulator.get_cpu().get_register_value(x)
+}
+
+fn pc(emulator: &Emulator) -> u16 {
+    emulator.get_cpu().get_program_counter()
+}
+
+#[test]
+fn nop_and_sys_are_ignored() {
+    let emulator = run(&[0x0000, 0x0123], 2);
+    assert_eq!(pc(&emulator), 0x204);
+}
+
+#[test]
+fn cls_clears_screen() {
+    // Draw font glyph 0 then clear
+    let mut emulator = run_with(no_display_wait(), &[0xA000, 0xD005, 0x00E0], 2);
+    assert!(emulator.get_display().get_pixel(0, 0));
+    emulator.tick().unwrap();
+    assert!(!emulator.get_display().get_pixel(0, 0));
+}
+
+#[test]
+fn call_and_ret() {
+    // 0x200: CALL 0x206, 0x202: LD V0 = 1, 0x204: JMP 0x204, 0x206: RET
+    let mut emulator = run(&[0x2206, 0x6001, 0x1204, 0x00EE], 1);
+    assert_eq!(pc(&emulator), 0x206);
+    emulator.tick().unwrap();
+    assert_eq!(pc(&emulator), 0x202);
+    emulator.tick().unwrap();
+    assert_eq!(v(&emulator, 0), 1);
+}
+
+#[test]
+fn jmp() {
+    let emulator = run(&[0x1ABC], 1);
+    assert_eq!(pc(&emulator), 0xABC);
+}
+
+#[test]
+fn se_immediate() {
+    let emulator = run(&[0x6012, 0x3012], 2);
+    assert_eq!(pc(&emulator), 0x206);
+    let emulator = run(&[0x6012, 0x3013], 2);
+    assert_eq!(pc(&emulator), 0x204);
+}
+
+#[test]
+fn sne_immediate() {
+    let emulator = run(&[0x6012, 0x4013], 2);
+    assert_eq!(pc(&emulator), 0x206);
+    let emulator = run(&[0x6012, 0x4012], 2);
+    assert_eq!(pc(&emulator), 0x204);
+}
+
+#[test]
+fn se_register() {
+    let emulator = run(&[0x6005, 0x6105, 0x5010], 3);
+    assert_eq!(pc(&emulator), 0x208);
+    let emulator = run(&[0x6005, 0x6106, 0x5010], 3);
+    assert_eq!(pc(&emulator), 0x206);
+}
+
+#[test]
+fn sne_register() {
+    let emulator = run(&[0x6005, 0x6106, 0x9010], 3);
+    assert_eq!(pc(&emulator), 0x208);
+    let emulator = run(&[0x6005, 0x6105, 0x9010], 3);
+    assert_eq!(pc(&emulator), 0x206);
+}
+
+#[test]
+fn ld_immediate() {
+    let emulator = run(&[0x6A42], 1);
+    assert_eq!(v(&emulator, 0xA), 0x42);
+}
+
+#[test]
+fn add_immediate_wraps_without_carry() {
+    let emulator = run(&[0x60FF, 0x6F07, 0x7002], 3);
+    assert_eq!(v(&emulator, 0), 0x01);
+    assert_eq!(v(&emulator, 0xF), 0x07);
+}
+
+#[test]
+fn ld_register() {
+    let emulator = run(&[0x6133, 0x8010], 2);
+    assert_eq!(v(&emulator, 0), 0x33);
+}
+
+#[test]
+fn or_register() {
+    let emulator = run(&[0x600C, 0x610A, 0x8011], 3);
+    assert_eq!(v(&emulator, 0), 0x0E);
+}
+
+#[test]
+fn and_register() {
+    let emulator = run(&[0x600C, 0x610A, 0x8012], 3);
+    assert_eq!(v(&emulator, 0), 0x08);
+}
+
+#[test]
+fn xor_register() {
+    let emulator = run(&[0x600C, 0x610A, 0x8013], 3);
+    assert_eq!(v(&emulator, 0), 0x06);
+}
+
+#[test]
+fn add_register_sets_carry() {
+    let emulator = run(&[0x60F0, 0x6120, 0x8014], 3);
+    assert_eq!(v(&emulator, 0), 0x10);
+    assert_eq!(v(&emulator, 0xF), 1);
+    let emulator = run(&[0x6010, 0x6120, 0x8014], 3);
+    assert_eq!(v(&emulator, 0), 0x30);
+    assert_eq!(v(&emulator, 0xF), 0);
+}
+
+#[test]
+fn sub_register_sets_not_borrow() {
+    let emulator = run(&[0x6030, 0x6110, 0x8015], 3);
+    assert_eq!(v(&emulator, 0), 0x20);
+    assert_eq!(v(&emulator, 0xF), 1);
+    let emulator = run(&[0x6010, 0x6130, 0x8015], 3);
+    assert_eq!(v(&emulator, 0), 0xE0);
+    assert_eq!(v(&emulator, 0xF), 0);
+}
+
+#[test]
+fn subn_register_sets_not_borrow() {
+    let emulator = run(&[0x6010, 0x6130, 0x8017], 3);
+    assert_eq!(v(&emulator, 0), 0x20);
+    assert_eq!(v(&emulator, 0xF), 1);
+    let emulator = run(&[0x6030, 0x6110, 0x8017], 3);
+    assert_eq!(v(&emulator, 0), 0xE0);
+    assert_eq!(v(&emulator, 0xF), 0);
+}
+
+#[test]
+fn shr_sets_shifted_out_bit() {
+    let emulator = run(&[0x6105, 0x8016], 2);
+    assert_eq!(v(&emulator, 0), 0x02);
+    assert_eq!(v(&emulator, 0xF), 1);
+}
+
+#[test]
+fn shl_sets_shifted_out_bit() {
+    let emulator = run(&[0x6181, 0x801E], 2);
+    assert_eq!(v(&emulator, 0), 0x02);
+    assert_eq!(v(&emulator, 0xF), 1);
+}
+
+#[test]
+fn shift_in_place_quirk() {
+    let config = Config { quirks: Quirks::CHIP_48, ..Config::default() };
+    let emulator = run_with(config, &[0x6005, 0x6180, 0x8016], 3);
+    assert_eq!(v(&emulator, 0), 0x02);
+    assert_eq!(v(&emulator, 1), 0x80);
+    assert_eq!(v(&emulator, 0xF), 1);
+}
+
+#[test]
+fn logic_ops_reset_vf_quirk() {
+    let emulator = run(&[0x6F05, 0x8011], 2);
+    assert_eq!(v(&emulator, 0xF), 0);
+
+    let config = Config { quirks: Quirks::SCHIP_1_1, ..Config::default() };
+    let emulator = run_with(config, &[0x6F05, 0x8011], 2);
+    assert_eq!(v(&emulator, 0xF), 5);
+}
+
+#[test]
+fn flag_result_wins_over_vf_operand() {
+    let emulator = run(&[0x6FFF, 0x6101, 0x8F14], 3);
+    assert_eq!(v(&emulator, 0xF), 1);
+}
+
+#[test]
+fn ld_i() {
+    let emulator = run(&[0xA123], 1);
+    assert_eq!(emulator.get_cpu().get_i_register(), 0x123);
+}
+
+#[test]
+fn jmp_v0() {
+    let emulator = run(&[0x6004, 0xB300], 2);
+    assert_eq!(pc(&emulator), 0x304);
+}
+
+#[test]
+fn jmp_vx_quirk() {
+    let config = Config { quirks: Quirks::SCHIP_1_1, ..Config::default() };
+    let emulator = run_with(config, &[0x6004, 0x6310, 0xB300], 3);
+    assert_eq!(pc(&emulator), 0x310);
+}
+
+#[test]
+fn rnd_is_masked() {
+    for _ in 0..32 {
+        let emulator = run(&[0xC00F], 1);
+        assert_eq!(v(&emulator, 0) & 0xF0, 0);
+    }
+}
+
+#[test]
+fn drw_draws_and_reports_collision() {
+    // Draw the "0" glyph at (1, 2)
+    let emulator = run(&[0x6001, 0x6102, 0xA000, 0xD015], 4);
+    assert!(emulator.get_display().get_pixel(1, 2));
+    assert!(emulator.get_display().get_pixel(4, 2));
+    assert!(!emulator.get_display().get_pixel(5, 2));
+    assert!(!emulator.get_display().get_pixel(2, 3));
+    assert_eq!(v(&emulator, 0xF), 0);
+
+    // Drawing it again erases it and flags the collision
+    let emulator = run_with(no_display_wait(), &[0x6001, 0x6102, 0xA000, 0xD015, 0xD015], 5);
+    assert!(!emulator.get_display().get_pixel(1, 2));
+    assert_eq!(v(&emulator, 0xF), 1);
+}
+
+#[test]
+fn drw_clips_or_wraps_at_edges() {
+    // Draw the "0" glyph at (62, 30) so it hangs off the right and bottom edges
+    let program = [0x603E, 0x611E, 0xA000, 0xD015];
+    let emulator = run(&program, 4);
+    assert!(emulator.get_display().get_pixel(62, 30));
+    assert!(!emulator.get_display().get_pixel(0, 30));
+    assert!(!emulator.get_display().get_pixel(62, 0));
+
+    let config = Config { quirks: Quirks::XO_CHIP, ..Config::default() };
+    let emulator = run_with(config, &program, 4);
+    assert!(emulator.get_display().get_pixel(0, 30));
+    assert!(emulator.get_display().get_pixel(62, 0));
+}
+
+#[test]
+fn drw_starting_position_wraps() {
+    let emulator = run(&[0x6041, 0x6122, 0xA000, 0xD015], 4);
+    assert!(emulator.get_display().get_pixel(1, 2));
+}
+
+#[test]
+fn display_wait_stalls_until_next_frame() {
+    let mut emulator = run(&[0xA000, 0xD005, 0x6001], 2);
+    assert!(matches!(emulator.tick(), Ok(StepOutcome::WaitingForVblank)));
+    assert_eq!(v(&emulator, 0), 0);
+
+    emulator.run_frame().unwrap();
+    assert_eq!(v(&emulator, 0), 0);
+    emulator.run_frame().unwrap();
+    assert_eq!(v(&emulator, 0), 1);
+}
+
+#[test]
+fn skp_key_pressed() {
+    let mut emulator = Emulator::new();
+    load(&mut emulator, &[0x6007, 0xE09E]);
+    emulator.key_down(7);
+    emulator.tick().unwrap();
+    emulator.tick().unwrap();
+    assert_eq!(pc(&emulator), 0x206);
+}
+
+#[test]
+fn sknp_key_not_pressed() {
+    let emulator = run(&[0x6007, 0xE0A1], 2);
+    assert_eq!(pc(&emulator), 0x206);
+
+    let mut emulator = Emulator::new();
+    load(&mut emulator, &[0x6007, 0xE0A1]);
+    emulator.key_down(7);
+    emulator.tick().unwrap();
+    emulator.tick().unwrap();
+    assert_eq!(pc(&emulator), 0x204);
+}
+
+#[test]
+fn ld_delay_timer() {
+    let emulator = run(&[0x6033, 0xF015, 0xF107], 3);
+    assert_eq!(emulator.get_memory().get_delay_timer(), 0x33);
+    assert_eq!(v(&emulator, 1), 0x33);
+}
+
+#[test]
+fn ld_sound_timer() {
+    let emulator = run(&[0x6044, 0xF018], 2);
+    assert_eq!(emulator.get_memory().get_sound_timer(), 0x44);
+}
+
+#[test]
+fn ld_wait_for_key() {
+    let mut emulator = run(&[0xF30A], 3);
+    assert_eq!(pc(&emulator), 0x200);
+    assert!(emulator.is_waiting_for_key());
+
+    // Completes on release, not on press
+    emulator.key_down(0xB);
+    assert!(matches!(emulator.tick().unwrap(), StepOutcome::WaitingForKey));
+    assert_eq!(pc(&emulator), 0x200);
+
+    emulator.key_up(0xB);
+    assert!(matches!(emulator.tick().unwrap(), StepOutcome::Executed));
+    assert_eq!(pc(&emulator), 0x202);
+    assert_eq!(v(&emulator, 3), 0xB);
+    assert!(!emulator.is_waiting_for_key());
+}
+
+#[test]
+fn ld_wait_for_key_on_press() {
+    let config = Config {
+        quirks: Quirks { key_wait_release: false, ..Quirks::default() },
+        ..Config::default()
+    };
+    let mut emulator = run_with(config, &[0xF30A], 2);
+    emulator.key_down(0xB);
+    emulator.tick().unwrap();
+    assert_eq!(pc(&emulator), 0x202);
+    assert_eq!(v(&emulator, 3), 0xB);
+}
+
+#[test]
+fn ld_wait_for_key_between_ticks() {
+    // A tap shorter than a frame still completes the wait
+    let mut emulator = run(&[0xF30A], 1);
+    emulator.key_down(0x4);
+    emulator.key_up(0x4);
+    emulator.tick().unwrap();
+    assert_eq!(pc(&emulator), 0x202);
+    assert_eq!(v(&emulator, 3), 0x4);
+}
+
+#[test]
+fn set_keypad_bitmask() {
+    let mut emulator = Emulator::new();
+    emulator.set_keypad(0b1000_0000_0000_0101);
+    let input = emulator.get_input();
+    let pressed: Vec<usize> = (0..16).filter(|&key| input.is_key_pressed(key)).collect();
+    assert_eq!(pressed, [0, 2, 15]);
+}
+
+#[test]
+fn run_frame_idles_on_key_wait() {
+    let mut emulator = Emulator::new();
+    load(&mut emulator, &[0xF30A]);
+    emulator.run_frame().unwrap();
+    assert!(emulator.is_waiting_for_key());
+    assert_eq!(pc(&emulator), 0x200);
+}
+
+#[test]
+fn add_i() {
+    let emulator = run(&[0xA100, 0x6020, 0xF01E], 3);
+    assert_eq!(emulator.get_cpu().get_i_register(), 0x120);
+}
+
+#[test]
+fn ld_font() {
+    let emulator = run(&[0x600A, 0xF029], 2);
+    assert_eq!(emulator.get_cpu().get_i_register(), 50);
+}
+
+#[test]
+fn bcd() {
+    let emulator = run(&[0x60FE, 0xA300, 0xF033], 3);
+    let memory = emulator.get_memory();
+    assert_eq!(memory.fetch_byte(0x300).unwrap(), 2);
+    assert_eq!(memory.fetch_byte(0x301).unwrap(), 5);
+    assert_eq!(memory.fetch_byte(0x302).unwrap(), 4);
+}
+
+#[test]
+fn store_registers() {
+    let emulator = run(&[0x6011, 0x6122, 0x6233, 0xA300, 0xF155], 5);
+    let memory = emulator.get_memory();
+    assert_eq!(memory.fetch_byte(0x300).unwrap(), 0x11);
+    assert_eq!(memory.fetch_byte(0x301).unwrap(), 0x22);
+    assert_eq!(memory.fetch_byte(0x302).unwrap(), 0x00);
+    assert_eq!(emulator.get_cpu().get_i_register(), 0x302);
+}
+
+#[test]
+fn load_store_without_increment_quirk() {
+    let config = Config { quirks: Quirks::SCHIP_1_1, ..Config::default() };
+    let emulator = run_with(config, &[0xA300, 0xF155, 0xF165], 3);
+    assert_eq!(emulator.get_cpu().get_i_register(), 0x300);
+}
+
+#[test]
+fn load_registers() {
+    // Read the first two bytes of the "0" glyph
+    let emulator = run(&[0x6277, 0xA000, 0xF165], 3);
+    assert_eq!(v(&emulator, 0), 0xF0);
+    assert_eq!(v(&emulator, 1), 0x90);
+    assert_eq!(v(&emulator, 2), 0x77);
+}
+
+#[test]
+fn load_rom_rejects_oversized_rom() {
+    let mut emulator = run(&[0x6007, 0x1202], 1);
+    let rom_info = emulator.get_rom_info().copied();
+    assert!(rom_info.is_some());
+    let rom = vec![0; emulator.get_memory().max_program_size() + 1];
+    assert!(matches!(emulator.load_rom(&rom), Err(Chip8Error::RomTooLarge { .. })));
+    // The running program is left as it was
+    assert_eq!(emulator.get_rom_info().copied(), rom_info);
+    assert_eq!(emulator.get_cpu_state().v_registers[0], 7);
+    assert_eq!(pc(&emulator), 0x202);
+    assert_eq!(emulator.get_memory().fetch_word(0x200).unwrap(), 0x6007);
+}
+
+#[test]
+fn load_rom_reader_reads_to_the_end() {
+    let rom = [0x60, 0x2A, 0x12, 0x02];
+    let mut emulator = Emulator::new();
+    emulator.load_rom_reader(&rom[..]).unwrap();
+    assert_eq!(emulator.get_rom_info().copied(), Some(RomInfo::new(&rom)));
+    assert_eq!(&emulator.get_ram()[0x200..0x204], rom);
+    emulator.tick().unwrap();
+    assert_eq!(v(&emulator, 0), 0x2A);
+}
+
+#[test]
+fn load_rom_file_reports_missing_files() {
+    let mut emulator = Emulator::new();
+    let err = emulator.load_rom_file("/nonexistent/rom.ch8").unwrap_err();
+    assert!(matches!(&err, Chip8Error::Io(io) if io.kind() == std::io::ErrorKind::NotFound), "{err}");
+    assert!(emulator.get_rom_info().is_none());
+}
+
+#[test]
+fn invalid_opcode_halts() {
+    let mut emulator = run(&[0x6001, 0x8008], 1);
+    let error = emulator.tick().unwrap_err();
+    assert!(matches!(error, Chip8Error::InvalidOpcode { pc: 0x202, opcode: 0x8008 }));
+    assert!(emulator.is_halted());
+    assert_eq!(pc(&emulator), 0x202);
+    assert!(matches!(emulator.tick(), Ok(StepOutcome::Halted)));
+}
+
+#[test]
+fn stack_overflow() {
+    let mut emulator = run(&[0x2200], 16);
+    assert!(matches!(emulator.tick(), Err(Chip8Error::StackOverflow)));
+}
+
+#[test]
+fn stack_underflow() {
+    let mut emulator = run(&[0x00EE], 0);
+    assert!(matches!(emulator.tick(), Err(Chip8Error::StackUnderflow)));
+}
+
+#[test]
+fn fetch_past_end_of_memory() {
+    let mut emulator = run(&[0x1FFF], 1);
+    assert!(matches!(emulator.tick(), Err(Chip8Error::MemoryOutOfBounds { addr: 0x1000 })));
+}
+
+#[test]
+fn store_past_end_of_memory_leaves_memory_untouched() {
+    let mut emulator = run(&[0x6011, 0xAFFF, 0xF155], 2);
+    assert!(matches!(emulator.tick(), Err(Chip8Error::MemoryOutOfBounds { addr: 0x1000 })));
+    assert_eq!(emulator.get_memory().fetch_byte(0xFFF).unwrap(), 0);
+}
+
+#[test]
+fn skip_policy_continues_after_fault() {
+    let config = Config { fault_policy: FaultPolicy::Skip, ..Config::default() };
+    let mut emulator = run_with(config, &[0x8008, 0x6001], 0);
+    assert!(matches!(emulator.tick(), Ok(StepOutcome::Skipped(Chip8Error::InvalidOpcode { .. }))));
+    assert!(matches!(emulator.tick(), Ok(StepOutcome::Executed)));
+    assert_eq!(v(&emulator, 0), 1);
+}
+
+#[test]
+fn wrap_policy_wraps_memory_and_stack() {
+    let config = Config { fault_policy: FaultPolicy::Wrap, ..Config::default() };
+    let emulator = run_with(config.clone(), &[0x6011, 0x6122, 0xAFFF, 0xF155], 4);
+    assert_eq!(emulator.get_memory().fetch_byte(0xFFF).unwrap(), 0x11);
+    assert_eq!(emulator.get_memory().fetch_byte(0x000).unwrap(), 0x22);
+
+    let emulator = run_with(config, &[0x00EE], 1);
+    assert!(!emulator.is_halted());
+    assert_eq!(pc(&emulator), 0);
+}
+
+#[test]
+fn run_frame_executes_instructions_then_ticks_timers() {
+    let config = Config { instructions_per_frame: 3, ..Config::default() };
+    // LD V0 = 2, LD DT = V0, LD ST = V0, then count up in V1
+    let mut emulator = run_with(config, &[0x6002, 0xF015, 0xF018, 0x7101, 0x7101, 0x7101], 0);
+    emulator.run_frame().unwrap();
+    assert_eq!(emulator.get_memory().get_delay_timer(), 1);
+    assert!(emulator.is_sound_active());
+    assert_eq!(v(&emulator, 1), 0);
+
+    emulator.run_frame().unwrap();
+    assert_eq!(emulator.get_memory().get_delay_timer(), 0);
+    assert!(!emulator.is_sound_active());
+    assert_eq!(v(&emulator, 1), 3);
+    assert_eq!(emulator.get_frame_count(), 2);
+}
+
+#[test]
+fn halted_machine_freezes_timers() {
+    let mut emulator = run(&[0x6005, 0xF015, 0x8008], 0);
+    assert!(emulator.run_frame().is_err());
+    emulator.run_frame().unwrap();
+    assert_eq!(emulator.get_memory().get_delay_timer(), 5);
+    assert_eq!(emulator.get_frame_count(), 0);
+}
+
+#[test]
+fn schip_resolution_switch() {
+    let emulator = run_with(schip(), &[0x00FF], 1);
+    assert!(emulator.get_display().is_hires());
+    assert_eq!(emulator.get_display().get_width(), 128);
+    assert_eq!(emulator.get_display().get_height(), 64);
+
+    let emulator = run_with(schip(), &[0x00FF, 0x00FE], 2);
+    assert!(!emulator.get_display().is_hires());
+    assert_eq!(emulator.get_display().get_width(), 64);
+}
+
+#[test]
+fn schip_scrolling() {
+    // Draw the "1" glyph top row (0x20: single pixel at x = 2) at the origin
+    let program = [0x00FF, 0xA005, 0xD001];
+    let mut down = program.to_vec();
+    down.push(0x00C3);
+    let emulator = run_with(schip(), &down, 4);
+    assert!(!emulator.get_display().get_pixel(2, 0));
+    assert!(emulator.get_display().get_pixel(2, 3));
+
+    let mut right = program.to_vec();
+    right.push(0x00FB);
+    let emulator = run_with(schip(), &right, 4);
+    assert!(emulator.get_display().get_pixel(6, 0));
+
+    let mut left = program.to_vec();
+    left.extend([0x00FB, 0x00FC, 0x00FC]);
+    let emulator = run_with(schip(), &left, 6);
+    assert!(!emulator.get_display().get_pixel(2, 0));
+    assert!(!emulator.get_display().get_pixel(6, 0));
+}
+
+#[test]
+fn schip_large_sprite() {
+    // Sprite data is the first 32 bytes of the big font
+    let emulator = run_with(schip(), &[0x00FF, 0xA050, 0xD000], 3);
+    let display = emulator.get_display();
+    // Row 0 is 0x3C7E: pixels 2-5 and 9-14 lit
+    assert!(!display.get_pixel(1, 0));
+    assert!(display.get_pixel(2, 0));
+    assert!(display.get_pixel(9, 0));
+    assert!(display.get_pixel(14, 0));
+    assert!(!display.get_pixel(15, 0));
+    assert_eq!(v(&emulator, 0xF), 0);
+}
+
+#[test]
+fn schip_big_font() {
+    let emulator = run_with(schip(), &[0x6003, 0xF030], 2);
+    assert_eq!(emulator.get_cpu().get_i_register() as usize, BIG_FONT_ADDRESS + 30);
+}
+
+#[test]
+fn schip_flag_registers() {
+    let emulator = run_with(schip(), &[0x6011, 0x6122, 0xF175, 0x6000, 0x6100, 0xF185], 6);
+    assert_eq!(v(&emulator, 0), 0x11);
+    assert_eq!(v(&emulator, 1), 0x22);
+}
+
+#[test]
+fn schip_exit() {
+    let mut emulator = run_with(schip(), &[0x00FD], 0);
+    assert!(matches!(emulator.tick(), Ok(StepOutcome::Exited)));
+    assert!(emulator.is_halted());
+}
+
+#[test]
+fn schip_opcodes_need_schip_mode() {
+    let mut emulator = run(&[0xF075], 0);
+    assert!(matches!(emulator.tick(), Err(Chip8Error::InvalidOpcode { .. })));
+
+    let emulator = run(&[0xA050, 0xD000], 2);
+    assert!(!emulator.get_display().get_pixel(2, 0));
+}
+
+#[test]
+fn xochip_has_64k_of_memory() {
+    let mut emulator = Emulator::with_config(xochip());
+    let rom = vec![0; 0x8000];
+    emulator.load_rom(&rom).unwrap();
+    assert_eq!(emulator.get_memory().max_program_size(), 0x10000 - 0x200);
+}
+
+#[test]
+fn xochip_long_load() {
+    let emulator = run_with(xochip(), &[0xF000, 0xBEEF, 0x6001], 2);
+    assert_eq!(emulator.get_cpu().get_i_register(), 0xBEEF);
+    assert_eq!(v(&emulator, 0), 1);
+}
+
+#[test]
+fn xochip_skip_steps_over_long_load() {
+    let emulator = run_with(xochip(), &[0x3000, 0xF000, 0xBEEF, 0x6001], 2);
+    assert_eq!(emulator.get_cpu().get_i_register(), 0);
+    assert_eq!(v(&emulator, 0), 1);
+}
+
+#[test]
+fn xochip_register_range_save_and_load() {
+    let emulator = run_with(xochip(), &[0x6111, 0x6222, 0x6333, 0xA300, 0x5132, 0x5312], 5);
+    let memory = emulator.get_memory();
+    assert_eq!(memory.fetch_byte(0x300).unwrap(), 0x11);
+    assert_eq!(memory.fetch_byte(0x302).unwrap(), 0x33);
+
+    // Loading in reverse order swaps V1 and V3
+    let emulator = run_with(xochip(), &[0x6111, 0x6222, 0x6333, 0xA300, 0x5132, 0x5313], 6);
+    assert_eq!(v(&emulator, 1), 0x33);
+    assert_eq!(v(&emulator, 3), 0x11);
+    assert_eq!(emulator.get_cpu().get_i_register(), 0x300);
+}
+
+#[test]
+fn xochip_bitplanes() {
+    // Select both planes and draw glyphs "0" and "1" as plane 1 and plane 2 data
+    let emulator = run_with(xochip(), &[0xF301, 0xA000, 0xD005], 3);
+    let display = emulator.get_display();
+    // "0" row 0 is 0xF0 and "1" row 0 is 0x20
+    assert_eq!(display.get_pixel_planes(0, 0), 1);
+    assert_eq!(display.get_pixel_planes(2, 0), 3);
+    assert_eq!(display.get_pixel_planes(4, 0), 0);
+
+    // Clearing only plane 2 keeps plane 1
+    let emulator = run_with(xochip(), &[0xF301, 0xA000, 0xD005, 0xF201, 0x00E0], 5);
+    assert_eq!(emulator.get_display().get_pixel_planes(2, 0), 1);
+}
+
+#[test]
+fn xochip_scroll_up() {
+    let emulator = run_with(xochip(), &[0x6103, 0xA005, 0xD011, 0x00D2], 4);
+    assert!(emulator.get_display().get_pixel(2, 1));
+    assert!(!emulator.get_display().get_pixel(2, 3));
+}
+
+#[test]
+fn xochip_audio() {
+    let emulator = run_with(xochip(), &[0xA000, 0xF002, 0x6070, 0xF03A], 4);
+    let memory = emulator.get_memory();
+    assert_eq!(memory.get_audio_pattern().unwrap()[..5], [0xF0, 0x90, 0x90, 0x90, 0xF0]);
+    assert_eq!(memory.get_pitch(), 0x70);
+}
+
+#[test]
+fn framebuffer_view() {
+    let emulator = run(&[0x6001, 0xA000, 0xD001], 3);
+    let framebuffer = emulator.get_framebuffer();
+    assert_eq!((framebuffer.width(), framebuffer.height()), (64, 32));
+    assert_eq!(framebuffer.as_slice().len(), 64 * 32);
+    assert_eq!(framebuffer.rows().count(), 32);
+    let lit: Vec<(usize, usize)> = framebuffer.pixels().filter(|&(_, _, pixel)| pixel != 0).map(|(x, y, _)| (x, y)).collect();
+    assert_eq!(lit, [(1, 1), (2, 1), (3, 1), (4, 1)]);
+    assert!(framebuffer.is_lit(1, 1));
+    assert!(!framebuffer.is_lit(0, 1));
+}
+
+#[test]
+fn cpu_state_snapshot() {
+    let emulator = run(&[0x6A42, 0xA123, 0x2206, 0x0000], 3);
+    let state = emulator.get_cpu_state();
+    assert_eq!(state.v_registers[0xA], 0x42);
+    assert_eq!(state.i_register, 0x123);
+    assert_eq!(state.program_counter, 0x206);
+    assert_eq!(state.stack_pointer, 1);
+    assert_eq!(state.stack[0], 0x206);
+}
+
+#[test]
+fn ram_view() {
+    let emulator = run(&[0x1234], 0);
+    let ram = emulator.get_ram();
+    assert_eq!(ram.len(), 0x1000);
+    assert_eq!(ram[0x200..0x202], [0x12, 0x34]);
+    assert_eq!(ram[..5], crate::display::FONT_SET[..5]);
+}
+
+#[test]
+fn save_state_round_trip() {
+    let program = [0x6005, 0xA000, 0xD015, 0x2208, 0x7001, 0x1208];
+    let mut emulator = run_with(no_display_wait(), &program, 4);
+    emulator.memory.op_ld_st(&emulator.cpu, 0);
+    emulator.key_down(0x3);
+    let state = emulator.save_state();
+    let cpu_state = emulator.get_cpu_state();
+    let screen = emulator.get_framebuffer().as_slice().to_vec();
+
+    for _ in 0..3 {
+        emulator.run_frame().unwrap();
+    }
+    emulator.key_up(0x3);
+    assert_ne!(emulator.get_cpu_state(), cpu_state);
+
+    emulator.load_state(&state).unwrap();
+    assert_eq!(emulator.get_cpu_state(), cpu_state);
+    assert_eq!(emulator.get_framebuffer().as_slice(), screen);
+    assert_eq!(emulator.get_memory().get_sound_timer(), 5);
+    assert!(emulator.get_input().is_key_pressed(0x3));
+    assert_eq!(emulator.get_rom_info().unwrap().len, program.len() * 2);
+    assert_eq!(emulator.save_state(), state);
+}
+
+#[test]
+fn load_state_rejects_other_mode() {
+    let state = run(&[0x6001], 1).save_state();
+    let mut emulator = run_with(schip(), &[0x6002], 1);
+    assert!(matches!(emulator.load_state(&state), Err(Chip8Error::InvalidSaveState { .. })));
+    assert_eq!(v(&emulator, 0), 2);
+}
+
+#[test]
+fn load_state_rejects_corruption() {
+    let mut state = run(&[0x6001], 1).save_state();
+    let mut emulator = run(&[0x6002], 1);
+
+    let last = state.len() - 10;
+    state[last] ^= 0x01;
+    assert!(matches!(emulator.load_state(&state), Err(Chip8Error::InvalidSaveState { .. })));
+    state[last] ^= 0x01;
+
+    state[5] += 1;
+    assert!(matches!(emulator.load_state(&state), Err(Chip8Error::InvalidSaveState { .. })));
+    state[5] -= 1;
+
+    assert!(emulator.load_state(&state[..state.len() - 1]).is_err());
+    assert!(emulator.load_state(b"not a state").is_err());
+    assert_eq!(v(&emulator, 0), 2);
+
+    emulator.load_state(&state).unwrap();
+    assert_eq!(v(&emulator, 0), 1);
+}
+
+#[test]
+fn rewind_disabled_by_default() {
+    let mut emulator = run(&[0x7001, 0x1200], 0);
+    emulator.run_frame().unwrap();
+    assert!(emulator.get_rewind_buffer().is_none());
+    assert_eq!(emulator.rewind(1).unwrap(), 0);
+}
+
+#[test]
+fn rewind_restores_earlier_frames() {
+    let config = Config { rewind: Some(RewindConfig::default()), ..Config::default() };
+    let mut emulator = Emulator::with_config(config);
+    // One increment of V0 per frame
+    load(&mut emulator, &[0x7001, 0x1200]);
+    let mut history = Vec::new();
+    for _ in 0..10 {
+        emulator.run_frame().unwrap();
+        history.push(v(&emulator, 0));
+    }
+    assert_eq!(emulator.get_rewind_buffer().unwrap().len(), 10);
+
+    assert_eq!(emulator.rewind(3).unwrap(), 3);
+    assert_eq!(v(&emulator, 0), history[6]);
+    assert_eq!(emulator.get_frame_count(), 7);
+    assert_eq!(emulator.rewind(1).unwrap(), 1);
+    assert_eq!(v(&emulator, 0), history[5]);
+
+    // Playing on records new history from the rewound point
+    emulator.run_frame().unwrap();
+    assert_eq!(v(&emulator, 0), history[6]);
+    assert_eq!(emulator.rewind(100).unwrap(), 6);
+    assert_eq!(v(&emulator, 0), history[0]);
+}
+
+#[test]
+fn rewind_history_cleared_on_load() {
+    let config = Config { rewind: Some(RewindConfig::default()), ..Config::default() };
+    let mut emulator = Emulator::with_config(config);
+    load(&mut emulator, &[0x7001, 0x1200]);
+    emulator.run_frame().unwrap();
+    load(&mut emulator, &[0x7002, 0x1200]);
+    assert!(emulator.get_rewind_buffer().unwrap().is_empty());
+}
+
+fn seeded(seed: u64) -> Config {
+    Config { seed: Some(seed), ..Config::default() }
+}
+
+/// Draws `count` random bytes through CXNN
+fn random_bytes(emulator: &mut Emulator, count: usize) -> Vec<u8> {
+    (0..count)
+        .map(|_| {
+            emulator.cpu.set_program_counter(START_ADDRESS);
+            emulator.tick().unwrap();
+            v(emulator, 0)
+        })
+        .collect()
+}
+
+#[test]
+fn rnd_is_reproducible_with_seed() {
+    let first = random_bytes(&mut run_with(seeded(1234), &[0xC0FF], 0), 32);
+    assert_eq!(random_bytes(&mut run_with(seeded(1234), &[0xC0FF], 0), 32), first);
+    assert_ne!(random_bytes(&mut run_with(seeded(4321), &[0xC0FF], 0), 32), first);
+}
+
+#[test]
+fn unseeded_config_records_seed() {
+    let mut emulator = run(&[0xC0FF], 0);
+    let seed = emulator.get_config().seed.unwrap();
+    assert_eq!(emulator.get_seed(), seed);
+    let first = random_bytes(&mut emulator, 16);
+    assert_eq!(random_bytes(&mut run_with(seeded(seed), &[0xC0FF], 0), 16), first);
+}
+
+#[test]
+fn rnd_restarts_on_reset() {
+    let mut emulator = run_with(seeded(99), &[0xC0FF], 0);
+    let first = random_bytes(&mut emulator, 8);
+    load(&mut emulator, &[0xC0FF]);
+    assert_eq!(random_bytes(&mut emulator, 8), first);
+}
+
+#[test]
+fn save_state_captures_rng() {
+    let mut emulator = run_with(seeded(5), &[0xC0FF], 0);
+    random_bytes(&mut emulator, 3);
+    let state = emulator.save_state();
+    let expected = random_bytes(&mut emulator, 8);
+    emulator.load_state(&state).unwrap();
+    assert_eq!(random_bytes(&mut emulator, 8), expected);
+
+    // States from an emulator with a different generator don't fit
+    let mut vip = run_with(seeded(5), &[0xC0FF], 0);
+    vip.set_rng(Box::new(VipRng::new([0; 256], 0)));
+    assert!(matches!(vip.load_state(&state), Err(Chip8Error::InvalidSaveState { .. })));
+}
+
+#[test]
+fn pluggable_rng() {
+    let mut code_page = [0; 256];
+    code_page[0x01] = 0x42;
+    let mut emulator = run_with(seeded(0), &[0xC0FF], 0);
+    emulator.set_rng(Box::new(VipRng::new(code_page, 0)));
+    assert_eq!(random_bytes(&mut emulator, 1), [0x42]);
+}
@@ -0,0 +1,56 @@
+use std::fmt;
+use std::io;
+
+/// Errors surfaced by the emulator core
+#[derive(Debug)]
+pub enum Chip8Error {
+    /// ROM does not fit in the program area of memory
+    RomTooLarge { size: usize, max: usize },
+    /// Underlying I/O failure while reading a ROM
+    Io(io::Error),
+    /// Opcode at the given address is not a known instruction
+    InvalidOpcode { pc: u16, opcode: u16 },
+    /// CALL with all stack slots in use
+    StackOverflow,
+    /// RET with an empty stack
+    StackUnderflow,
+    /// Access outside of the address space
+    MemoryOutOfBounds { addr: usize },
+    /// Save state is corrupt or was made by an incompatible emulator
+    InvalidSaveState { reason: &'static str },
+}
+
+impl fmt::Display for Chip8Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Chip8Error::RomTooLarge { size, max } => {
+                write!(f, "ROM is {size} bytes, but at most {max} bytes fit in memory")
+            }
+            Chip8Error::Io(err) => write!(f, "I/O error: {err}"),
+            Chip8Error::InvalidOpcode { pc, opcode } => {
+                write!(f, "invalid opcode {opcode:04X} at address {pc:03X}")
+            }
+            Chip8Error::StackOverflow => write!(f, "stack overflow"),
+            Chip8Error::StackUnderflow => write!(f, "stack underflow"),
+            Chip8Error::MemoryOutOfBounds { addr } => {
+                write!(f, "memory access out of bounds at address {addr:03X}")
+            }
+            Chip8Error::InvalidSaveState { reason } => write!(f, "invalid save state: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for Chip8Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Chip8Error::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Chip8Error {
+    fn from(err: io::Error) -> Self {
+        Chip8Error::Io(err)
+    }
+}
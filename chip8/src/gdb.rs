@@ -0,0 +1,472 @@
+//! GDB remote serial protocol stub, so standard tooling can attach to a
+//! running emulator over TCP.
+//!
+//! Registers are numbered V0 - VF (0 - 15, one byte each), I (16), PC (17)
+//! and SP (18), which are two bytes, then DT (19) and ST (20), one byte
+//! each. Two byte registers are sent big-endian like CHIP-8 memory words.
+//! The address space is the emulator's RAM.
+//!
+//! Supported: register and memory access, software breakpoints (`Z0`),
+//! write, read and access watchpoints (`Z2` - `Z4`), single-step, continue,
+//! their reverse counterparts and interrupting a continue with Ctrl-C.
+
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::TcpStream;
+use crate::cpu::NUMBER_OF_REGISTERS;
+use crate::debug::{Debugger, Stop, Watchpoint};
+use crate::error::Chip8Error;
+use crate::memory::Access;
+
+/// Instructions run between checks for an interrupt while continuing
+const RESUME_SLICE: u64 = 10_000;
+
+/// Byte a client sends to interrupt a running target
+const INTERRUPT: u8 = 0x03;
+
+/// Largest packet accepted from and sent to the client
+const PACKET_SIZE: usize = 0x1000;
+
+/// Target description, so clients know the register layout
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.chip8.core">
+    <reg name="v0" bitsize="8" regnum="0"/>
+    <reg name="v1" bitsize="8"/>
+    <reg name="v2" bitsize="8"/>
+    <reg name="v3" bitsize="8"/>
+    <reg name="v4" bitsize="8"/>
+    <reg name="v5" bitsize="8"/>
+    <reg name="v6" bitsize="8"/>
+    <reg name="v7" bitsize="8"/>
+    <reg name="v8" bitsize="8"/>
+    <reg name="v9" bitsize="8"/>
+    <reg name="va" bitsize="8"/>
+    <reg name="vb" bitsize="8"/>
+    <reg name="vc" bitsize="8"/>
+    <reg name="vd" bitsize="8"/>
+    <reg name="ve" bitsize="8"/>
+    <reg name="vf" bitsize="8"/>
+    <reg name="i" bitsize="16" type="data_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+    <reg name="sp" bitsize="16"/>
+    <reg name="dt" bitsize="8"/>
+    <reg name="st" bitsize="8"/>
+  </feature>
+</target>
+"#;
+
+/// Register number of I, the first register after V0 - VF
+const REGISTER_I: usize = NUMBER_OF_REGISTERS;
+const REGISTER_PC: usize = REGISTER_I + 1;
+const REGISTER_SP: usize = REGISTER_I + 2;
+const REGISTER_DT: usize = REGISTER_I + 3;
+const REGISTER_ST: usize = REGISTER_I + 4;
+
+/// What the session does after a packet
+#[derive(Debug, PartialEq, Eq)]
+enum Action {
+    Reply(String),
+    Step,
+    Continue,
+    ReverseStep,
+    ReverseContinue,
+    /// Client detached or killed the session
+    Close(Option<String>),
+}
+
+/// Serves one GDB client at a time over a debugger
+pub struct GdbServer {
+    debugger: Debugger,
+    /// Watchpoint numbers by packet type, address and length
+    watchpoints: HashMap<(u8, u16, u16), usize>,
+    no_ack: bool,
+}
+
+impl GdbServer {
+    /// Constructor
+    pub fn new(mut debugger: Debugger) -> Self {
+        debugger.set_instruction_limit(RESUME_SLICE);
+        Self { debugger, watchpoints: HashMap::new(), no_ack: false }
+    }
+
+    pub fn get_debugger(&self) -> &Debugger {
+        &self.debugger
+    }
+
+    pub fn into_debugger(self) -> Debugger {
+        self.debugger
+    }
+
+    /// Serves a client until it detaches, kills the session or disconnects
+    pub fn serve(&mut self, stream: TcpStream) -> io::Result<()> {
+        let mut connection = Connection { stream, buffer: VecDeque::new() };
+        self.no_ack = false;
+        while let Some(packet) = connection.read_packet(self.no_ack)? {
+            let reply = match self.handle(&packet) {
+                Action::Reply(reply) => reply,
+                Action::Step => stop_reply(self.debugger.step()),
+                Action::Continue => self.resume(&mut connection)?,
+                Action::ReverseStep => stop_reply(self.debugger.reverse_step()),
+                Action::ReverseContinue => stop_reply(self.debugger.reverse_continue()),
+                Action::Close(reply) => {
+                    if let Some(reply) = reply {
+                        connection.send(&reply)?;
+                    }
+                    return Ok(());
+                }
+            };
+            connection.send(&reply)?;
+            if packet == "QStartNoAckMode" {
+                self.no_ack = true;
+            }
+        }
+        Ok(())
+    }
+
+    /// Continues in slices, checking for an interrupt between them
+    fn resume(&mut self, connection: &mut Connection) -> io::Result<String> {
+        loop {
+            match self.debugger.resume() {
+                Stop::LimitReached => {
+                    if connection.poll_interrupt()? {
+                        return Ok("S02".to_string());
+                    }
+                }
+                stop => return Ok(stop_reply(stop)),
+            }
+        }
+    }
+
+    /// Handles a packet's contents, without the framing
+    fn handle(&mut self, packet: &str) -> Action {
+        let (command, args) = packet.split_at(packet.chars().next().map_or(0, char::len_utf8));
+        let reply = match command {
+            "?" => "S05".to_string(),
+            "g" => self.read_registers(),
+            "G" => self.write_registers(args),
+            "p" => self.read_register(args),
+            "P" => self.write_register(args),
+            "m" => self.read_memory(args),
+            "M" => self.write_memory(args),
+            "Z" | "z" => self.breakpoint(command == "Z", args),
+            "s" | "c" => {
+                if !args.is_empty() {
+                    match u16::from_str_radix(args, 16) {
+                        Ok(address) => self.debugger.get_emulator_mut().set_program_counter(address),
+                        Err(_) => return Action::Reply("E01".to_string()),
+                    }
+                }
+                return if command == "s" { Action::Step } else { Action::Continue };
+            }
+            "b" if args == "s" => return Action::ReverseStep,
+            "b" if args == "c" => return Action::ReverseContinue,
+            "D" => return Action::Close(Some("OK".to_string())),
+            "k" => return Action::Close(None),
+            "H" => "OK".to_string(),
+            "T" => "OK".to_string(),
+            "q" | "Q" => self.query(packet),
+            _ => String::new(),
+        };
+        Action::Reply(reply)
+    }
+
+    fn query(&self, packet: &str) -> String {
+        if packet.starts_with("qSupported") {
+            return format!("PacketSize={PACKET_SIZE:x};QStartNoAckMode+;qXfer:features:read+;ReverseStep+;ReverseContinue+");
+        }
+        if let Some(args) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            let Some((offset, length)) = parse_pair(args, ',') else {
+                return "E01".to_string();
+            };
+            let start = (offset as usize).min(TARGET_XML.len());
+            let end = start.saturating_add(length as usize).min(TARGET_XML.len());
+            let prefix = if end < TARGET_XML.len() { 'm' } else { 'l' };
+            return format!("{prefix}{}", &TARGET_XML[start..end]);
+        }
+        match packet {
+            "QStartNoAckMode" => "OK",
+            "qAttached" => "1",
+            "qC" => "QC1",
+            "qfThreadInfo" => "m1",
+            "qsThreadInfo" => "l",
+            _ => "",
+        }
+        .to_string()
+    }
+
+    /// Register values as GDB sends them, in register number order
+    fn registers(&self) -> Vec<Vec<u8>> {
+        let emulator = self.debugger.get_emulator();
+        let state = emulator.get_cpu_state();
+        let mut registers: Vec<Vec<u8>> = state.v_registers.iter().map(|&value| vec![value]).collect();
+        registers.push(state.i_register.to_be_bytes().to_vec());
+        registers.push(state.program_counter.to_be_bytes().to_vec());
+        registers.push(state.stack_pointer.to_be_bytes().to_vec());
+        registers.push(vec![emulator.get_memory().get_delay_timer()]);
+        registers.push(vec![emulator.get_memory().get_sound_timer()]);
+        registers
+    }
+
+    fn read_registers(&self) -> String {
+        self.registers().iter().map(|bytes| to_hex(bytes)).collect()
+    }
+
+    fn write_registers(&mut self, args: &str) -> String {
+        let Some(bytes) = from_hex(args) else {
+            return "E01".to_string();
+        };
+        let sizes: Vec<usize> = self.registers().iter().map(Vec::len).collect();
+        if bytes.len() != sizes.iter().sum::<usize>() {
+            return "E01".to_string();
+        }
+        let mut offset = 0;
+        for (number, size) in sizes.into_iter().enumerate() {
+            if !self.set_register(number, &bytes[offset..offset + size]) {
+                return "E01".to_string();
+            }
+            offset += size;
+        }
+        "OK".to_string()
+    }
+
+    fn read_register(&self, args: &str) -> String {
+        let registers = self.registers();
+        match usize::from_str_radix(args, 16).ok().and_then(|number| registers.get(number)) {
+            Some(bytes) => to_hex(bytes),
+            None => "E01".to_string(),
+        }
+    }
+
+    fn write_register(&mut self, args: &str) -> String {
+        let Some((number, value)) = args.split_once('=') else {
+            return "E01".to_string();
+        };
+        let number = usize::from_str_radix(number, 16).ok();
+        let value = from_hex(value);
+        match (number, value) {
+            (Some(number), Some(value)) if self.set_register(number, &value) => "OK".to_string(),
+            _ => "E01".to_string(),
+        }
+    }
+
+    /// Sets a register from its GDB encoding. The stack pointer can only be
+    /// written with its current value.
+    fn set_register(&mut self, number: usize, bytes: &[u8]) -> bool {
+        let emulator = self.debugger.get_emulator_mut();
+        let word = || Some(u16::from_be_bytes(bytes.try_into().ok()?));
+        match (number, bytes) {
+            (0..NUMBER_OF_REGISTERS, &[value]) => emulator.set_register(number, value),
+            (REGISTER_I, _) if bytes.len() == 2 => emulator.set_i_register(word().unwrap()),
+            (REGISTER_PC, _) if bytes.len() == 2 => emulator.set_program_counter(word().unwrap()),
+            (REGISTER_SP, _) if word() == Some(emulator.get_cpu_state().stack_pointer) => {}
+            (REGISTER_DT, &[value]) => emulator.set_delay_timer(value),
+            (REGISTER_ST, &[value]) => emulator.set_sound_timer(value),
+            _ => return false,
+        }
+        true
+    }
+
+    fn read_memory(&self, args: &str) -> String {
+        let ram = self.debugger.get_emulator().get_ram();
+        match parse_pair(args, ',') {
+            Some((address, length)) => {
+                // Reads past the end of memory are cut short, as GDB expects
+                let start = (address as usize).min(ram.len());
+                let end = start.saturating_add(length as usize).min(ram.len()).min(start + PACKET_SIZE / 2);
+                if start == ram.len() && length > 0 {
+                    return "E01".to_string();
+                }
+                to_hex(&ram[start..end])
+            }
+            None => "E01".to_string(),
+        }
+    }
+
+    fn write_memory(&mut self, args: &str) -> String {
+        let Some((range, data)) = args.split_once(':') else {
+            return "E01".to_string();
+        };
+        match (parse_pair(range, ','), from_hex(data)) {
+            (Some((address, length)), Some(bytes)) if bytes.len() == length as usize => {
+                match self.debugger.get_emulator_mut().write_memory(address as usize, &bytes) {
+                    Ok(()) => "OK".to_string(),
+                    Err(_) => "E01".to_string(),
+                }
+            }
+            _ => "E01".to_string(),
+        }
+    }
+
+    /// `Z`/`z` type,addr,kind: type 0 is a breakpoint, 2 - 4 are write,
+    /// read and access watchpoints over `kind` bytes
+    fn breakpoint(&mut self, insert: bool, args: &str) -> String {
+        let mut fields = args.split(',');
+        let (Some(kind), Some(address), Some(length)) = (fields.next(), fields.next(), fields.next()) else {
+            return "E01".to_string();
+        };
+        let (Ok(address), Ok(length)) = (u16::from_str_radix(address, 16), u16::from_str_radix(length, 16)) else {
+            return "E01".to_string();
+        };
+        let accesses = match kind {
+            "0" | "1" => {
+                if insert {
+                    self.debugger.add_breakpoint(address);
+                } else {
+                    self.debugger.remove_breakpoint(address);
+                }
+                return "OK".to_string();
+            }
+            "2" => vec![Access::Write],
+            "3" => vec![Access::Read],
+            "4" => vec![Access::Read, Access::Write],
+            _ => return String::new(),
+        };
+        let key = (kind.as_bytes()[0], address, length);
+        if insert {
+            let range = address as usize..=(address as usize + length.max(1) as usize - 1);
+            let number = self.debugger.add_watchpoint(Watchpoint::Memory { range, accesses });
+            if let Some(previous) = self.watchpoints.insert(key, number) {
+                self.debugger.remove_watchpoint(previous);
+            }
+        } else if let Some(number) = self.watchpoints.remove(&key) {
+            self.debugger.remove_watchpoint(number);
+        }
+        "OK".to_string()
+    }
+}
+
+/// Stop reply packet for a stop, by the signal a native target would raise
+fn stop_reply(stop: Stop) -> String {
+    match stop {
+        Stop::Exited => "W00".to_string(),
+        Stop::HistoryStart => "T05replaylog:begin;".to_string(),
+        Stop::MemoryWatch { access, .. } => {
+            let kind = match access.access {
+                Access::Write => "watch",
+                Access::Read => "rwatch",
+                Access::Fetch => "awatch",
+            };
+            format!("T05{kind}:{:x};", access.address)
+        }
+        Stop::Fault(Chip8Error::InvalidOpcode { .. }) => "S04".to_string(),
+        Stop::Fault(Chip8Error::MemoryOutOfBounds { .. }) => "S0b".to_string(),
+        Stop::Fault(_) | Stop::Halted => "S06".to_string(),
+        _ => "S05".to_string(),
+    }
+}
+
+/// Parses `a,b` with hex numbers
+fn parse_pair(text: &str, separator: char) -> Option<(u32, u32)> {
+    let (first, second) = text.split_once(separator)?;
+    Some((u32::from_str_radix(first, 16).ok()?, u32::from_str_radix(second, 16).ok()?))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn from_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) || !text.is_ascii() {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(&text[index..index + 2], 16).ok())
+        .collect()
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum: u8, &byte| sum.wrapping_add(byte))
+}
+
+/// Packet framing over a TCP stream
+struct Connection {
+    stream: TcpStream,
+    /// Bytes received but not yet consumed
+    buffer: VecDeque<u8>,
+}
+
+impl Connection {
+    /// Next byte, blocking until one arrives, or `None` on disconnect
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        if self.buffer.is_empty() {
+            let mut chunk = [0; 1024];
+            let len = self.stream.read(&mut chunk)?;
+            self.buffer.extend(&chunk[..len]);
+        }
+        Ok(self.buffer.pop_front())
+    }
+
+    /// Reads the next packet, acknowledging it unless acks are off.
+    /// Corrupt packets are rejected with `-` so the client resends them.
+    fn read_packet(&mut self, no_ack: bool) -> io::Result<Option<String>> {
+        loop {
+            // Skip acks and interrupts that arrive while stopped
+            loop {
+                match self.read_byte()? {
+                    Some(b'$') => break,
+                    Some(_) => {}
+                    None => return Ok(None),
+                }
+            }
+            let mut data = Vec::new();
+            loop {
+                match self.read_byte()? {
+                    Some(b'#') => break,
+                    // Escaped byte
+                    Some(b'}') => match self.read_byte()? {
+                        Some(byte) => data.push(byte ^ 0x20),
+                        None => return Ok(None),
+                    },
+                    Some(byte) => data.push(byte),
+                    None => return Ok(None),
+                }
+            }
+            let (Some(high), Some(low)) = (self.read_byte()?, self.read_byte()?) else {
+                return Ok(None);
+            };
+            let expected = std::str::from_utf8(&[high, low]).ok().and_then(|hex| u8::from_str_radix(hex, 16).ok());
+            if no_ack {
+                return Ok(Some(String::from_utf8_lossy(&data).into_owned()));
+            }
+            if expected == Some(checksum(&data)) {
+                self.stream.write_all(b"+")?;
+                return Ok(Some(String::from_utf8_lossy(&data).into_owned()));
+            }
+            self.stream.write_all(b"-")?;
+        }
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        let packet = format!("${data}#{:02x}", checksum(data.as_bytes()));
+        self.stream.write_all(packet.as_bytes())
+    }
+
+    /// Whether the client sent an interrupt, without blocking
+    fn poll_interrupt(&mut self) -> io::Result<bool> {
+        self.stream.set_nonblocking(true)?;
+        let mut chunk = [0; 1024];
+        let result = loop {
+            match self.stream.read(&mut chunk) {
+                Ok(0) => break Err(io::Error::new(ErrorKind::UnexpectedEof, "client disconnected")),
+                Ok(len) => self.buffer.extend(&chunk[..len]),
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break Ok(()),
+                Err(err) => break Err(err),
+            }
+        };
+        self.stream.set_nonblocking(false)?;
+        result?;
+        match self.buffer.iter().position(|&byte| byte == INTERRUPT) {
+            Some(index) => {
+                self.buffer.remove(index);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,129 @@
+use super::*;
+use crate::asm::assemble;
+use crate::config::Config;
+use crate::emulator::Emulator;
+
+const PROGRAM: &str = "
+        LD V0, 0x12     ; 200
+        LD I, data      ; 202
+        LD [I], V0      ; 204
+end:    JP end          ; 206
+data:   DB 0xAB, 0xCD   ; 208
+";
+
+fn server() -> GdbServer {
+    let mut emulator = Emulator::with_config(Config::default());
+    emulator.load_rom(&assemble(PROGRAM).unwrap()).unwrap();
+    GdbServer::new(Debugger::new(emulator))
+}
+
+fn reply(server: &mut GdbServer, packet: &str) -> String {
+    match server.handle(packet) {
+        Action::Reply(reply) => reply,
+        action => panic!("expected a reply to {packet}, got {action:?}"),
+    }
+}
+
+#[test]
+fn registers_are_encoded_in_order() {
+    let mut server = server();
+    let registers = reply(&mut server, "g");
+    // 16 V registers, I, PC and SP, then the two timers
+    assert_eq!(registers.len(), (16 + 3 * 2 + 2) * 2);
+    assert_eq!(&registers[32..44], "000002000000");
+    assert_eq!(reply(&mut server, "p11"), "0200");
+    assert_eq!(reply(&mut server, "p15"), "E01");
+}
+
+#[test]
+fn registers_can_be_written() {
+    let mut server = server();
+    assert_eq!(reply(&mut server, "P3=7f"), "OK");
+    assert_eq!(reply(&mut server, "P10=0345"), "OK");
+    assert_eq!(reply(&mut server, "P13=3c"), "OK");
+    assert_eq!(reply(&mut server, "P12=0001"), "E01");
+    assert_eq!(reply(&mut server, "P3=123"), "E01");
+    let state = server.get_debugger().get_emulator().get_cpu_state();
+    assert_eq!(state.v_registers[3], 0x7F);
+    assert_eq!(state.i_register, 0x345);
+    assert_eq!(server.get_debugger().get_emulator().get_memory().get_delay_timer(), 0x3C);
+
+    let mut registers = reply(&mut server, "g");
+    registers.replace_range(0..2, "99");
+    assert_eq!(reply(&mut server, &format!("G{registers}")), "OK");
+    assert_eq!(server.get_debugger().get_emulator().get_cpu_state().v_registers[0], 0x99);
+}
+
+#[test]
+fn memory_reads_and_writes() {
+    let mut server = server();
+    assert_eq!(reply(&mut server, "m208,2"), "abcd");
+    assert_eq!(reply(&mut server, "M208,2:0102"), "OK");
+    assert_eq!(reply(&mut server, "m207,3"), "060102");
+    assert_eq!(reply(&mut server, "M208,2:01"), "E01");
+    // Reads are cut short at the end of RAM
+    assert_eq!(reply(&mut server, "mffe,4").len(), 4);
+    assert_eq!(reply(&mut server, "m1000,1"), "E01");
+}
+
+#[test]
+fn breakpoints_stop_continue() {
+    let mut server = server();
+    assert_eq!(reply(&mut server, "Z0,204,2"), "OK");
+    assert_eq!(server.handle("c"), Action::Continue);
+    assert!(matches!(server.debugger.resume(), Stop::Breakpoint(0x204)));
+    assert_eq!(reply(&mut server, "z0,204,2"), "OK");
+    assert!(server.get_debugger().get_breakpoints().is_empty());
+}
+
+#[test]
+fn watchpoints_report_the_address() {
+    let mut server = server();
+    assert_eq!(reply(&mut server, "Z2,208,1"), "OK");
+    assert_eq!(stop_reply(server.debugger.resume()), "T05watch:208;");
+    assert_eq!(reply(&mut server, "z2,208,1"), "OK");
+    assert!(server.get_debugger().get_watchpoints().is_empty());
+}
+
+#[test]
+fn step_and_continue_accept_an_address() {
+    let mut server = server();
+    assert_eq!(server.handle("s204"), Action::Step);
+    assert_eq!(server.get_debugger().get_emulator().get_cpu_state().program_counter, 0x204);
+    assert_eq!(server.handle("cxyz"), Action::Reply("E01".to_string()));
+}
+
+#[test]
+fn queries() {
+    let mut server = server();
+    assert!(reply(&mut server, "qSupported:multiprocess+").contains("qXfer:features:read+"));
+    assert_eq!(reply(&mut server, "?"), "S05");
+    assert_eq!(reply(&mut server, "qAttached"), "1");
+    assert_eq!(reply(&mut server, "vMustReplyEmpty"), "");
+    let start = reply(&mut server, "qXfer:features:read:target.xml:0,10");
+    assert_eq!(start, format!("m{}", &TARGET_XML[..0x10]));
+    let all = reply(&mut server, "qXfer:features:read:target.xml:0,1000");
+    assert_eq!(all, format!("l{TARGET_XML}"));
+    assert_eq!(server.handle("D"), Action::Close(Some("OK".to_string())));
+}
+
+#[test]
+fn faults_map_to_signals() {
+    let fault = Stop::Fault(Chip8Error::InvalidOpcode { pc: 0x200, opcode: 0xFFFF });
+    assert_eq!(stop_reply(fault), "S04");
+    assert_eq!(stop_reply(Stop::Fault(Chip8Error::MemoryOutOfBounds { addr: 0x1000 })), "S0b");
+    assert_eq!(stop_reply(Stop::Exited), "W00");
+}
+
+#[test]
+fn reverse_execution() {
+    let mut server = server();
+    assert!(reply(&mut server, "qSupported").contains("ReverseStep+"));
+    assert_eq!(server.handle("bs"), Action::ReverseStep);
+    assert_eq!(stop_reply(server.debugger.reverse_step()), "T05replaylog:begin;");
+    server.debugger.step();
+    server.debugger.step();
+    assert_eq!(server.handle("bc"), Action::ReverseContinue);
+    assert_eq!(stop_reply(server.debugger.reverse_step()), "S05");
+    assert_eq!(reply(&mut server, "p11"), "0202");
+}
@@ -0,0 +1,99 @@
+//! Framebuffer export as ASCII art, PBM and PNG images, for headless runs
+//! and snapshot tests.
+
+use std::fmt::Write as _;
+use crate::display::Framebuffer;
+use crate::state::crc32;
+
+/// RGB colors indexed by the plane bits of a pixel
+pub const PALETTE: [[u8; 3]; 4] = [
+    [0x00, 0x00, 0x00],
+    [0xFF, 0xFF, 0xFF],
+    [0xAA, 0xAA, 0xAA],
+    [0x55, 0x55, 0x55],
+];
+
+/// Characters indexed by the plane bits of a pixel
+pub const ASCII_PIXELS: [char; 4] = ['.', '#', '+', '@'];
+
+/// One line of characters per row, see `ASCII_PIXELS`
+pub fn to_ascii(framebuffer: &Framebuffer) -> String {
+    let mut text = String::with_capacity((framebuffer.width() + 1) * framebuffer.height());
+    for row in framebuffer.rows() {
+        text.extend(row.iter().map(|&pixel| ASCII_PIXELS[pixel as usize & 0x3]));
+        text.push('\n');
+    }
+    text
+}
+
+/// Plain PBM, with lit pixels in any plane black
+pub fn to_pbm(framebuffer: &Framebuffer) -> Vec<u8> {
+    let mut text = format!("P1\n{} {}\n", framebuffer.width(), framebuffer.height());
+    for row in framebuffer.rows() {
+        let bits: Vec<&str> = row.iter().map(|&pixel| if pixel != 0 { "1" } else { "0" }).collect();
+        let _ = writeln!(text, "{}", bits.join(" "));
+    }
+    text.into_bytes()
+}
+
+/// Indexed color PNG using `PALETTE`, one image pixel per screen pixel
+pub fn to_png(framebuffer: &Framebuffer) -> Vec<u8> {
+    let (width, height) = (framebuffer.width(), framebuffer.height());
+    let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    // Bit depth 8, indexed color, default compression, filter and interlacing
+    header.extend_from_slice(&[8, 3, 0, 0, 0]);
+    write_chunk(&mut png, b"IHDR", &header);
+    write_chunk(&mut png, b"PLTE", PALETTE.as_flattened());
+    // Every row starts with filter type 0
+    let mut scanlines = Vec::with_capacity((width + 1) * height);
+    for row in framebuffer.rows() {
+        scanlines.push(0);
+        scanlines.extend(row.iter().map(|&pixel| pixel & 0x3));
+    }
+    write_chunk(&mut png, b"IDAT", &zlib_stored(&scanlines));
+    write_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+/// Wraps data in a zlib stream of uncompressed blocks. Screens are a few
+/// kilobytes at most, so compressing them isn't worth a deflate encoder.
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+    let mut blocks = data.chunks(u16::MAX as usize).peekable();
+    if blocks.peek().is_none() {
+        out.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        out.push(blocks.peek().is_none() as u8);
+        let len = block.len() as u16;
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    b << 16 | a
+}
+
+#[cfg(test)]
+mod tests;
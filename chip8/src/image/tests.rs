@@ -0,0 +1,74 @@
+use super::*;
+use crate::asm::assemble;
+use crate::config::Config;
+use crate::emulator::Emulator;
+
+/// Draws the font's 0 in the top left corner
+fn emulator() -> Emulator {
+    let mut emulator = Emulator::with_config(Config::default());
+    let rom = assemble("LD F, V0\nDRW V0, V0, 5\nend: JP end").unwrap();
+    emulator.load_rom(&rom).unwrap();
+    emulator.run_frame().unwrap();
+    emulator
+}
+
+#[test]
+fn ascii_has_a_line_per_row() {
+    let emulator = emulator();
+    let text = to_ascii(&emulator.get_framebuffer());
+    let lines: Vec<&str> = text.lines().collect();
+    assert_eq!(lines.len(), 32);
+    assert!(lines.iter().all(|line| line.len() == 64));
+    assert_eq!(&lines[0][..6], "####..");
+    assert_eq!(&lines[1][..6], "#..#..");
+    assert_eq!(&lines[4][..6], "####..");
+    assert!(lines[5].chars().all(|c| c == '.'));
+}
+
+#[test]
+fn pbm_marks_lit_pixels() {
+    let emulator = emulator();
+    let pbm = String::from_utf8(to_pbm(&emulator.get_framebuffer())).unwrap();
+    let mut lines = pbm.lines();
+    assert_eq!(lines.next(), Some("P1"));
+    assert_eq!(lines.next(), Some("64 32"));
+    assert!(lines.next().unwrap().starts_with("1 1 1 1 0 "));
+    assert_eq!(lines.count(), 31);
+}
+
+#[test]
+fn png_holds_the_pixels_uncompressed() {
+    let emulator = emulator();
+    let png = to_png(&emulator.get_framebuffer());
+    assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+    assert_eq!(&png[12..16], b"IHDR");
+    assert_eq!(&png[16..24], [0, 0, 0, 64, 0, 0, 0, 32]);
+    assert_eq!(&png[png.len() - 8..png.len() - 4], b"IEND");
+
+    // Walk the chunks, checking each CRC and collecting the image data
+    let mut data = Vec::new();
+    let mut pos = 8;
+    while pos < png.len() {
+        let len = u32::from_be_bytes(png[pos..pos + 4].try_into().unwrap()) as usize;
+        let chunk = &png[pos + 4..pos + 8 + len];
+        let crc = u32::from_be_bytes(png[pos + 8 + len..pos + 12 + len].try_into().unwrap());
+        assert_eq!(crc32(chunk), crc);
+        if &chunk[..4] == b"IDAT" {
+            data.extend_from_slice(&chunk[4..]);
+        }
+        pos += len + 12;
+    }
+    // zlib header, one final stored block, then the Adler-32 checksum
+    let scanlines = &data[7..data.len() - 4];
+    assert_eq!(data[2], 1);
+    assert_eq!(u16::from_le_bytes([data[3], data[4]]) as usize, scanlines.len());
+    assert_eq!(scanlines.len(), 65 * 32);
+    assert_eq!(&scanlines[..6], [0, 1, 1, 1, 1, 0]);
+    assert_eq!(&data[data.len() - 4..], adler32(scanlines).to_be_bytes());
+}
+
+#[test]
+fn adler32_matches_known_values() {
+    assert_eq!(adler32(b""), 1);
+    assert_eq!(adler32(b"Wikipedia"), 0x11E60398);
+}
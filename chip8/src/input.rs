@@ -1,54 +1,122 @@
-use std::cell::RefCell;
-use std::rc::Rc;
-use crate::emulator::{Emulator, EmulatorComponent};
+use crate::cpu::CPU;
+use crate::emulator::EmulatorComponent;
+use crate::error::Chip8Error;
+use crate::state::{invalid, StateReader, StateWriter};
 
-const NUMBER_OF_KEYS: usize = 16;
+pub const NUMBER_OF_KEYS: usize = 16;
+
+/// Progress of an FX0A instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum KeyWait {
+    Idle,
+    /// Waiting for any key to go down
+    Waiting,
+    /// A key went down, waiting for it to be released
+    Held(usize),
+}
 
 pub struct Input {
-    emulator: Rc<RefCell<Emulator>>,
     keys: [bool; NUMBER_OF_KEYS],
+    wait: KeyWait,
 }
 
 impl Input {
-    pub fn new(emulator: Rc<RefCell<Emulator>>) -> Self {
-        let input = Self {
-            emulator,
+    pub fn new() -> Self {
+        Self {
             keys: [false; NUMBER_OF_KEYS],
+            wait: KeyWait::Idle,
+        }
+    }
+
+    pub fn is_key_pressed(&self, key: usize) -> bool {
+        self.keys[key]
+    }
+
+    /// Whether an FX0A instruction is blocked waiting for input
+    pub fn is_waiting_for_key(&self) -> bool {
+        self.wait != KeyWait::Idle
+    }
+
+    /// Serializes the keypad as a bitmask and the FX0A progress
+    pub(crate) fn save_state(&self, writer: &mut StateWriter) {
+        let keys = (0..NUMBER_OF_KEYS).filter(|&key| self.keys[key]).fold(0u16, |mask, key| mask | 1 << key);
+        writer.write_u16(keys);
+        match self.wait {
+            KeyWait::Idle => writer.write_bytes(&[0, 0]),
+            KeyWait::Waiting => writer.write_bytes(&[1, 0]),
+            KeyWait::Held(key) => writer.write_bytes(&[2, key as u8]),
+        }
+    }
+
+    pub(crate) fn load_state(&mut self, reader: &mut StateReader) -> Result<(), Chip8Error> {
+        let keys = reader.read_u16()?;
+        for (i, key) in self.keys.iter_mut().enumerate() {
+            *key = keys & (1 << i) != 0;
+        }
+        self.wait = match (reader.read_u8()?, reader.read_u8()? as usize) {
+            (0, _) => KeyWait::Idle,
+            (1, _) => KeyWait::Waiting,
+            (2, key) if key < NUMBER_OF_KEYS => KeyWait::Held(key),
+            _ => return Err(invalid("invalid key wait state")),
         };
-        input
+        Ok(())
+    }
+
+    pub fn set_key(&mut self, key: usize, pressed: bool) {
+        // Remember the press so a release before the next instruction isn't lost
+        if pressed && !self.keys[key] && self.wait == KeyWait::Waiting {
+            self.wait = KeyWait::Held(key);
+        }
+        self.keys[key] = pressed;
     }
 
     /// SKP Vx - Skip next instructor if key at index Vx is pressed.
-    pub fn op_skp(&mut self, x: usize, reverse: bool) {
-        let vx = self.emulator.get_mut().get_cpu().get_register_value(x) as usize;
+    pub fn op_skp(&self, cpu: &mut CPU, x: usize, reverse: bool) {
+        // Only the low nibble selects a key
+        let vx = (cpu.get_register_value(x) & 0xF) as usize;
         let key = self.keys[vx];
-        let pc = self.emulator.get_mut().get_cpu().get_program_counter();
+        let pc = cpu.get_program_counter();
         if (!reverse && key) || (reverse && !key) {
-            self.emulator.get_mut().get_cpu().set_program_counter(pc + 2);
+            cpu.set_program_counter(pc.wrapping_add(2));
         }
     }
 
     /// LD Vx - Loads register Vx with value of key pressed.
-    pub fn op_ld_wait(&mut self, x: usize) {
-        let mut pressed = false;
-        for i in 0..self.keys.len() {
-            if self.keys[i] {
-                self.emulator.get_mut().get_cpu().set_register_value(x, i as u8);
-                pressed = true;
-                break;
+    ///
+    /// The instruction is re-executed until a key is pressed, or pressed and
+    /// released again when `wait_for_release` is set.
+    pub fn op_ld_wait(&mut self, cpu: &mut CPU, x: usize, wait_for_release: bool) {
+        if !matches!(self.wait, KeyWait::Held(_)) {
+            if let Some(i) = self.keys.iter().position(|&key| key) {
+                self.wait = KeyWait::Held(i);
             }
         }
-
-        if !pressed {
-            // redo opcode
-            let pc = self.emulator.get_mut().get_cpu().get_program_counter();
-            self.emulator.get_mut().get_cpu().set_program_counter(pc - 2);
+        match self.wait {
+            KeyWait::Held(key) if !wait_for_release || !self.keys[key] => {
+                cpu.set_register_value(x, key as u8);
+                self.wait = KeyWait::Idle;
+            }
+            _ => {
+                if self.wait == KeyWait::Idle {
+                    self.wait = KeyWait::Waiting;
+                }
+                // redo opcode
+                let pc = cpu.get_program_counter();
+                cpu.set_program_counter(pc.wrapping_sub(2));
+            }
         }
     }
 }
 
+impl Default for Input {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl EmulatorComponent for Input {
     fn reset(&mut self) {
         self.keys = [false; NUMBER_OF_KEYS];
+        self.wait = KeyWait::Idle;
     }
 }
\ No newline at end of file
@@ -0,0 +1,371 @@
+//! Decoded form of the CHIP-8, SUPER-CHIP and XO-CHIP instruction sets.
+
+use std::fmt;
+use crate::config::Mode;
+
+/// A single decoded instruction.
+///
+/// `x` and `y` name registers V0 - VF, `nn` is an immediate byte and
+/// addresses are 12 bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    /// 0000 - No operation
+    Nop,
+    /// 00CN - Scroll down N pixels
+    ScrollDown(u8),
+    /// 00DN - Scroll up N pixels
+    ScrollUp(u8),
+    /// 00E0 - Clear the selected planes
+    Clear,
+    /// 00EE - Return from subroutine
+    Return,
+    /// 00FB - Scroll right 4 pixels
+    ScrollRight,
+    /// 00FC - Scroll left 4 pixels
+    ScrollLeft,
+    /// 00FD - Stop the interpreter
+    Exit,
+    /// 00FE - Switch to 64x32 resolution
+    Lores,
+    /// 00FF - Switch to 128x64 resolution
+    Hires,
+    /// 0NNN - Call machine code routine, ignored
+    Sys(u16),
+    /// 1NNN - Jump to NNN
+    Jump(u16),
+    /// 2NNN - Call subroutine at NNN
+    Call(u16),
+    /// 3XNN - Skip if VX == NN
+    SkipEqImm { x: u8, nn: u8 },
+    /// 4XNN - Skip if VX != NN
+    SkipNeImm { x: u8, nn: u8 },
+    /// 5XY0 - Skip if VX == VY
+    SkipEq { x: u8, y: u8 },
+    /// 5XY2 - Store VX - VY at I
+    SaveRange { x: u8, y: u8 },
+    /// 5XY3 - Load VX - VY from I
+    LoadRange { x: u8, y: u8 },
+    /// 6XNN - VX = NN
+    SetImm { x: u8, nn: u8 },
+    /// 7XNN - VX += NN
+    AddImm { x: u8, nn: u8 },
+    /// 8XY0 - VX = VY
+    Set { x: u8, y: u8 },
+    /// 8XY1 - VX |= VY
+    Or { x: u8, y: u8 },
+    /// 8XY2 - VX &= VY
+    And { x: u8, y: u8 },
+    /// 8XY3 - VX ^= VY
+    Xor { x: u8, y: u8 },
+    /// 8XY4 - VX += VY
+    Add { x: u8, y: u8 },
+    /// 8XY5 - VX -= VY
+    Sub { x: u8, y: u8 },
+    /// 8XY6 - Shift right
+    ShiftRight { x: u8, y: u8 },
+    /// 8XY7 - VX = VY - VX
+    SubReverse { x: u8, y: u8 },
+    /// 8XYE - Shift left
+    ShiftLeft { x: u8, y: u8 },
+    /// 9XY0 - Skip if VX != VY
+    SkipNe { x: u8, y: u8 },
+    /// ANNN - I = NNN
+    SetI(u16),
+    /// BNNN - Jump to NNN + V0, or XNN + VX
+    JumpOffset(u16),
+    /// CXNN - VX = random & NN
+    Random { x: u8, nn: u8 },
+    /// DXYN - Draw an N row sprite, or a 16x16 sprite when N is 0
+    Draw { x: u8, y: u8, n: u8 },
+    /// EX9E - Skip if key VX is pressed
+    SkipKey(u8),
+    /// EXA1 - Skip if key VX is not pressed
+    SkipNotKey(u8),
+    /// F000 NNNN - I = the 16 bit address in the next word
+    SetILong,
+    /// FN01 - Select drawing planes
+    Plane(u8),
+    /// F002 - Load the audio pattern from I
+    Audio,
+    /// FX07 - VX = delay timer
+    GetDelay(u8),
+    /// FX0A - Wait for a key and store it in VX
+    WaitKey(u8),
+    /// FX15 - Delay timer = VX
+    SetDelay(u8),
+    /// FX18 - Sound timer = VX
+    SetSound(u8),
+    /// FX1E - I += VX
+    AddI(u8),
+    /// FX29 - I = small font glyph VX
+    Font(u8),
+    /// FX30 - I = large font glyph VX
+    BigFont(u8),
+    /// FX33 - Store BCD of VX at I
+    Bcd(u8),
+    /// FX3A - Pitch = VX
+    Pitch(u8),
+    /// FX55 - Store V0 - VX at I
+    Store(u8),
+    /// FX65 - Load V0 - VX from I
+    Load(u8),
+    /// FX75 - Store V0 - VX in the RPL flags
+    StoreFlags(u8),
+    /// FX85 - Load V0 - VX from the RPL flags
+    LoadFlags(u8),
+    /// Not an instruction in any mode
+    Invalid(u16),
+}
+
+/// Decodes an opcode, accepting every instruction up to XO-CHIP
+pub fn decode(operation: u16) -> Instruction {
+    let x = ((operation & 0x0F00) >> 8) as u8;
+    let y = ((operation & 0x00F0) >> 4) as u8;
+    let n = (operation & 0x000F) as u8;
+    let nn = (operation & 0x00FF) as u8;
+    let nnn = operation & 0x0FFF;
+    match (operation >> 12, x, y, n) {
+        (0, 0, 0, 0) => Instruction::Nop,
+        (0, 0, 0xC, _) => Instruction::ScrollDown(n),
+        (0, 0, 0xD, _) => Instruction::ScrollUp(n),
+        (0, 0, 0xE, 0) => Instruction::Clear,
+        (0, 0, 0xE, 0xE) => Instruction::Return,
+        (0, 0, 0xF, 0xB) => Instruction::ScrollRight,
+        (0, 0, 0xF, 0xC) => Instruction::ScrollLeft,
+        (0, 0, 0xF, 0xD) => Instruction::Exit,
+        (0, 0, 0xF, 0xE) => Instruction::Lores,
+        (0, 0, 0xF, 0xF) => Instruction::Hires,
+        (0, _, _, _) => Instruction::Sys(nnn),
+        (1, _, _, _) => Instruction::Jump(nnn),
+        (2, _, _, _) => Instruction::Call(nnn),
+        (3, _, _, _) => Instruction::SkipEqImm { x, nn },
+        (4, _, _, _) => Instruction::SkipNeImm { x, nn },
+        (5, _, _, 0) => Instruction::SkipEq { x, y },
+        (5, _, _, 2) => Instruction::SaveRange { x, y },
+        (5, _, _, 3) => Instruction::LoadRange { x, y },
+        (6, _, _, _) => Instruction::SetImm { x, nn },
+        (7, _, _, _) => Instruction::AddImm { x, nn },
+        (8, _, _, 0) => Instruction::Set { x, y },
+        (8, _, _, 1) => Instruction::Or { x, y },
+        (8, _, _, 2) => Instruction::And { x, y },
+        (8, _, _, 3) => Instruction::Xor { x, y },
+        (8, _, _, 4) => Instruction::Add { x, y },
+        (8, _, _, 5) => Instruction::Sub { x, y },
+        (8, _, _, 6) => Instruction::ShiftRight { x, y },
+        (8, _, _, 7) => Instruction::SubReverse { x, y },
+        (8, _, _, 0xE) => Instruction::ShiftLeft { x, y },
+        (9, _, _, 0) => Instruction::SkipNe { x, y },
+        (0xA, _, _, _) => Instruction::SetI(nnn),
+        (0xB, _, _, _) => Instruction::JumpOffset(nnn),
+        (0xC, _, _, _) => Instruction::Random { x, nn },
+        (0xD, _, _, _) => Instruction::Draw { x, y, n },
+        (0xE, _, 9, 0xE) => Instruction::SkipKey(x),
+        (0xE, _, 0xA, 1) => Instruction::SkipNotKey(x),
+        (0xF, 0, 0, 0) => Instruction::SetILong,
+        (0xF, _, 0, 1) => Instruction::Plane(x),
+        (0xF, 0, 0, 2) => Instruction::Audio,
+        (0xF, _, 0, 7) => Instruction::GetDelay(x),
+        (0xF, _, 0, 0xA) => Instruction::WaitKey(x),
+        (0xF, _, 1, 5) => Instruction::SetDelay(x),
+        (0xF, _, 1, 8) => Instruction::SetSound(x),
+        (0xF, _, 1, 0xE) => Instruction::AddI(x),
+        (0xF, _, 2, 9) => Instruction::Font(x),
+        (0xF, _, 3, 0) => Instruction::BigFont(x),
+        (0xF, _, 3, 3) => Instruction::Bcd(x),
+        (0xF, _, 3, 0xA) => Instruction::Pitch(x),
+        (0xF, _, 5, 5) => Instruction::Store(x),
+        (0xF, _, 6, 5) => Instruction::Load(x),
+        (0xF, _, 7, 5) => Instruction::StoreFlags(x),
+        (0xF, _, 8, 5) => Instruction::LoadFlags(x),
+        (_, _, _, _) => Instruction::Invalid(operation),
+    }
+}
+
+/// Decodes an opcode as the interpreter for `mode` sees it.
+///
+/// Instructions from later extensions are machine code calls in the 0NNN
+/// range and invalid everywhere else.
+pub fn decode_for(operation: u16, mode: Mode) -> Instruction {
+    let instruction = decode(operation);
+    if instruction.mode() <= mode {
+        instruction
+    } else if operation >> 12 == 0 {
+        Instruction::Sys(operation & 0x0FFF)
+    } else {
+        Instruction::Invalid(operation)
+    }
+}
+
+impl Instruction {
+    /// First mode that supports the instruction
+    pub fn mode(&self) -> Mode {
+        match self {
+            Instruction::ScrollDown(_)
+            | Instruction::ScrollRight
+            | Instruction::ScrollLeft
+            | Instruction::Exit
+            | Instruction::Lores
+            | Instruction::Hires
+            | Instruction::BigFont(_)
+            | Instruction::StoreFlags(_)
+            | Instruction::LoadFlags(_) => Mode::SuperChip,
+            Instruction::ScrollUp(_)
+            | Instruction::SaveRange { .. }
+            | Instruction::LoadRange { .. }
+            | Instruction::SetILong
+            | Instruction::Plane(_)
+            | Instruction::Audio
+            | Instruction::Pitch(_) => Mode::XoChip,
+            _ => Mode::Chip8,
+        }
+    }
+
+    /// Opcode of the instruction, the inverse of `decode`.
+    ///
+    /// For F000 NNNN only the first word is returned.
+    pub fn encode(&self) -> u16 {
+        let xy = |x: u8, y: u8| (x as u16 & 0xF) << 8 | (y as u16 & 0xF) << 4;
+        let xnn = |x: u8, nn: u8| (x as u16 & 0xF) << 8 | nn as u16;
+        let x = |x: u8| (x as u16 & 0xF) << 8;
+        match *self {
+            Instruction::Nop => 0x0000,
+            Instruction::ScrollDown(n) => 0x00C0 | (n as u16 & 0xF),
+            Instruction::ScrollUp(n) => 0x00D0 | (n as u16 & 0xF),
+            Instruction::Clear => 0x00E0,
+            Instruction::Return => 0x00EE,
+            Instruction::ScrollRight => 0x00FB,
+            Instruction::ScrollLeft => 0x00FC,
+            Instruction::Exit => 0x00FD,
+            Instruction::Lores => 0x00FE,
+            Instruction::Hires => 0x00FF,
+            Instruction::Sys(nnn) => nnn & 0x0FFF,
+            Instruction::Jump(nnn) => 0x1000 | (nnn & 0x0FFF),
+            Instruction::Call(nnn) => 0x2000 | (nnn & 0x0FFF),
+            Instruction::SkipEqImm { x, nn } => 0x3000 | xnn(x, nn),
+            Instruction::SkipNeImm { x, nn } => 0x4000 | xnn(x, nn),
+            Instruction::SkipEq { x, y } => 0x5000 | xy(x, y),
+            Instruction::SaveRange { x, y } => 0x5002 | xy(x, y),
+            Instruction::LoadRange { x, y } => 0x5003 | xy(x, y),
+            Instruction::SetImm { x, nn } => 0x6000 | xnn(x, nn),
+            Instruction::AddImm { x, nn } => 0x7000 | xnn(x, nn),
+            Instruction::Set { x, y } => 0x8000 | xy(x, y),
+            Instruction::Or { x, y } => 0x8001 | xy(x, y),
+            Instruction::And { x, y } => 0x8002 | xy(x, y),
+            Instruction::Xor { x, y } => 0x8003 | xy(x, y),
+            Instruction::Add { x, y } => 0x8004 | xy(x, y),
+            Instruction::Sub { x, y } => 0x8005 | xy(x, y),
+            Instruction::ShiftRight { x, y } => 0x8006 | xy(x, y),
+            Instruction::SubReverse { x, y } => 0x8007 | xy(x, y),
+            Instruction::ShiftLeft { x, y } => 0x800E | xy(x, y),
+            Instruction::SkipNe { x, y } => 0x9000 | xy(x, y),
+            Instruction::SetI(nnn) => 0xA000 | (nnn & 0x0FFF),
+            Instruction::JumpOffset(nnn) => 0xB000 | (nnn & 0x0FFF),
+            Instruction::Random { x, nn } => 0xC000 | xnn(x, nn),
+            Instruction::Draw { x, y, n } => 0xD000 | xy(x, y) | (n as u16 & 0xF),
+            Instruction::SkipKey(vx) => 0xE09E | x(vx),
+            Instruction::SkipNotKey(vx) => 0xE0A1 | x(vx),
+            Instruction::SetILong => 0xF000,
+            Instruction::Plane(n) => 0xF001 | x(n),
+            Instruction::Audio => 0xF002,
+            Instruction::GetDelay(vx) => 0xF007 | x(vx),
+            Instruction::WaitKey(vx) => 0xF00A | x(vx),
+            Instruction::SetDelay(vx) => 0xF015 | x(vx),
+            Instruction::SetSound(vx) => 0xF018 | x(vx),
+            Instruction::AddI(vx) => 0xF01E | x(vx),
+            Instruction::Font(vx) => 0xF029 | x(vx),
+            Instruction::BigFont(vx) => 0xF030 | x(vx),
+            Instruction::Bcd(vx) => 0xF033 | x(vx),
+            Instruction::Pitch(vx) => 0xF03A | x(vx),
+            Instruction::Store(vx) => 0xF055 | x(vx),
+            Instruction::Load(vx) => 0xF065 | x(vx),
+            Instruction::StoreFlags(vx) => 0xF075 | x(vx),
+            Instruction::LoadFlags(vx) => 0xF085 | x(vx),
+            Instruction::Invalid(operation) => operation,
+        }
+    }
+
+    /// Size in bytes, including the address word of F000 NNNN
+    pub fn size(&self) -> u16 {
+        match self {
+            Instruction::SetILong => 4,
+            _ => 2,
+        }
+    }
+
+    /// Whether the instruction may skip the next one
+    pub fn is_skip(&self) -> bool {
+        matches!(
+            self,
+            Instruction::SkipEqImm { .. }
+                | Instruction::SkipNeImm { .. }
+                | Instruction::SkipEq { .. }
+                | Instruction::SkipNe { .. }
+                | Instruction::SkipKey(_)
+                | Instruction::SkipNotKey(_)
+        )
+    }
+}
+
+/// Classic mnemonics with hex operands, e.g. `LD V0, 0x12`
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Instruction::Nop => write!(f, "NOP"),
+            Instruction::ScrollDown(n) => write!(f, "SCD {n}"),
+            Instruction::ScrollUp(n) => write!(f, "SCU {n}"),
+            Instruction::Clear => write!(f, "CLS"),
+            Instruction::Return => write!(f, "RET"),
+            Instruction::ScrollRight => write!(f, "SCR"),
+            Instruction::ScrollLeft => write!(f, "SCL"),
+            Instruction::Exit => write!(f, "EXIT"),
+            Instruction::Lores => write!(f, "LOW"),
+            Instruction::Hires => write!(f, "HIGH"),
+            Instruction::Sys(nnn) => write!(f, "SYS 0x{nnn:03X}"),
+            Instruction::Jump(nnn) => write!(f, "JP 0x{nnn:03X}"),
+            Instruction::Call(nnn) => write!(f, "CALL 0x{nnn:03X}"),
+            Instruction::SkipEqImm { x, nn } => write!(f, "SE V{x:X}, 0x{nn:02X}"),
+            Instruction::SkipNeImm { x, nn } => write!(f, "SNE V{x:X}, 0x{nn:02X}"),
+            Instruction::SkipEq { x, y } => write!(f, "SE V{x:X}, V{y:X}"),
+            Instruction::SaveRange { x, y } => write!(f, "SAVE V{x:X}, V{y:X}"),
+            Instruction::LoadRange { x, y } => write!(f, "LOAD V{x:X}, V{y:X}"),
+            Instruction::SetImm { x, nn } => write!(f, "LD V{x:X}, 0x{nn:02X}"),
+            Instruction::AddImm { x, nn } => write!(f, "ADD V{x:X}, 0x{nn:02X}"),
+            Instruction::Set { x, y } => write!(f, "LD V{x:X}, V{y:X}"),
+            Instruction::Or { x, y } => write!(f, "OR V{x:X}, V{y:X}"),
+            Instruction::And { x, y } => write!(f, "AND V{x:X}, V{y:X}"),
+            Instruction::Xor { x, y } => write!(f, "XOR V{x:X}, V{y:X}"),
+            Instruction::Add { x, y } => write!(f, "ADD V{x:X}, V{y:X}"),
+            Instruction::Sub { x, y } => write!(f, "SUB V{x:X}, V{y:X}"),
+            Instruction::ShiftRight { x, y } => write!(f, "SHR V{x:X}, V{y:X}"),
+            Instruction::SubReverse { x, y } => write!(f, "SUBN V{x:X}, V{y:X}"),
+            Instruction::ShiftLeft { x, y } => write!(f, "SHL V{x:X}, V{y:X}"),
+            Instruction::SkipNe { x, y } => write!(f, "SNE V{x:X}, V{y:X}"),
+            Instruction::SetI(nnn) => write!(f, "LD I, 0x{nnn:03X}"),
+            Instruction::JumpOffset(nnn) => write!(f, "JP V0, 0x{nnn:03X}"),
+            Instruction::Random { x, nn } => write!(f, "RND V{x:X}, 0x{nn:02X}"),
+            Instruction::Draw { x, y, n } => write!(f, "DRW V{x:X}, V{y:X}, {n}"),
+            Instruction::SkipKey(x) => write!(f, "SKP V{x:X}"),
+            Instruction::SkipNotKey(x) => write!(f, "SKNP V{x:X}"),
+            Instruction::SetILong => write!(f, "LD I, LONG"),
+            Instruction::Plane(n) => write!(f, "PLANE {n}"),
+            Instruction::Audio => write!(f, "AUDIO"),
+            Instruction::GetDelay(x) => write!(f, "LD V{x:X}, DT"),
+            Instruction::WaitKey(x) => write!(f, "LD V{x:X}, K"),
+            Instruction::SetDelay(x) => write!(f, "LD DT, V{x:X}"),
+            Instruction::SetSound(x) => write!(f, "LD ST, V{x:X}"),
+            Instruction::AddI(x) => write!(f, "ADD I, V{x:X}"),
+            Instruction::Font(x) => write!(f, "LD F, V{x:X}"),
+            Instruction::BigFont(x) => write!(f, "LD HF, V{x:X}"),
+            Instruction::Bcd(x) => write!(f, "LD B, V{x:X}"),
+            Instruction::Pitch(x) => write!(f, "PITCH V{x:X}"),
+            Instruction::Store(x) => write!(f, "LD [I], V{x:X}"),
+            Instruction::Load(x) => write!(f, "LD V{x:X}, [I]"),
+            Instruction::StoreFlags(x) => write!(f, "LD R, V{x:X}"),
+            Instruction::LoadFlags(x) => write!(f, "LD V{x:X}, R"),
+            Instruction::Invalid(operation) => write!(f, "DW 0x{operation:04X}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,42 @@
+use super::*;
+
+#[test]
+fn decodes_fields() {
+    assert_eq!(decode(0x6A42), Instruction::SetImm { x: 0xA, nn: 0x42 });
+    assert_eq!(decode(0x8BC4), Instruction::Add { x: 0xB, y: 0xC });
+    assert_eq!(decode(0xD125), Instruction::Draw { x: 1, y: 2, n: 5 });
+    assert_eq!(decode(0x2ABC), Instruction::Call(0xABC));
+    assert_eq!(decode(0xF30A), Instruction::WaitKey(3));
+    assert_eq!(decode(0x5121), Instruction::Invalid(0x5121));
+    assert_eq!(decode(0xE19F), Instruction::Invalid(0xE19F));
+}
+
+#[test]
+fn decode_for_gates_extensions() {
+    assert_eq!(decode_for(0x00FF, Mode::Chip8), Instruction::Sys(0x0FF));
+    assert_eq!(decode_for(0x00FF, Mode::SuperChip), Instruction::Hires);
+    assert_eq!(decode_for(0x00D2, Mode::SuperChip), Instruction::Sys(0x0D2));
+    assert_eq!(decode_for(0x00D2, Mode::XoChip), Instruction::ScrollUp(2));
+    assert_eq!(decode_for(0xF030, Mode::Chip8), Instruction::Invalid(0xF030));
+    assert_eq!(decode_for(0xF000, Mode::SuperChip), Instruction::Invalid(0xF000));
+    assert_eq!(decode_for(0xF000, Mode::XoChip), Instruction::SetILong);
+}
+
+#[test]
+fn classic_mnemonics() {
+    let listing: Vec<String> = [0x00E0, 0x6012, 0x8126, 0xA2F0, 0xD015, 0xF165, 0xB300, 0x0123]
+        .iter()
+        .map(|&operation| decode(operation).to_string())
+        .collect();
+    assert_eq!(
+        listing,
+        ["CLS", "LD V0, 0x12", "SHR V1, V2", "LD I, 0x2F0", "DRW V0, V1, 5", "LD V1, [I]", "JP V0, 0x300", "SYS 0x123"]
+    );
+}
+
+#[test]
+fn encode_inverts_decode() {
+    for operation in 0..=u16::MAX {
+        assert_eq!(decode(operation).encode(), operation, "{operation:04X}");
+    }
+}
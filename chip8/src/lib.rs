@@ -1,5 +1,21 @@
+pub mod asm;
+pub mod audio;
+pub mod config;
 pub mod emulator;
-mod cpu;
-mod memory;
+pub mod error;
+pub mod image;
+pub mod cpu;
+pub mod debug;
+pub mod disasm;
+pub mod gdb;
+pub mod memory;
+pub mod octo;
 pub mod display;
-mod input;
\ No newline at end of file
+pub mod input;
+pub mod instruction;
+pub mod quirks;
+pub mod rewind;
+pub mod rng;
+pub mod rom;
+pub mod state;
+pub mod trace;
\ No newline at end of file
@@ -1,58 +1,255 @@
 use std::cell::RefCell;
-use std::rc::Rc;
-use crate::display::{FONT_SET, FONT_SET_SIZE};
-use crate::emulator::{Emulator, EmulatorComponent};
+use std::ops::RangeInclusive;
+use crate::cpu::{CPU, START_ADDRESS};
+use crate::display::{BIG_FONT_ADDRESS, BIG_FONT_SET, BIG_FONT_SET_SIZE, FONT_SET, FONT_SET_SIZE};
+use crate::emulator::EmulatorComponent;
+use crate::error::Chip8Error;
+use crate::state::{invalid, StateReader, StateWriter};
 
-const RAM_SIZE: usize = 0x1000; // 4096 bytes
+pub const RAM_SIZE: usize = 0x1000; // 4096 bytes
+pub const XO_RAM_SIZE: usize = 0x10000; // 65536 bytes
+/// Size of the XO-CHIP audio pattern buffer in bytes
+pub const AUDIO_PATTERN_SIZE: usize = 16;
+/// Pitch register value that plays the audio pattern at 4000 Hz
+pub const DEFAULT_PITCH: u8 = 64;
+
+/// Kind of RAM access reported to watchpoints
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    /// Instruction fetch
+    Fetch,
+    /// Data read by FX65, 5XY3, F002 or a sprite draw
+    Read,
+    /// Data write by FX55, 5XY2 or FX33
+    Write,
+}
+
+impl Access {
+    fn bit(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+/// Access to a watched address
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryAccess {
+    pub address: usize,
+    pub access: Access,
+    /// Byte read or written
+    pub value: u8,
+}
 
 pub struct Memory {
-    emulator: Rc<RefCell<Emulator>>,
-    ram: [u8; RAM_SIZE],
+    ram: Vec<u8>,
+    /// Watched access kinds per address, empty while nothing is watched so
+    /// unwatched accesses cost a single failed lookup
+    watches: Vec<u8>,
+    /// Accesses to watched addresses since they were last taken
+    accesses: RefCell<Vec<MemoryAccess>>,
     delay_timer: u8,
     sound_timer: u8,
+    audio_pattern: Option<[u8; AUDIO_PATTERN_SIZE]>,
+    pitch: u8,
+    wrap: bool,
 }
 
 impl Memory {
-    pub fn new(emulator: Rc<RefCell<Emulator>>) -> Self {
+    /// Constructor with `ram_size` bytes of RAM
+    pub fn new(ram_size: usize) -> Self {
         let mut memory = Self {
-            emulator,
-            ram: [0; RAM_SIZE],
+            ram: vec![0; ram_size],
+            watches: Vec::new(),
+            accesses: RefCell::new(Vec::new()),
             delay_timer: 0,
             sound_timer: 0,
+            audio_pattern: None,
+            pitch: DEFAULT_PITCH,
+            wrap: false,
         };
         memory.initialize_font_set();
         memory
     }
 
-    /// Initializes the font set within the first 80 bytes of memory.
+    /// Initializes the font set within the first 80 bytes of memory,
+    /// followed by the SUPER-CHIP large font.
     pub fn initialize_font_set(&mut self) {
         self.ram[..FONT_SET_SIZE].copy_from_slice(&FONT_SET);
+        self.ram[BIG_FONT_ADDRESS..BIG_FONT_ADDRESS + BIG_FONT_SET_SIZE].copy_from_slice(&BIG_FONT_SET);
+    }
+
+    /// Entire address space
+    pub fn get_ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    /// Records accesses of the given kinds to `range`, on top of earlier watches
+    pub fn watch(&mut self, range: RangeInclusive<usize>, accesses: &[Access]) {
+        if self.watches.is_empty() {
+            self.watches = vec![0; self.ram.len()];
+        }
+        let mask = accesses.iter().fold(0, |mask, access| mask | access.bit());
+        let end = (*range.end()).min(self.ram.len().saturating_sub(1));
+        for watch in self.watches.iter_mut().take(end + 1).skip(*range.start()) {
+            *watch |= mask;
+        }
+    }
+
+    /// Removes all watches and any accesses not yet taken
+    pub fn clear_watches(&mut self) {
+        self.watches = Vec::new();
+        self.accesses.get_mut().clear();
+    }
+
+    /// Accesses to watched addresses since the last call, oldest first
+    pub fn take_accesses(&self) -> Vec<MemoryAccess> {
+        self.accesses.take()
+    }
+
+    /// Moves the watches of another memory over, for restoring save states
+    pub(crate) fn take_watches(&mut self, other: &mut Memory) {
+        self.watches = std::mem::take(&mut other.watches);
+    }
+
+    fn record(&self, address: usize, access: Access, value: u8) {
+        if let Some(&mask) = self.watches.get(address) {
+            if mask & access.bit() != 0 {
+                self.accesses.borrow_mut().push(MemoryAccess { address, access, value });
+            }
+        }
+    }
+
+    /// Serializes RAM, timers and audio registers
+    pub(crate) fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u32(self.ram.len() as u32);
+        writer.write_bytes(&self.ram);
+        writer.write_u8(self.delay_timer);
+        writer.write_u8(self.sound_timer);
+        writer.write_bool(self.audio_pattern.is_some());
+        writer.write_bytes(&self.audio_pattern.unwrap_or_default());
+        writer.write_u8(self.pitch);
+    }
+
+    pub(crate) fn load_state(&mut self, reader: &mut StateReader) -> Result<(), Chip8Error> {
+        if reader.read_u32()? as usize != self.ram.len() {
+            return Err(invalid("memory size does not match"));
+        }
+        let len = self.ram.len();
+        self.ram.copy_from_slice(reader.read_bytes(len)?);
+        self.delay_timer = reader.read_u8()?;
+        self.sound_timer = reader.read_u8()?;
+        let has_pattern = reader.read_bool()?;
+        let pattern = reader.read_bytes(AUDIO_PATTERN_SIZE)?.try_into().unwrap();
+        self.audio_pattern = has_pattern.then_some(pattern);
+        self.pitch = reader.read_u8()?;
+        Ok(())
+    }
+
+    /// Largest program that fits between the start address and the end of memory
+    pub fn max_program_size(&self) -> usize {
+        self.ram.len() - START_ADDRESS as usize
+    }
+
+    /// Resets memory and copies a program to the start address. A program
+    /// too large for memory is rejected with memory left as it was.
+    pub fn load_program(&mut self, rom: &[u8]) -> Result<(), Chip8Error> {
+        let max = self.max_program_size();
+        if rom.len() > max {
+            return Err(Chip8Error::RomTooLarge { size: rom.len(), max });
+        }
+        self.reset();
+        let start = START_ADDRESS as usize;
+        self.ram[start..start + rom.len()].copy_from_slice(rom);
+        Ok(())
+    }
+
+    /// Sets whether out of range addresses wrap around instead of faulting
+    pub fn set_wrap(&mut self, wrap: bool) {
+        self.wrap = wrap;
+    }
+
+    /// Maps an address onto RAM, wrapping it or reporting it as out of bounds
+    fn resolve(&self, addr: usize) -> Result<usize, Chip8Error> {
+        if addr < self.ram.len() {
+            Ok(addr)
+        } else if self.wrap {
+            Ok(addr % self.ram.len())
+        } else {
+            Err(Chip8Error::MemoryOutOfBounds { addr })
+        }
     }
 
     /// Fetch byte
-    pub fn fetch_byte(&self, index: u16) -> u8 {
-        self.ram[index as usize]
+    pub fn fetch_byte(&self, index: usize) -> Result<u8, Chip8Error> {
+        let addr = self.resolve(index)?;
+        self.record(addr, Access::Read, self.ram[addr]);
+        Ok(self.ram[addr])
+    }
+
+    /// Stores byte at index, without reporting it to watches
+    pub fn store_byte(&mut self, index: usize, value: u8) -> Result<(), Chip8Error> {
+        let addr = self.resolve(index)?;
+        self.ram[addr] = value;
+        Ok(())
+    }
+
+    /// Fetches word at index, without reporting it to watches
+    pub fn fetch_word(&self, index: u16) -> Result<u16, Chip8Error> {
+        let index = index as usize;
+        Ok((self.ram[self.resolve(index)?] as u16) << 8 | (self.ram[self.resolve(index + 1)?] as u16))
+    }
+
+    /// Fetches the instruction word at index
+    pub fn fetch_instruction(&self, index: u16) -> Result<u16, Chip8Error> {
+        let operation = self.fetch_word(index)?;
+        if !self.watches.is_empty() {
+            let [high, low] = operation.to_be_bytes();
+            self.record(self.resolve(index as usize)?, Access::Fetch, high);
+            self.record(self.resolve(index as usize + 1)?, Access::Fetch, low);
+        }
+        Ok(operation)
     }
 
-    /// Fetches word at index
-    pub fn fetch_word(&self, index: u16) -> u16 {
-        (self.ram[index as usize] as u16) << 8 | (self.ram[(index + 1) as usize] as u16)
+    /// Writes a byte, reporting it to watches
+    fn write(&mut self, addr: usize, value: u8) {
+        self.ram[addr] = value;
+        self.record(addr, Access::Write, value);
     }
 
     pub fn get_delay_timer(&self) -> u8 {
         self.delay_timer
     }
 
-    pub fn op_ld_dt(&mut self, x: usize) {
-        self.delay_timer = self.emulator.get_mut().get_cpu().get_register_value(x);
+    pub fn get_sound_timer(&self) -> u8 {
+        self.sound_timer
+    }
+
+    pub fn set_delay_timer(&mut self, value: u8) {
+        self.delay_timer = value;
+    }
+
+    pub fn set_sound_timer(&mut self, value: u8) {
+        self.sound_timer = value;
+    }
+
+    /// XO-CHIP audio pattern, if a program has loaded one
+    pub fn get_audio_pattern(&self) -> Option<&[u8; AUDIO_PATTERN_SIZE]> {
+        self.audio_pattern.as_ref()
+    }
+
+    pub fn get_pitch(&self) -> u8 {
+        self.pitch
+    }
+
+    pub fn op_ld_dt(&mut self, cpu: &CPU, x: usize) {
+        self.delay_timer = cpu.get_register_value(x);
     }
 
-    pub fn op_ld_st(&mut self, x: usize) {
-        self.sound_timer = self.emulator.get_mut().get_cpu().get_register_value(x);
+    pub fn op_ld_st(&mut self, cpu: &CPU, x: usize) {
+        self.sound_timer = cpu.get_register_value(x);
     }
 
-    pub fn op_ld_bcd(&mut self, x: usize) {
-        let vx = self.emulator.get_mut().get_cpu().get_register_value(x) as f32;
+    pub fn op_ld_bcd(&mut self, cpu: &CPU, x: usize) -> Result<(), Chip8Error> {
+        let vx = cpu.get_register_value(x) as f32;
 
         // Fetch the hundreds digit by dividing by 100 and tossing the decimal
         let hundreds = (vx / 100.0).floor() as u8;
@@ -61,45 +258,105 @@ impl Memory {
         // Fetch the ones digit by tossing the hundreds and the tens
         let ones = (vx % 10.0) as u8;
 
-        let i_reg = self.emulator.get_mut().get_cpu().get_i_register();
-        self.ram[i_reg as usize] = hundreds;
-        self.ram[(i_reg + 1) as usize] = tens;
-        self.ram[(i_reg + 2) as usize] = ones;
+        let i = cpu.get_i_register() as usize;
+        // Validate the whole range up front so a fault leaves memory untouched
+        self.resolve(i + 2)?;
+        for (idx, digit) in [hundreds, tens, ones].into_iter().enumerate() {
+            let addr = self.resolve(i + idx)?;
+            self.write(addr, digit);
+        }
+        Ok(())
     }
 
-    pub fn op_str(&mut self, x: usize) {
-        let i = self.emulator.get_mut().get_cpu().get_i_register() as usize;
+    /// STR V0 - VX into I, optionally leaving I past the last byte written
+    pub fn op_str(&mut self, cpu: &mut CPU, x: usize, increment_i: bool) -> Result<(), Chip8Error> {
+        let i = cpu.get_i_register() as usize;
+        self.resolve(i + x)?;
         for idx in 0..=x {
-            self.ram[i + idx] = self.emulator.get_mut().get_cpu().get_register_value(idx);
+            let addr = self.resolve(i + idx)?;
+            self.write(addr, cpu.get_register_value(idx));
+        }
+        if increment_i {
+            cpu.set_i_register(cpu.get_i_register().wrapping_add(x as u16 + 1));
         }
+        Ok(())
     }
 
-    pub fn op_ld(&mut self, x: usize) {
-        let i = self.emulator.get_mut().get_cpu().get_i_register() as usize;
+    /// LD I into V0 - VX, optionally leaving I past the last byte read
+    pub fn op_ld(&self, cpu: &mut CPU, x: usize, increment_i: bool) -> Result<(), Chip8Error> {
+        let i = cpu.get_i_register() as usize;
+        self.resolve(i + x)?;
         for idx in 0..=x {
-            self.emulator.get_mut().get_cpu().set_register_value(idx, self.ram[i + idx]);
+            cpu.set_register_value(idx, self.fetch_byte(i + idx)?);
         }
+        if increment_i {
+            cpu.set_i_register(cpu.get_i_register().wrapping_add(x as u16 + 1));
+        }
+        Ok(())
     }
 
-    pub fn tick_timers(&mut self) {
-        if self.delay_timer > 0 {
-            self.delay_timer -= 1;
+    /// SAVE VX - VY - Store a register range at I, leaving I unchanged
+    pub fn op_str_range(&mut self, cpu: &CPU, x: usize, y: usize) -> Result<(), Chip8Error> {
+        let i = cpu.get_i_register() as usize;
+        let len = x.abs_diff(y);
+        self.resolve(i + len)?;
+        for offset in 0..=len {
+            let register = if x <= y { x + offset } else { x - offset };
+            let addr = self.resolve(i + offset)?;
+            self.write(addr, cpu.get_register_value(register));
         }
+        Ok(())
+    }
 
-        if self.sound_timer > 0 {
-            if self.sound_timer == 1 {
-                unimplemented!("Beeping not implemented yet.")
-            }
-            self.sound_timer -= 1;
+    /// LOAD VX - VY - Load a register range from I, leaving I unchanged
+    pub fn op_ld_range(&self, cpu: &mut CPU, x: usize, y: usize) -> Result<(), Chip8Error> {
+        let i = cpu.get_i_register() as usize;
+        let len = x.abs_diff(y);
+        self.resolve(i + len)?;
+        for offset in 0..=len {
+            let register = if x <= y { x + offset } else { x - offset };
+            cpu.set_register_value(register, self.fetch_byte(i + offset)?);
+        }
+        Ok(())
+    }
+
+    /// AUDIO - Load the 16 byte audio pattern from I
+    pub fn op_ld_audio(&mut self, cpu: &CPU) -> Result<(), Chip8Error> {
+        let i = cpu.get_i_register() as usize;
+        let mut pattern = [0; AUDIO_PATTERN_SIZE];
+        for (offset, byte) in pattern.iter_mut().enumerate() {
+            *byte = self.fetch_byte(i + offset)?;
         }
+        self.audio_pattern = Some(pattern);
+        Ok(())
+    }
+
+    /// PITCH = VX - Set the audio pattern playback rate
+    pub fn op_ld_pitch(&mut self, cpu: &CPU, x: usize) {
+        self.pitch = cpu.get_register_value(x);
+    }
+
+    /// Decrements the delay and sound timers, called once per 60 Hz frame
+    pub fn tick_timers(&mut self) {
+        self.delay_timer = self.delay_timer.saturating_sub(1);
+        self.sound_timer = self.sound_timer.saturating_sub(1);
+    }
+}
+
+impl Default for Memory {
+    fn default() -> Self {
+        Self::new(RAM_SIZE)
     }
 }
 
 impl EmulatorComponent for Memory {
     fn reset(&mut self) {
-        self.ram = [0; RAM_SIZE];
+        self.ram.fill(0);
         self.delay_timer = 0;
         self.sound_timer = 0;
+        self.audio_pattern = None;
+        self.pitch = DEFAULT_PITCH;
+        self.accesses.get_mut().clear();
         self.initialize_font_set();
     }
 }
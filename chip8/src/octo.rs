@@ -0,0 +1,940 @@
+//! Compiler for the Octo assembly language.
+//!
+//! Supports the Octo statement set including the SUPER-CHIP and XO-CHIP
+//! extensions, structured control flow (`if`/`then`, `if`/`begin`/`else`/
+//! `end`, `loop`/`while`/`again`) and the `:alias`, `:const`, `:macro`,
+//! `:calc`, `:byte`, `:org`, `:next`, `:unpack` and `:assert` directives.
+//!
+//! Execution starts at the `main` label: the start address holds a
+//! `jump main` and the program itself is placed after it.
+
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::path::Path;
+use crate::asm::AsmError;
+use crate::cpu::START_ADDRESS;
+use crate::instruction::Instruction;
+
+/// Macro expansions allowed per program, to stop runaway recursion
+const MAX_MACRO_EXPANSIONS: usize = 10_000;
+/// Deepest nesting of parentheses and unary operators in a `:calc`
+/// expression, so evaluating it can't overflow the stack
+const MAX_CALC_DEPTH: usize = 256;
+
+/// Compiles Octo source into a ROM loadable at the program start address
+pub fn compile(source: &str) -> Result<Vec<u8>, AsmError> {
+    Compiler::new("<input>", source).run()
+}
+
+/// Compiles an Octo source file
+pub fn compile_file<P: AsRef<Path>>(path: P) -> Result<Vec<u8>, AsmError> {
+    let name = path.as_ref().display().to_string();
+    let source = fs::read_to_string(&path).map_err(|err| AsmError {
+        file: name.clone(),
+        line: 0,
+        column: 0,
+        message: format!("cannot read file: {err}"),
+    })?;
+    Compiler::new(&name, &source).run()
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Token {
+    text: String,
+    line: usize,
+    column: usize,
+}
+
+/// Splits source into whitespace separated tokens, dropping `#` comments.
+/// Quoted strings are kept together as one token.
+fn tokenize(source: &str) -> VecDeque<Token> {
+    let mut tokens = VecDeque::new();
+    for (index, line) in source.lines().enumerate() {
+        let chars: Vec<char> = line.chars().collect();
+        let mut pos = 0;
+        while pos < chars.len() {
+            if chars[pos].is_whitespace() {
+                pos += 1;
+                continue;
+            }
+            if chars[pos] == '#' {
+                break;
+            }
+            let start = pos;
+            if chars[pos] == '"' {
+                pos += 1;
+                while pos < chars.len() && chars[pos] != '"' {
+                    pos += 1;
+                }
+                pos = (pos + 1).min(chars.len());
+            } else {
+                while pos < chars.len() && !chars[pos].is_whitespace() {
+                    pos += 1;
+                }
+            }
+            tokens.push_back(Token {
+                text: chars[start..pos].iter().collect(),
+                line: index + 1,
+                column: start + 1,
+            });
+        }
+    }
+    tokens
+}
+
+/// Parses decimal, `0x` hex and `0b` binary literals, optionally negative
+fn parse_number(text: &str) -> Option<i64> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(binary) = digits.strip_prefix("0b").or_else(|| digits.strip_prefix("0B")) {
+        i64::from_str_radix(binary, 2).ok()?
+    } else if digits.starts_with(|c: char| c.is_ascii_digit()) {
+        digits.parse().ok()?
+    } else {
+        return None;
+    };
+    Some(if negative { -value } else { value })
+}
+
+/// Register named `v0` - `vf`
+fn parse_register(text: &str) -> Option<u8> {
+    let digit = text.strip_prefix('v').or_else(|| text.strip_prefix('V'))?;
+    if digit.len() != 1 {
+        return None;
+    }
+    u8::from_str_radix(digit, 16).ok()
+}
+
+/// Where a label's address is patched in once it is defined
+#[derive(Debug, Clone, Copy)]
+enum FixupKind {
+    /// Low 12 bits of an instruction word
+    Address,
+    /// A whole word, for `i := long`
+    Long,
+    /// Byte operand holding a nibble and the high address bits, for `:unpack`
+    UnpackHigh(u8),
+    /// Byte operand holding the low address bits, for `:unpack`
+    UnpackLow,
+}
+
+struct Fixup {
+    address: u32,
+    kind: FixupKind,
+    label: String,
+    token: Token,
+}
+
+/// Open control flow block
+enum Block {
+    /// `if ... begin`, holding the address of the jump to patch at `else` or `end`
+    If { jump: u32, token: Token },
+    /// `loop`, holding the start and the jumps out of `while`s
+    Loop { start: u32, exits: Vec<u32>, token: Token },
+}
+
+/// Operand of a jump, call or `i :=`
+enum Target {
+    Resolved(u16),
+    Label(String),
+}
+
+/// Condition of `if` or `while`
+enum Condition {
+    Equal { x: u8, rhs: Operand, equal: bool },
+    Key { x: u8, pressed: bool },
+    /// `<`, `>`, `<=` or `>=`, computed through VF
+    Compare { x: u8, rhs: Operand, op: String },
+}
+
+enum Operand {
+    Register(u8),
+    Byte(u8),
+}
+
+struct Macro {
+    params: Vec<String>,
+    body: Vec<Token>,
+}
+
+struct Compiler {
+    file: String,
+    tokens: VecDeque<Token>,
+    /// Program bytes starting at the program start address
+    rom: Vec<u8>,
+    here: u32,
+    labels: HashMap<String, u16>,
+    constants: HashMap<String, f64>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    fixups: Vec<Fixup>,
+    blocks: Vec<Block>,
+    /// Label placed on the operand of the next instruction by `:next`
+    next_label: Option<(String, Token)>,
+    expansions: usize,
+    /// End of the input, for errors about missing tokens
+    end: Token,
+}
+
+impl Compiler {
+    fn new(file: &str, source: &str) -> Self {
+        let line_count = source.lines().count().max(1);
+        let last_column = source.lines().last().map_or(0, |line| line.chars().count()) + 1;
+        Self {
+            file: file.to_string(),
+            tokens: tokenize(source),
+            rom: Vec::new(),
+            here: START_ADDRESS as u32 + 2,
+            labels: HashMap::new(),
+            constants: HashMap::new(),
+            aliases: HashMap::new(),
+            macros: HashMap::new(),
+            fixups: Vec::new(),
+            blocks: Vec::new(),
+            next_label: None,
+            expansions: 0,
+            end: Token { text: String::new(), line: line_count, column: last_column },
+        }
+    }
+
+    fn error(&self, token: &Token, message: impl Into<String>) -> AsmError {
+        AsmError {
+            file: self.file.clone(),
+            line: token.line,
+            column: token.column,
+            message: message.into(),
+        }
+    }
+
+    fn next(&mut self) -> Result<Token, AsmError> {
+        self.tokens.pop_front().ok_or_else(|| self.error(&self.end, "unexpected end of file"))
+    }
+
+    fn peek_is(&self, text: &str) -> bool {
+        self.tokens.front().is_some_and(|token| token.text == text)
+    }
+
+    fn expect(&mut self, text: &str) -> Result<Token, AsmError> {
+        let token = self.next()?;
+        if token.text != text {
+            return Err(self.error(&token, format!("expected '{text}', found '{}'", token.text)));
+        }
+        Ok(token)
+    }
+
+    fn run(mut self) -> Result<Vec<u8>, AsmError> {
+        while let Some(token) = self.tokens.pop_front() {
+            self.statement(token)?;
+        }
+        if let Some(block) = self.blocks.last() {
+            let (token, message) = match block {
+                Block::If { token, .. } => (token, "'if' without 'end'"),
+                Block::Loop { token, .. } => (token, "'loop' without 'again'"),
+            };
+            return Err(self.error(token, message));
+        }
+        if let Some((_, token)) = &self.next_label {
+            return Err(self.error(token, "':next' without a following instruction"));
+        }
+        let Some(&main) = self.labels.get("main") else {
+            return Err(self.error(&self.end, "program has no 'main' label"));
+        };
+        for fixup in std::mem::take(&mut self.fixups) {
+            let Some(&address) = self.labels.get(&fixup.label) else {
+                return Err(self.error(&fixup.token, format!("undefined name '{}'", fixup.label)));
+            };
+            self.patch(fixup.address, fixup.kind, address);
+        }
+        self.write(START_ADDRESS as u32, &Instruction::Jump(main).encode().to_be_bytes());
+        Ok(self.rom)
+    }
+
+    fn write(&mut self, address: u32, bytes: &[u8]) {
+        let offset = (address - START_ADDRESS as u32) as usize;
+        if self.rom.len() < offset + bytes.len() {
+            self.rom.resize(offset + bytes.len(), 0);
+        }
+        self.rom[offset..offset + bytes.len()].copy_from_slice(bytes);
+    }
+
+    fn emit_byte(&mut self, token: &Token, byte: u8) -> Result<(), AsmError> {
+        if self.here > 0xFFFF {
+            return Err(self.error(token, "program does not fit in 64 KiB"));
+        }
+        self.write(self.here, &[byte]);
+        self.here += 1;
+        Ok(())
+    }
+
+    fn emit(&mut self, token: &Token, instruction: Instruction) -> Result<(), AsmError> {
+        if let Some((label, label_token)) = self.next_label.take() {
+            self.define_label(&label_token, label, self.here + 1)?;
+        }
+        let [high, low] = instruction.encode().to_be_bytes();
+        self.emit_byte(token, high)?;
+        self.emit_byte(token, low)
+    }
+
+    fn patch(&mut self, address: u32, kind: FixupKind, value: u16) {
+        let offset = (address - START_ADDRESS as u32) as usize;
+        match kind {
+            FixupKind::Address => {
+                self.rom[offset] = (self.rom[offset] & 0xF0) | (value >> 8) as u8 & 0x0F;
+                self.rom[offset + 1] = value as u8;
+            }
+            FixupKind::Long => self.rom[offset..offset + 2].copy_from_slice(&value.to_be_bytes()),
+            FixupKind::UnpackHigh(nibble) => self.rom[offset] = nibble << 4 | (value >> 8) as u8 & 0x0F,
+            FixupKind::UnpackLow => self.rom[offset] = value as u8,
+        }
+    }
+
+    fn define_label(&mut self, token: &Token, name: String, address: u32) -> Result<(), AsmError> {
+        self.check_name(token, &name)?;
+        if self.labels.contains_key(&name) {
+            return Err(self.error(token, format!("label '{name}' is already defined")));
+        }
+        self.labels.insert(name, address as u16);
+        Ok(())
+    }
+
+    /// Rejects names that would shadow registers or numbers
+    fn check_name(&self, token: &Token, name: &str) -> Result<(), AsmError> {
+        if parse_register(name).is_some() || parse_number(name).is_some() || name.is_empty() {
+            return Err(self.error(token, format!("'{name}' can't be used as a name")));
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self, token: Token) -> Result<(), AsmError> {
+        match token.text.as_str() {
+            ":" => {
+                let name = self.next()?;
+                self.define_label(&name, name.text.clone(), self.here)?;
+            }
+            ":next" => {
+                let name = self.next()?;
+                self.next_label = Some((name.text.clone(), name));
+            }
+            ":const" => {
+                let name = self.next()?;
+                self.check_name(&name, &name.text)?;
+                let value = self.number()?;
+                self.constants.insert(name.text, value as f64);
+            }
+            ":alias" => {
+                let name = self.next()?;
+                self.check_name(&name, &name.text)?;
+                let register = self.register()?;
+                self.aliases.insert(name.text, register);
+            }
+            ":calc" => {
+                let name = self.next()?;
+                self.check_name(&name, &name.text)?;
+                let value = self.calc_block()?;
+                self.constants.insert(name.text, value);
+            }
+            ":byte" => {
+                let value = if self.peek_is("{") { self.calc_block()? as i64 } else { self.number()? };
+                let byte = self.fit_byte(&token, value)?;
+                self.emit_byte(&token, byte)?;
+            }
+            ":org" => {
+                let value = self.number()?;
+                if !(START_ADDRESS as i64..=0xFFFF).contains(&value) {
+                    return Err(self.error(&token, format!("':org' address {value:#X} is outside the program area")));
+                }
+                self.here = value as u32;
+            }
+            ":call" => {
+                let target = self.target()?;
+                self.emit_target(&token, Instruction::Call(0), target)?;
+            }
+            ":unpack" => {
+                let nibble = self.number()?;
+                if !(0..=0xF).contains(&nibble) {
+                    return Err(self.error(&token, "':unpack' expects a nibble"));
+                }
+                let target = self.target()?;
+                let (high, low) = match target {
+                    Target::Resolved(address) => ((nibble as u8) << 4 | (address >> 8) as u8, address as u8),
+                    Target::Label(ref label) => {
+                        for (offset, kind) in [(1, FixupKind::UnpackHigh(nibble as u8)), (3, FixupKind::UnpackLow)] {
+                            let address = self.here + offset;
+                            self.fixups.push(Fixup { address, kind, label: label.clone(), token: token.clone() });
+                        }
+                        (0, 0)
+                    }
+                };
+                self.emit(&token, Instruction::SetImm { x: 0, nn: high })?;
+                self.emit(&token, Instruction::SetImm { x: 1, nn: low })?;
+            }
+            ":macro" => self.define_macro()?,
+            ":assert" => {
+                let message = if self.tokens.front().is_some_and(|next| next.text.starts_with('"')) {
+                    self.next()?.text.trim_matches('"').to_string()
+                } else {
+                    "assertion failed".to_string()
+                };
+                if self.calc_block()? == 0.0 {
+                    return Err(self.error(&token, message));
+                }
+            }
+            ":proto" => {
+                self.next()?;
+            }
+            ":breakpoint" => {
+                self.next()?;
+            }
+            ":monitor" => {
+                self.next()?;
+                self.next()?;
+            }
+            "return" | ";" => self.emit(&token, Instruction::Return)?,
+            "clear" => self.emit(&token, Instruction::Clear)?,
+            "hires" => self.emit(&token, Instruction::Hires)?,
+            "lores" => self.emit(&token, Instruction::Lores)?,
+            "exit" => self.emit(&token, Instruction::Exit)?,
+            "scroll-left" => self.emit(&token, Instruction::ScrollLeft)?,
+            "scroll-right" => self.emit(&token, Instruction::ScrollRight)?,
+            "scroll-down" => {
+                let n = self.nibble()?;
+                self.emit(&token, Instruction::ScrollDown(n))?;
+            }
+            "scroll-up" => {
+                let n = self.nibble()?;
+                self.emit(&token, Instruction::ScrollUp(n))?;
+            }
+            "audio" => self.emit(&token, Instruction::Audio)?,
+            "plane" => {
+                let n = self.nibble()?;
+                self.emit(&token, Instruction::Plane(n))?;
+            }
+            "bcd" => {
+                let x = self.register()?;
+                self.emit(&token, Instruction::Bcd(x))?;
+            }
+            "saveflags" => {
+                let x = self.register()?;
+                self.emit(&token, Instruction::StoreFlags(x))?;
+            }
+            "loadflags" => {
+                let x = self.register()?;
+                self.emit(&token, Instruction::LoadFlags(x))?;
+            }
+            "save" | "load" => {
+                let x = self.register()?;
+                let instruction = if self.peek_is("-") {
+                    self.next()?;
+                    let y = self.register()?;
+                    if token.text == "save" { Instruction::SaveRange { x, y } } else { Instruction::LoadRange { x, y } }
+                } else if token.text == "save" {
+                    Instruction::Store(x)
+                } else {
+                    Instruction::Load(x)
+                };
+                self.emit(&token, instruction)?;
+            }
+            "sprite" => {
+                let x = self.register()?;
+                let y = self.register()?;
+                let n = self.nibble()?;
+                self.emit(&token, Instruction::Draw { x, y, n })?;
+            }
+            "jump" => {
+                let target = self.target()?;
+                self.emit_target(&token, Instruction::Jump(0), target)?;
+            }
+            "jump0" => {
+                let target = self.target()?;
+                self.emit_target(&token, Instruction::JumpOffset(0), target)?;
+            }
+            "native" => {
+                let target = self.target()?;
+                self.emit_target(&token, Instruction::Sys(0), target)?;
+            }
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let x = self.register()?;
+                let instruction = match token.text.as_str() {
+                    "delay" => Instruction::SetDelay(x),
+                    "buzzer" => Instruction::SetSound(x),
+                    _ => Instruction::Pitch(x),
+                };
+                self.emit(&token, instruction)?;
+            }
+            "i" => self.index_statement(&token)?,
+            "if" => self.if_statement(&token)?,
+            "else" => {
+                let Some(Block::If { jump, .. }) = self.blocks.pop() else {
+                    return Err(self.error(&token, "'else' without 'if ... begin'"));
+                };
+                let else_jump = self.here;
+                self.emit(&token, Instruction::Jump(0))?;
+                self.patch(jump, FixupKind::Address, self.here as u16);
+                self.blocks.push(Block::If { jump: else_jump, token });
+            }
+            "end" => {
+                let Some(Block::If { jump, .. }) = self.blocks.pop() else {
+                    return Err(self.error(&token, "'end' without 'if ... begin'"));
+                };
+                self.patch(jump, FixupKind::Address, self.here as u16);
+            }
+            "loop" => self.blocks.push(Block::Loop { start: self.here, exits: Vec::new(), token }),
+            "while" => {
+                let condition = self.condition()?;
+                // Leave the loop unless the condition holds
+                self.emit_skip(&token, condition, true)?;
+                let exit = self.here;
+                self.emit(&token, Instruction::Jump(0))?;
+                match self.blocks.iter_mut().rev().find(|block| matches!(block, Block::Loop { .. })) {
+                    Some(Block::Loop { exits, .. }) => exits.push(exit),
+                    _ => return Err(self.error(&token, "'while' outside of a loop")),
+                }
+            }
+            "again" => {
+                let Some(Block::Loop { start, exits, .. }) = self.blocks.pop() else {
+                    return Err(self.error(&token, "'again' without 'loop'"));
+                };
+                self.emit(&token, Instruction::Jump(start as u16))?;
+                for exit in exits {
+                    self.patch(exit, FixupKind::Address, self.here as u16);
+                }
+            }
+            _ => {
+                if let Some(x) = self.lookup_register(&token.text) {
+                    return self.register_statement(&token, x);
+                }
+                if self.macros.contains_key(&token.text) {
+                    return self.expand_macro(&token);
+                }
+                if let Some(value) = parse_number(&token.text).or_else(|| self.constants.get(&token.text).map(|&value| value as i64)) {
+                    // Bare numbers are data bytes
+                    let byte = self.fit_byte(&token, value)?;
+                    return self.emit_byte(&token, byte);
+                }
+                if token.text.starts_with(':') || token.text.starts_with('"') {
+                    return Err(self.error(&token, format!("unknown directive '{}'", token.text)));
+                }
+                // Anything else is a subroutine call
+                let target = self.resolve_target(&token)?;
+                self.emit_target(&token, Instruction::Call(0), target)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// `i := nnn`, `i := hex vx`, `i := bighex vx`, `i := long nnnn` and `i += vx`
+    fn index_statement(&mut self, token: &Token) -> Result<(), AsmError> {
+        let op = self.next()?;
+        match op.text.as_str() {
+            "+=" => {
+                let x = self.register()?;
+                self.emit(token, Instruction::AddI(x))
+            }
+            ":=" if self.peek_is("hex") || self.peek_is("bighex") => {
+                let big = self.next()?.text == "bighex";
+                let x = self.register()?;
+                self.emit(token, if big { Instruction::BigFont(x) } else { Instruction::Font(x) })
+            }
+            ":=" if self.peek_is("long") => {
+                self.next()?;
+                let target = self.target_with_range(0xFFFF)?;
+                self.emit(token, Instruction::SetILong)?;
+                let address = match target {
+                    Target::Resolved(address) => address,
+                    Target::Label(label) => {
+                        self.fixups.push(Fixup { address: self.here, kind: FixupKind::Long, label, token: token.clone() });
+                        0
+                    }
+                };
+                let [high, low] = address.to_be_bytes();
+                self.emit_byte(token, high)?;
+                self.emit_byte(token, low)
+            }
+            ":=" => {
+                let target = self.target()?;
+                self.emit_target(token, Instruction::SetI(0), target)
+            }
+            _ => Err(self.error(&op, format!("unknown operator 'i {}'", op.text))),
+        }
+    }
+
+    /// Statements starting with a register, `vx := ...`, `vx += ...` and so on
+    fn register_statement(&mut self, token: &Token, x: u8) -> Result<(), AsmError> {
+        let op = self.next()?;
+        let instruction = match op.text.as_str() {
+            ":=" if self.peek_is("random") => {
+                self.next()?;
+                Instruction::Random { x, nn: self.byte()? }
+            }
+            ":=" if self.peek_is("key") => {
+                self.next()?;
+                Instruction::WaitKey(x)
+            }
+            ":=" if self.peek_is("delay") => {
+                self.next()?;
+                Instruction::GetDelay(x)
+            }
+            ":=" => match self.operand()? {
+                Operand::Register(y) => Instruction::Set { x, y },
+                Operand::Byte(nn) => Instruction::SetImm { x, nn },
+            },
+            "+=" => match self.operand()? {
+                Operand::Register(y) => Instruction::Add { x, y },
+                Operand::Byte(nn) => Instruction::AddImm { x, nn },
+            },
+            "-=" => match self.operand()? {
+                Operand::Register(y) => Instruction::Sub { x, y },
+                Operand::Byte(nn) => Instruction::AddImm { x, nn: nn.wrapping_neg() },
+            },
+            "=-" => Instruction::SubReverse { x, y: self.register()? },
+            "|=" => Instruction::Or { x, y: self.register()? },
+            "&=" => Instruction::And { x, y: self.register()? },
+            "^=" => Instruction::Xor { x, y: self.register()? },
+            ">>=" => Instruction::ShiftRight { x, y: self.register()? },
+            "<<=" => Instruction::ShiftLeft { x, y: self.register()? },
+            _ => return Err(self.error(&op, format!("unknown operator '{}'", op.text))),
+        };
+        self.emit(token, instruction)
+    }
+
+    fn if_statement(&mut self, token: &Token) -> Result<(), AsmError> {
+        let condition = self.condition()?;
+        let keyword = self.next()?;
+        match keyword.text.as_str() {
+            // The next statement runs when the condition holds
+            "then" => self.emit_skip(token, condition, false),
+            "begin" => {
+                self.emit_skip(token, condition, true)?;
+                let jump = self.here;
+                self.emit(token, Instruction::Jump(0))?;
+                self.blocks.push(Block::If { jump, token: token.clone() });
+                Ok(())
+            }
+            _ => Err(self.error(&keyword, format!("expected 'then' or 'begin', found '{}'", keyword.text))),
+        }
+    }
+
+    fn condition(&mut self) -> Result<Condition, AsmError> {
+        let x = self.register()?;
+        let op = self.next()?;
+        match op.text.as_str() {
+            "key" => Ok(Condition::Key { x, pressed: true }),
+            "-key" => Ok(Condition::Key { x, pressed: false }),
+            "==" | "!=" => Ok(Condition::Equal { x, rhs: self.operand()?, equal: op.text == "==" }),
+            "<" | ">" | "<=" | ">=" => Ok(Condition::Compare { x, rhs: self.operand()?, op: op.text }),
+            _ => Err(self.error(&op, format!("unknown comparison '{}'", op.text))),
+        }
+    }
+
+    /// Emits a skip over the next instruction, taken when the condition is
+    /// `skip_when` true
+    fn emit_skip(&mut self, token: &Token, condition: Condition, skip_when: bool) -> Result<(), AsmError> {
+        let instruction = match condition {
+            Condition::Equal { x, rhs, equal } => match (rhs, equal == skip_when) {
+                (Operand::Byte(nn), true) => Instruction::SkipEqImm { x, nn },
+                (Operand::Byte(nn), false) => Instruction::SkipNeImm { x, nn },
+                (Operand::Register(y), true) => Instruction::SkipEq { x, y },
+                (Operand::Register(y), false) => Instruction::SkipNe { x, y },
+            },
+            Condition::Key { x, pressed } if pressed == skip_when => Instruction::SkipKey(x),
+            Condition::Key { x, .. } => Instruction::SkipNotKey(x),
+            Condition::Compare { x, rhs, op } => {
+                // VF = 1 when no borrow: after `vf =- vx` when VX >= rhs,
+                // after `vf -= vx` when rhs >= VX
+                match rhs {
+                    Operand::Register(y) => self.emit(token, Instruction::Set { x: 0xF, y })?,
+                    Operand::Byte(nn) => self.emit(token, Instruction::SetImm { x: 0xF, nn })?,
+                }
+                let (subtract, holds_when_set) = match op.as_str() {
+                    "<" => (Instruction::SubReverse { x: 0xF, y: x }, false),
+                    ">=" => (Instruction::SubReverse { x: 0xF, y: x }, true),
+                    ">" => (Instruction::Sub { x: 0xF, y: x }, false),
+                    _ => (Instruction::Sub { x: 0xF, y: x }, true),
+                };
+                self.emit(token, subtract)?;
+                let equal = Condition::Equal { x: 0xF, rhs: Operand::Byte(holds_when_set as u8), equal: true };
+                return self.emit_skip(token, equal, skip_when);
+            }
+        };
+        self.emit(token, instruction)
+    }
+
+    fn define_macro(&mut self) -> Result<(), AsmError> {
+        let name = self.next()?;
+        self.check_name(&name, &name.text)?;
+        let mut params = Vec::new();
+        loop {
+            let token = self.next()?;
+            if token.text == "{" {
+                break;
+            }
+            params.push(token.text);
+        }
+        let body = self.block_body()?;
+        self.macros.insert(name.text, Macro { params, body });
+        Ok(())
+    }
+
+    /// Tokens up to the `}` matching an already consumed `{`
+    fn block_body(&mut self) -> Result<Vec<Token>, AsmError> {
+        let mut body = Vec::new();
+        let mut depth = 0;
+        loop {
+            let token = self.next()?;
+            match token.text.as_str() {
+                "{" => depth += 1,
+                "}" if depth == 0 => return Ok(body),
+                "}" => depth -= 1,
+                _ => {}
+            }
+            body.push(token);
+        }
+    }
+
+    fn expand_macro(&mut self, token: &Token) -> Result<(), AsmError> {
+        self.expansions += 1;
+        if self.expansions > MAX_MACRO_EXPANSIONS {
+            return Err(self.error(token, "too many macro expansions"));
+        }
+        let params = self.macros[&token.text].params.clone();
+        let mut args = HashMap::new();
+        for param in params {
+            args.insert(param, self.next()?.text);
+        }
+        let body = &self.macros[&token.text].body;
+        // Expanded tokens keep the position of the invocation
+        let expanded: Vec<Token> = body
+            .iter()
+            .map(|body_token| Token {
+                text: args.get(&body_token.text).cloned().unwrap_or_else(|| body_token.text.clone()),
+                line: token.line,
+                column: token.column,
+            })
+            .collect();
+        for expanded_token in expanded.into_iter().rev() {
+            self.tokens.push_front(expanded_token);
+        }
+        Ok(())
+    }
+
+    /// `{ expression }`, evaluated right to left without precedence as in Octo
+    fn calc_block(&mut self) -> Result<f64, AsmError> {
+        let open = self.expect("{")?;
+        let body = self.block_body()?;
+        let mut pos = 0;
+        let value = self.calc_expr(&body, &mut pos, &open, 0)?;
+        if let Some(extra) = body.get(pos) {
+            return Err(self.error(extra, format!("unexpected '{}' in expression", extra.text)));
+        }
+        Ok(value)
+    }
+
+    fn calc_expr(&self, tokens: &[Token], pos: &mut usize, open: &Token, depth: usize) -> Result<f64, AsmError> {
+        let mut terms = vec![self.calc_term(tokens, pos, open, depth)?];
+        let mut ops = Vec::new();
+        while let Some(op) = tokens.get(*pos).filter(|token| token.text != ")") {
+            *pos += 1;
+            ops.push(op);
+            terms.push(self.calc_term(tokens, pos, open, depth)?);
+        }
+        // Operators apply right to left
+        let mut value = terms.pop().unwrap_or_default();
+        for (op, lhs) in ops.into_iter().zip(terms).rev() {
+            value = self.calc_binary(op, lhs, value)?;
+        }
+        Ok(value)
+    }
+
+    fn calc_binary(&self, op: &Token, lhs: f64, rhs: f64) -> Result<f64, AsmError> {
+        let truth = |value: bool| value as i32 as f64;
+        let shift = |shift: fn(i64, u32) -> Option<i64>| {
+            u32::try_from(rhs as i64)
+                .ok()
+                .and_then(|amount| shift(lhs as i64, amount))
+                .map(|value| value as f64)
+                .ok_or_else(|| self.error(op, format!("shift by {rhs} is out of range")))
+        };
+        Ok(match op.text.as_str() {
+            "+" => lhs + rhs,
+            "-" => lhs - rhs,
+            "*" => lhs * rhs,
+            "/" => lhs / rhs,
+            "%" => lhs % rhs,
+            "&" => (lhs as i64 & rhs as i64) as f64,
+            "|" => (lhs as i64 | rhs as i64) as f64,
+            "^" => (lhs as i64 ^ rhs as i64) as f64,
+            "<<" => shift(i64::checked_shl)?,
+            ">>" => shift(i64::checked_shr)?,
+            "pow" => lhs.powf(rhs),
+            "min" => lhs.min(rhs),
+            "max" => lhs.max(rhs),
+            "<" => truth(lhs < rhs),
+            ">" => truth(lhs > rhs),
+            "<=" => truth(lhs <= rhs),
+            ">=" => truth(lhs >= rhs),
+            "==" => truth(lhs == rhs),
+            "!=" => truth(lhs != rhs),
+            _ => return Err(self.error(op, format!("unknown operator '{}'", op.text))),
+        })
+    }
+
+    fn calc_term(&self, tokens: &[Token], pos: &mut usize, open: &Token, depth: usize) -> Result<f64, AsmError> {
+        let Some(token) = tokens.get(*pos) else {
+            return Err(self.error(open, "incomplete expression"));
+        };
+        if depth > MAX_CALC_DEPTH {
+            return Err(self.error(token, "expression nested too deeply"));
+        }
+        *pos += 1;
+        let nested = depth + 1;
+        let unary =
+            |f: fn(f64) -> f64, compiler: &Self, pos: &mut usize| Ok(f(compiler.calc_term(tokens, pos, open, nested)?));
+        match token.text.as_str() {
+            "(" => {
+                let value = self.calc_expr(tokens, pos, open, nested)?;
+                match tokens.get(*pos) {
+                    Some(close) if close.text == ")" => {
+                        *pos += 1;
+                        Ok(value)
+                    }
+                    _ => Err(self.error(token, "unclosed '('")),
+                }
+            }
+            "-" => unary(|value| -value, self, pos),
+            "~" => unary(|value| !(value as i64) as f64, self, pos),
+            "!" => unary(|value| (value == 0.0) as i32 as f64, self, pos),
+            "abs" => unary(f64::abs, self, pos),
+            "sqrt" => unary(f64::sqrt, self, pos),
+            "sin" => unary(f64::sin, self, pos),
+            "cos" => unary(f64::cos, self, pos),
+            "floor" => unary(f64::floor, self, pos),
+            "ceil" => unary(f64::ceil, self, pos),
+            "HERE" => Ok(self.here as f64),
+            "PI" => Ok(std::f64::consts::PI),
+            "E" => Ok(std::f64::consts::E),
+            text => {
+                if let Some(value) = parse_number(text) {
+                    Ok(value as f64)
+                } else if let Some(&value) = self.constants.get(text) {
+                    Ok(value)
+                } else if let Some(&address) = self.labels.get(text) {
+                    Ok(address as f64)
+                } else {
+                    Err(self.error(token, format!("undefined name '{text}'")))
+                }
+            }
+        }
+    }
+
+    fn lookup_register(&self, text: &str) -> Option<u8> {
+        parse_register(text).or_else(|| self.aliases.get(text).copied())
+    }
+
+    fn register(&mut self) -> Result<u8, AsmError> {
+        let token = self.next()?;
+        self.lookup_register(&token.text)
+            .ok_or_else(|| self.error(&token, format!("expected a register, found '{}'", token.text)))
+    }
+
+    /// Number literal or constant
+    fn number(&mut self) -> Result<i64, AsmError> {
+        let token = self.next()?;
+        self.number_value(&token)
+            .ok_or_else(|| self.error(&token, format!("expected a number, found '{}'", token.text)))
+    }
+
+    fn number_value(&self, token: &Token) -> Option<i64> {
+        parse_number(&token.text).or_else(|| self.constants.get(&token.text).map(|&value| value as i64))
+    }
+
+    fn fit_byte(&self, token: &Token, value: i64) -> Result<u8, AsmError> {
+        if !(-128..=255).contains(&value) {
+            return Err(self.error(token, format!("value {value} does not fit in a byte")));
+        }
+        Ok(value as u8)
+    }
+
+    fn byte(&mut self) -> Result<u8, AsmError> {
+        let token = self.next()?;
+        let value = self
+            .number_value(&token)
+            .ok_or_else(|| self.error(&token, format!("expected a number, found '{}'", token.text)))?;
+        self.fit_byte(&token, value)
+    }
+
+    fn nibble(&mut self) -> Result<u8, AsmError> {
+        let token = self.next()?;
+        match self.number_value(&token) {
+            Some(value @ 0..=15) => Ok(value as u8),
+            _ => Err(self.error(&token, format!("expected a number from 0 to 15, found '{}'", token.text))),
+        }
+    }
+
+    /// Register or byte
+    fn operand(&mut self) -> Result<Operand, AsmError> {
+        if let Some(x) = self.tokens.front().and_then(|token| self.lookup_register(&token.text)) {
+            self.next()?;
+            return Ok(Operand::Register(x));
+        }
+        Ok(Operand::Byte(self.byte()?))
+    }
+
+    fn target(&mut self) -> Result<Target, AsmError> {
+        self.target_with_range(0xFFF)
+    }
+
+    fn target_with_range(&mut self, max: i64) -> Result<Target, AsmError> {
+        let token = self.next()?;
+        match self.resolve_target(&token)? {
+            Target::Resolved(address) if address as i64 > max => {
+                Err(self.error(&token, format!("address {address:#X} is out of range")))
+            }
+            target => Ok(target),
+        }
+    }
+
+    fn resolve_target(&self, token: &Token) -> Result<Target, AsmError> {
+        if let Some(value) = self.number_value(token) {
+            if !(0..=0xFFFF).contains(&value) {
+                return Err(self.error(token, format!("address {value} is out of range")));
+            }
+            return Ok(Target::Resolved(value as u16));
+        }
+        if let Some(&address) = self.labels.get(&token.text) {
+            return Ok(Target::Resolved(address));
+        }
+        self.check_name(token, &token.text)?;
+        Ok(Target::Label(token.text.clone()))
+    }
+
+    /// Emits an instruction with a 12 bit address operand, patched later
+    /// for labels that aren't defined yet
+    fn emit_target(&mut self, token: &Token, instruction: Instruction, target: Target) -> Result<(), AsmError> {
+        let address = match target {
+            Target::Resolved(address) if address > 0xFFF => {
+                return Err(self.error(token, format!("address {address:#X} is out of range")));
+            }
+            Target::Resolved(address) => address,
+            Target::Label(label) => {
+                self.fixups.push(Fixup { address: self.here, kind: FixupKind::Address, label, token: token.clone() });
+                0
+            }
+        };
+        let instruction = match instruction {
+            Instruction::Call(_) => Instruction::Call(address),
+            Instruction::Jump(_) => Instruction::Jump(address),
+            Instruction::JumpOffset(_) => Instruction::JumpOffset(address),
+            Instruction::SetI(_) => Instruction::SetI(address),
+            _ => Instruction::Sys(address),
+        };
+        self.emit(token, instruction)
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,232 @@
+use super::*;
+use crate::config::{Config, Mode};
+use crate::emulator::Emulator;
+
+fn words(rom: &[u8]) -> Vec<u16> {
+    rom.chunks(2).map(|word| u16::from_be_bytes([word[0], word[1]])).collect()
+}
+
+fn error(source: &str) -> AsmError {
+    compile(source).unwrap_err()
+}
+
+/// Runs a program until it executes `exit`
+fn run(source: &str) -> Emulator {
+    let rom = compile(source).unwrap_or_else(|err| panic!("{err}"));
+    let mut emulator = Emulator::with_config(Config { mode: Mode::XoChip, ..Config::default() });
+    emulator.load_rom(&rom).unwrap();
+    for _ in 0..10_000 {
+        if emulator.is_halted() {
+            return emulator;
+        }
+        emulator.tick().unwrap();
+    }
+    panic!("program did not exit");
+}
+
+fn v(emulator: &Emulator, x: usize) -> u8 {
+    emulator.get_cpu_state().v_registers[x]
+}
+
+#[test]
+fn statements() {
+    let source = "
+: main
+    clear  v0 := 5  v1 := v0  v2 += 3  v3 += v1  v4 -= v1  v5 -= 1  v6 =- v7
+    v8 |= v9  va &= vb  vc ^= vd  ve >>= ve  v1 <<= v1  v2 := random 0x0F
+    v3 := key  v4 := delay  delay := v5  buzzer := v6  i := 0x300  i += v7
+    i := hex v8  i := bighex v9  bcd va  save vb  load vc  sprite v0 v1 4
+    jump0 0x400  native 0x123  hires  lores  scroll-down 2  scroll-left
+    scroll-right  exit  saveflags v3  loadflags v4  return ;
+";
+    assert_eq!(
+        words(&compile(source).unwrap()),
+        [
+            0x1202, 0x00E0, 0x6005, 0x8100, 0x7203, 0x8314, 0x8415, 0x75FF, 0x8677,
+            0x8891, 0x8AB2, 0x8CD3, 0x8EE6, 0x811E, 0xC20F,
+            0xF30A, 0xF407, 0xF515, 0xF618, 0xA300, 0xF71E,
+            0xF829, 0xF930, 0xFA33, 0xFB55, 0xFC65, 0xD014,
+            0xB400, 0x0123, 0x00FF, 0x00FE, 0x00C2, 0x00FC,
+            0x00FB, 0x00FD, 0xF375, 0xF485, 0x00EE, 0x00EE,
+        ]
+    );
+}
+
+#[test]
+fn xo_chip_statements() {
+    let source = ": main i := long data  plane 3  audio  pitch := v1  scroll-up 4  save v2 - v5  load v5 - v2\n: data 1 2";
+    assert_eq!(
+        compile(source).unwrap(),
+        [0x12, 0x02, 0xF0, 0x00, 0x02, 0x12, 0xF3, 0x01, 0xF0, 0x02, 0xF1, 0x3A, 0x00, 0xD4, 0x52, 0x52, 0x55, 0x23, 1, 2]
+    );
+}
+
+#[test]
+fn labels_calls_and_forward_references() {
+    let source = ": main draw jump main\n: draw i := sprite ;\n: sprite 0xFF 0x00";
+    assert_eq!(words(&compile(source).unwrap()), [0x1202, 0x2206, 0x1202, 0xA20A, 0x00EE, 0xFF00]);
+}
+
+#[test]
+fn main_need_not_come_first() {
+    let source = ": helper return\n: main :call helper exit";
+    assert_eq!(words(&compile(source).unwrap()), [0x1204, 0x00EE, 0x2202, 0x00FD]);
+}
+
+#[test]
+fn if_then_inverts_the_skip() {
+    let source = "
+: main
+    if v0 == 1 then v1 := 2
+    if v0 != v2 then v1 := 3
+    if v3 key then v1 := 4
+    if v3 -key then v1 := 5
+";
+    assert_eq!(
+        words(&compile(source).unwrap())[1..],
+        [0x4001, 0x6102, 0x5020, 0x6103, 0xE3A1, 0x6104, 0xE39E, 0x6105]
+    );
+}
+
+#[test]
+fn if_begin_else_end() {
+    let source = ": main if v0 == 0 begin v1 := 1 else v1 := 2 end exit";
+    assert_eq!(words(&compile(source).unwrap()), [0x1202, 0x3000, 0x120A, 0x6101, 0x120C, 0x6102, 0x00FD]);
+    let emulator = run(": main v0 := 0 if v0 == 0 begin v1 := 1 else v1 := 2 end if v0 != 0 begin v2 := 1 else v2 := 2 end exit");
+    assert_eq!((v(&emulator, 1), v(&emulator, 2)), (1, 2));
+}
+
+#[test]
+fn loops() {
+    let emulator = run("
+: main
+    v0 := 0  v1 := 0
+    loop
+        v0 += 1
+        while v0 != 10
+        v1 += 2
+    again
+    exit
+");
+    assert_eq!((v(&emulator, 0), v(&emulator, 1)), (10, 18));
+}
+
+#[test]
+fn comparisons() {
+    // Each comparison sets a bit of V2 when it holds
+    for (a, b) in [(3, 5), (5, 3), (4, 4), (0, 255)] {
+        let source = format!("
+: main
+    v0 := {a}  v1 := {b}  v2 := 0
+    if v0 < v1 then v2 += 1
+    if v0 > v1 then v2 += 2
+    if v0 <= v1 then v2 += 4
+    if v0 >= v1 then v2 += 8
+    if v0 < {b} then v2 += 16
+    if v0 >= {b} then v2 += 32
+    exit
+");
+        let emulator = run(&source);
+        let expected = (a < b) as u8 | ((a > b) as u8) << 1 | ((a <= b) as u8) << 2 | ((a >= b) as u8) << 3
+            | ((a < b) as u8) << 4 | ((a >= b) as u8) << 5;
+        assert_eq!(v(&emulator, 2), expected, "{a} vs {b}");
+    }
+}
+
+#[test]
+fn constants_aliases_and_calc() {
+    let source = "
+:const SPEED 3
+:alias px v4
+:calc DOUBLE { SPEED * 2 + 1 }
+:calc GROUPED { ( SPEED * 2 ) + 1 }
+: main px := SPEED  px += DOUBLE  v5 := GROUPED
+:byte { DOUBLE << 4 } :byte 0
+";
+    // Without precedence, SPEED * 2 + 1 is SPEED * (2 + 1)
+    assert_eq!(words(&compile(source).unwrap()), [0x1202, 0x6403, 0x7409, 0x6507, 0x9000]);
+}
+
+#[test]
+fn macros() {
+    let source = "
+:macro swap a b { vf := a a := b b := vf }
+: main swap v0 v1 swap v2 v3
+";
+    assert_eq!(words(&compile(source).unwrap()), [0x1202, 0x8F00, 0x8010, 0x81F0, 0x8F20, 0x8230, 0x83F0]);
+}
+
+#[test]
+fn next_unpack_and_org() {
+    let source = "
+: main
+    :next counter v0 := 7
+    :unpack 0xA data
+    i := counter
+: data 0x11
+:org 0x300
+: far 0x22
+";
+    let rom = compile(source).unwrap();
+    assert_eq!(words(&rom[..10]), [0x1202, 0x6007, 0x60A2, 0x610A, 0xA203]);
+    assert_eq!(rom[10], 0x11);
+    assert_eq!(rom.len(), 0x101);
+    assert_eq!(rom[0x100], 0x22);
+}
+
+#[test]
+fn assert_checks_expressions() {
+    assert!(compile(":assert { 1 == 1 } : main exit").is_ok());
+    assert_eq!(error(":calc X { 3 }\n:assert \"too small\" { X > 4 }").message, "too small");
+}
+
+#[test]
+fn calc_limits() {
+    let source = |calc: &str| format!(":calc X {{ {calc} }}\n: main v0 := X");
+    // Long chains still evaluate right to left
+    let chain = vec!["1"; 100_001].join(" - ");
+    assert_eq!(words(&compile(&source(&chain)).unwrap())[1], 0x6001);
+    assert_eq!(words(&compile(&source("8 - 4 - 2")).unwrap())[1], 0x6006);
+    assert_eq!(words(&compile(&source("1 << 7")).unwrap())[1], 0x6080);
+
+    let err = error(&source("1 << 64"));
+    assert_eq!((err.line, err.column, err.message.as_str()), (1, 13, "shift by 64 is out of range"));
+    assert_eq!(error(&source("1 >> -1")).message, "shift by -1 is out of range");
+
+    let nested = |depth: usize| format!("{}1{}", "( ".repeat(depth), " )".repeat(depth));
+    assert!(compile(&source(&nested(256))).is_ok());
+    assert_eq!(error(&source(&nested(257))).message, "expression nested too deeply");
+    assert_eq!(error(&source(&nested(100_000))).message, "expression nested too deeply");
+    assert_eq!(error(&source(&format!("{}1", "- ".repeat(100_000)))).message, "expression nested too deeply");
+}
+
+#[test]
+fn errors_carry_positions() {
+    let err = error(": main\n  v0 := 300");
+    assert_eq!((err.line, err.column, err.message.as_str()), (2, 9, "value 300 does not fit in a byte"));
+    assert_eq!(error(": main jump missing").to_string(), "<input>:1:8: undefined name 'missing'");
+    assert_eq!(error(": main v0 +=").message, "unexpected end of file");
+    assert_eq!(error(": main v0 *= v1").message, "unknown operator '*='");
+    assert_eq!(error(": main : main").message, "label 'main' is already defined");
+    assert_eq!(error(": main loop v0 += 1").message, "'loop' without 'again'");
+    assert_eq!(error(": main end").message, "'end' without 'if ... begin'");
+    assert_eq!(error(": main if v0 == 1 v1 := 2").message, "expected 'then' or 'begin', found 'v1'");
+    assert_eq!(error(": start exit").message, "program has no 'main' label");
+    assert_eq!(error(": main sprite v0 v1 16").message, "expected a number from 0 to 15, found '16'");
+    assert_eq!(error(": main :stringmode").message, "unknown directive ':stringmode'");
+    assert_eq!(error(":macro loop-forever { loop-forever } : main loop-forever").message, "too many macro expansions");
+}
+
+#[test]
+fn runs_on_the_emulator() {
+    let emulator = run("
+: main
+    v0 := 0  v1 := 0
+    loop
+        v1 += v0
+        v0 += 1
+        if v0 == 11 then exit
+    again
+");
+    assert_eq!(v(&emulator, 1), 55);
+}
@@ -0,0 +1,195 @@
+//! Rewind history of machine snapshots.
+//!
+//! The newest snapshot is kept in full. Every older snapshot is stored as
+//! the XOR against its successor, run-length encoded. Consecutive frames
+//! differ in only a few bytes, so each step of history costs tens of bytes
+//! and the oldest step can be dropped without touching the others.
+
+use std::collections::VecDeque;
+
+/// Default frames between snapshots
+pub const DEFAULT_REWIND_INTERVAL: u32 = 1;
+/// Default memory budget, several minutes of history at one snapshot per frame
+pub const DEFAULT_REWIND_BUDGET: usize = 8 * 1024 * 1024;
+
+/// How the emulator records rewind history
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RewindConfig {
+    /// Frames between snapshots, rewinding moves back in steps of this size
+    pub interval: u32,
+    /// Upper bound on the bytes used by the history
+    pub budget: usize,
+}
+
+impl Default for RewindConfig {
+    fn default() -> Self {
+        Self {
+            interval: DEFAULT_REWIND_INTERVAL,
+            budget: DEFAULT_REWIND_BUDGET,
+        }
+    }
+}
+
+/// Ring buffer of delta-compressed save states
+pub struct RewindBuffer {
+    config: RewindConfig,
+    /// Newest snapshot, uncompressed
+    head: Vec<u8>,
+    /// Oldest first, each entry turns its successor (or `head`) into the snapshot before it
+    deltas: VecDeque<Vec<u8>>,
+    /// Bytes used by `deltas`
+    delta_bytes: usize,
+    /// Frames run since the newest snapshot
+    frames_since_snapshot: u32,
+}
+
+impl RewindBuffer {
+    pub fn new(config: RewindConfig) -> Self {
+        Self {
+            config: RewindConfig { interval: config.interval.max(1), ..config },
+            head: Vec::new(),
+            deltas: VecDeque::new(),
+            delta_bytes: 0,
+            frames_since_snapshot: 0,
+        }
+    }
+
+    /// Number of snapshots held
+    pub fn len(&self) -> usize {
+        if self.head.is_empty() {
+            0
+        } else {
+            self.deltas.len() + 1
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.head.is_empty()
+    }
+
+    /// Bytes used by the snapshots
+    pub fn memory_used(&self) -> usize {
+        self.head.len() + self.delta_bytes
+    }
+
+    /// Frames of history that can be rewound
+    pub fn frames_available(&self) -> u64 {
+        if self.head.is_empty() {
+            return 0;
+        }
+        self.deltas.len() as u64 * self.config.interval as u64 + self.frames_since_snapshot as u64
+    }
+
+    pub fn clear(&mut self) {
+        self.head.clear();
+        self.deltas.clear();
+        self.delta_bytes = 0;
+        self.frames_since_snapshot = 0;
+    }
+
+    /// Counts a finished frame, returning whether a snapshot is due
+    pub fn frame_finished(&mut self) -> bool {
+        self.frames_since_snapshot += 1;
+        self.head.is_empty() || self.frames_since_snapshot >= self.config.interval
+    }
+
+    /// Records a snapshot as the newest entry, dropping the oldest entries
+    /// to stay within the memory budget
+    pub fn push(&mut self, snapshot: Vec<u8>) {
+        if self.head.len() == snapshot.len() {
+            let delta = encode(&self.head, &snapshot);
+            self.delta_bytes += delta.len();
+            self.deltas.push_back(delta);
+        } else {
+            // Snapshots of different sizes can't be diffed
+            self.clear();
+        }
+        self.head = snapshot;
+        self.frames_since_snapshot = 0;
+        while self.memory_used() > self.config.budget {
+            match self.deltas.pop_front() {
+                Some(delta) => self.delta_bytes -= delta.len(),
+                None => break,
+            }
+        }
+    }
+
+    /// Steps back at least `frames` frames, or as far as the history goes.
+    ///
+    /// Returns the snapshot to restore and the number of frames it lies in
+    /// the past, or `None` without history. The returned snapshot stays in
+    /// the buffer as the newest entry.
+    pub fn rewind(&mut self, frames: u32) -> Option<(&[u8], u64)> {
+        if self.head.is_empty() {
+            return None;
+        }
+        let mut rewound = self.frames_since_snapshot as u64;
+        while rewound < frames as u64 {
+            let Some(delta) = self.deltas.pop_back() else { break };
+            self.delta_bytes -= delta.len();
+            decode(&mut self.head, &delta);
+            rewound += self.config.interval as u64;
+        }
+        self.frames_since_snapshot = 0;
+        Some((&self.head, rewound))
+    }
+}
+
+/// Run-length encodes `old ^ new` as pairs of a zero run and a literal run,
+/// each length a LEB128 varint
+fn encode(old: &[u8], new: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut xor = old.iter().zip(new).map(|(a, b)| a ^ b).peekable();
+    while xor.peek().is_some() {
+        let mut zeros = 0;
+        while xor.next_if_eq(&0).is_some() {
+            zeros += 1;
+        }
+        let mut literal = Vec::new();
+        while let Some(byte) = xor.next_if(|&byte| byte != 0) {
+            literal.push(byte);
+        }
+        write_varint(&mut out, zeros);
+        write_varint(&mut out, literal.len());
+        out.extend_from_slice(&literal);
+    }
+    out
+}
+
+/// XORs an encoded delta into `data`
+fn decode(data: &mut [u8], delta: &[u8]) {
+    let mut pos = 0;
+    let mut bytes = delta.iter().copied();
+    while let Some(zeros) = read_varint(&mut bytes) {
+        pos += zeros;
+        let len = read_varint(&mut bytes).unwrap_or(0);
+        for (byte, xor) in data[pos..pos + len].iter_mut().zip(bytes.by_ref()) {
+            *byte ^= xor;
+        }
+        pos += len;
+    }
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(bytes: &mut impl Iterator<Item = u8>) -> Option<usize> {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = bytes.next()?;
+        value |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+        shift += 7;
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,75 @@
+use super::*;
+
+fn buffer(interval: u32, budget: usize) -> RewindBuffer {
+    RewindBuffer::new(RewindConfig { interval, budget })
+}
+
+#[test]
+fn delta_round_trip() {
+    let old: Vec<u8> = (0..1000).map(|i| (i % 251) as u8).collect();
+    let mut new = old.clone();
+    new[0] = 0xFF;
+    new[500..520].fill(0);
+    new[999] ^= 1;
+    let delta = encode(&old, &new);
+    assert!(delta.len() < 40);
+
+    let mut data = new.clone();
+    decode(&mut data, &delta);
+    assert_eq!(data, old);
+}
+
+#[test]
+fn identical_snapshots_are_tiny() {
+    assert_eq!(encode(&[7; 300], &[7; 300]), [0xAC, 0x02, 0x00]);
+}
+
+#[test]
+fn rewind_steps_through_history() {
+    let mut rewind = buffer(1, usize::MAX);
+    assert!(rewind.rewind(1).is_none());
+    for frame in 0..5u8 {
+        rewind.push(vec![frame; 64]);
+    }
+    assert_eq!(rewind.len(), 5);
+
+    let (snapshot, frames) = rewind.rewind(1).unwrap();
+    assert_eq!((snapshot[0], frames), (3, 1));
+    let (snapshot, frames) = rewind.rewind(2).unwrap();
+    assert_eq!((snapshot[0], frames), (1, 2));
+    // Stops at the oldest snapshot
+    let (snapshot, frames) = rewind.rewind(10).unwrap();
+    assert_eq!((snapshot[0], frames), (0, 1));
+    assert_eq!(rewind.len(), 1);
+}
+
+#[test]
+fn snapshots_follow_interval() {
+    let mut rewind = buffer(4, usize::MAX);
+    assert!(rewind.frame_finished());
+    rewind.push(vec![0; 16]);
+    let due: Vec<bool> = (0..4).map(|_| rewind.frame_finished()).collect();
+    assert_eq!(due, [false, false, false, true]);
+    rewind.push(vec![1; 16]);
+    rewind.frame_finished();
+
+    // One frame past the newest snapshot, which is where a rewind of one frame lands
+    assert_eq!(rewind.frames_available(), 5);
+    let (snapshot, frames) = rewind.rewind(1).unwrap();
+    assert_eq!((snapshot[0], frames), (1, 1));
+    let (snapshot, frames) = rewind.rewind(1).unwrap();
+    assert_eq!((snapshot[0], frames), (0, 4));
+}
+
+#[test]
+fn budget_drops_oldest() {
+    let mut rewind = buffer(1, 300);
+    for frame in 0..50u8 {
+        rewind.push(vec![frame; 64]);
+    }
+    assert!(rewind.memory_used() <= 300);
+    let len = rewind.len();
+    assert!(len > 1);
+    let (snapshot, _) = rewind.rewind(u32::MAX).unwrap();
+    assert_eq!(snapshot[0] as usize, 50 - len);
+}
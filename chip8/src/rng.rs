@@ -0,0 +1,115 @@
+//! Random number generators for CXNN.
+//!
+//! The generator is part of the machine state, so a seeded run is
+//! reproducible and save states capture where the sequence left off.
+
+/// Source of the random bytes used by CXNN
+pub trait Rng: Send {
+    /// Restarts the sequence from `seed`
+    fn seed(&mut self, seed: u64);
+
+    /// Next random byte
+    fn next_byte(&mut self) -> u8;
+
+    /// Internal state, stored in save states
+    fn get_state(&self) -> Vec<u8>;
+
+    /// Restores state from `get_state`, returning false if it is malformed
+    fn set_state(&mut self, state: &[u8]) -> bool;
+}
+
+/// Default generator, xorshift64*
+pub struct XorShiftRng {
+    state: u64,
+}
+
+impl XorShiftRng {
+    pub fn new(seed: u64) -> Self {
+        let mut rng = Self { state: 0 };
+        rng.seed(seed);
+        rng
+    }
+}
+
+impl Rng for XorShiftRng {
+    fn seed(&mut self, seed: u64) {
+        // SplitMix64 spreads similar seeds apart and never yields the
+        // all-zero state xorshift can't leave
+        let mut z = seed.wrapping_add(0x9E3779B97F4A7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        self.state = (z ^ (z >> 31)).max(1);
+    }
+
+    fn next_byte(&mut self) -> u8 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        (self.state.wrapping_mul(0x2545F4914F6CDD1D) >> 56) as u8
+    }
+
+    fn get_state(&self) -> Vec<u8> {
+        self.state.to_be_bytes().to_vec()
+    }
+
+    fn set_state(&mut self, state: &[u8]) -> bool {
+        match state.try_into().map(u64::from_be_bytes) {
+            Ok(value) if value != 0 => {
+                self.state = value;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Generator modelled on the COSMAC VIP interpreter.
+///
+/// The VIP keeps a 16 bit seed in register R9. Each CXNN increments it,
+/// adds the byte of interpreter code that the low byte points at in the
+/// interpreter's second page to the high byte, and returns that sum. The
+/// interpreter isn't bundled, so the 256 byte code page is supplied by the
+/// caller from a dump of the VIP ROM.
+pub struct VipRng {
+    code_page: [u8; 256],
+    r9: u16,
+}
+
+impl VipRng {
+    pub fn new(code_page: [u8; 256], seed: u64) -> Self {
+        let mut rng = Self { code_page, r9: 0 };
+        rng.seed(seed);
+        rng
+    }
+}
+
+impl Rng for VipRng {
+    fn seed(&mut self, seed: u64) {
+        self.r9 = seed as u16;
+    }
+
+    fn next_byte(&mut self) -> u8 {
+        self.r9 = self.r9.wrapping_add(1);
+        let [high, low] = self.r9.to_be_bytes();
+        let value = high.wrapping_add(self.code_page[low as usize]);
+        self.r9 = u16::from_be_bytes([value, low]);
+        value
+    }
+
+    fn get_state(&self) -> Vec<u8> {
+        self.r9.to_be_bytes().to_vec()
+    }
+
+    fn set_state(&mut self, state: &[u8]) -> bool {
+        match state.try_into().map(u16::from_be_bytes) {
+            Ok(value) => {
+                self.r9 = value;
+                true
+            }
+            Err(_) => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests;
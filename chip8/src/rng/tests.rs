@@ -0,0 +1,43 @@
+use super::*;
+
+fn bytes(rng: &mut dyn Rng, count: usize) -> Vec<u8> {
+    (0..count).map(|_| rng.next_byte()).collect()
+}
+
+#[test]
+fn same_seed_same_sequence() {
+    let first = bytes(&mut XorShiftRng::new(42), 64);
+    assert_eq!(bytes(&mut XorShiftRng::new(42), 64), first);
+    assert_ne!(bytes(&mut XorShiftRng::new(43), 64), first);
+}
+
+#[test]
+fn zero_seed_is_usable() {
+    let sequence = bytes(&mut XorShiftRng::new(0), 64);
+    assert!(sequence.iter().any(|&byte| byte != sequence[0]));
+}
+
+#[test]
+fn state_round_trip() {
+    let mut rng = XorShiftRng::new(7);
+    rng.next_byte();
+    let state = rng.get_state();
+    let expected = bytes(&mut rng, 16);
+
+    let mut restored = XorShiftRng::new(0);
+    assert!(restored.set_state(&state));
+    assert_eq!(bytes(&mut restored, 16), expected);
+    assert!(!restored.set_state(&[0; 8]));
+    assert!(!restored.set_state(&[1, 2, 3]));
+}
+
+#[test]
+fn vip_adds_code_byte_to_seed() {
+    let mut code_page = [0; 256];
+    code_page[0x01] = 0x10;
+    code_page[0x02] = 0x20;
+    let mut rng = VipRng::new(code_page, 0x0500);
+    assert_eq!(rng.next_byte(), 0x15);
+    assert_eq!(rng.next_byte(), 0x35);
+    assert_eq!(rng.get_state(), [0x35, 0x02]);
+}
@@ -0,0 +1,17 @@
+use super::*;
+
+fn hex(data: &[u8]) -> String {
+    RomInfo::new(data).sha1_hex()
+}
+
+#[test]
+fn sha1_known_answers() {
+    assert_eq!(hex(b"abc"), "a9993e364706816aba3e25717850c26c9cd0d89d");
+    assert_eq!(hex(b""), "da39a3ee5e6b4b0d3255bfef95601890afd80709");
+    // 56 bytes leave no room for the length, so padding takes a second block
+    assert_eq!(
+        hex(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"),
+        "84983e441c3bd26ebaae4aa1f95129e5e54670f1"
+    );
+    assert_eq!(hex(&[b'a'; 1000]), "291e9a6c66994949b57ba5e650361e98fc36b1ba");
+}
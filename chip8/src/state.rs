@@ -0,0 +1,173 @@
+//! Binary save state format.
+//!
+//! A save state is a header followed by the serialized machine and a
+//! checksum over everything before it:
+//!
+//! ```text
+//! magic "C8SS" | version u16 | mode u8 | payload length u32 | payload | CRC-32 u32
+//! ```
+//!
+//! All integers are big-endian, like CHIP-8 itself.
+
+use crate::config::Mode;
+use crate::error::Chip8Error;
+
+/// Identifies a save state file
+pub const MAGIC: [u8; 4] = *b"C8SS";
+/// Bumped whenever the payload layout changes
+pub const VERSION: u16 = 2;
+const HEADER_SIZE: usize = 11;
+const CHECKSUM_SIZE: usize = 4;
+
+/// Appends fields of a save state payload
+pub(crate) struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> Self {
+        Self { data: Vec::new() }
+    }
+
+    pub fn write_u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn write_bool(&mut self, value: bool) {
+        self.data.push(value as u8);
+    }
+
+    pub fn write_u16(&mut self, value: u16) {
+        self.data.extend_from_slice(&value.to_be_bytes());
+    }
+
+    pub fn write_u32(&mut self, value: u32) {
+        self.data.extend_from_slice(&value.to_be_bytes());
+    }
+
+    pub fn write_u64(&mut self, value: u64) {
+        self.data.extend_from_slice(&value.to_be_bytes());
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.data.extend_from_slice(bytes);
+    }
+
+    /// Wraps the payload in the header and checksum
+    pub fn finish(self, mode: Mode) -> Vec<u8> {
+        let mut state = Vec::with_capacity(HEADER_SIZE + self.data.len() + CHECKSUM_SIZE);
+        state.extend_from_slice(&MAGIC);
+        state.extend_from_slice(&VERSION.to_be_bytes());
+        state.push(mode_tag(mode));
+        state.extend_from_slice(&(self.data.len() as u32).to_be_bytes());
+        state.extend_from_slice(&self.data);
+        let checksum = crc32(&state);
+        state.extend_from_slice(&checksum.to_be_bytes());
+        state
+    }
+}
+
+/// Reads fields of a save state payload, failing on truncated data
+pub(crate) struct StateReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> StateReader<'a> {
+    /// Validates the header and checksum of `state` and returns a reader
+    /// over its payload
+    pub fn open(state: &'a [u8], mode: Mode) -> Result<Self, Chip8Error> {
+        if state.len() < HEADER_SIZE + CHECKSUM_SIZE || state[..4] != MAGIC {
+            return Err(invalid("not a save state"));
+        }
+        let version = u16::from_be_bytes([state[4], state[5]]);
+        if version != VERSION {
+            return Err(invalid("unsupported save state version"));
+        }
+        if state[6] != mode_tag(mode) {
+            return Err(invalid("save state is for a different mode"));
+        }
+        let len = u32::from_be_bytes([state[7], state[8], state[9], state[10]]) as usize;
+        if state.len() != HEADER_SIZE + len + CHECKSUM_SIZE {
+            return Err(invalid("save state has the wrong length"));
+        }
+        let (body, checksum) = state.split_at(HEADER_SIZE + len);
+        if crc32(body).to_be_bytes() != checksum {
+            return Err(invalid("save state checksum mismatch"));
+        }
+        Ok(Self { data: &body[HEADER_SIZE..], pos: 0 })
+    }
+
+    pub fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], Chip8Error> {
+        let bytes = self
+            .data
+            .get(self.pos..self.pos + len)
+            .ok_or_else(|| invalid("save state is truncated"))?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, Chip8Error> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool, Chip8Error> {
+        match self.read_u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(invalid("save state contains an invalid flag")),
+        }
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, Chip8Error> {
+        let bytes = self.read_bytes(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, Chip8Error> {
+        let bytes = self.read_bytes(4)?;
+        Ok(u32::from_be_bytes(bytes.try_into().unwrap()))
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, Chip8Error> {
+        let bytes = self.read_bytes(8)?;
+        Ok(u64::from_be_bytes(bytes.try_into().unwrap()))
+    }
+
+    /// Fails unless the whole payload was consumed
+    pub fn finish(self) -> Result<(), Chip8Error> {
+        if self.pos != self.data.len() {
+            return Err(invalid("save state has trailing data"));
+        }
+        Ok(())
+    }
+}
+
+/// Error for a save state that can't be restored
+pub(crate) fn invalid(reason: &'static str) -> Chip8Error {
+    Chip8Error::InvalidSaveState { reason }
+}
+
+fn mode_tag(mode: Mode) -> u8 {
+    match mode {
+        Mode::Chip8 => 0,
+        Mode::SuperChip => 1,
+        Mode::XoChip => 2,
+    }
+}
+
+/// CRC-32 (IEEE 802.3) of the given bytes
+pub(crate) fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB88320 & mask);
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests;
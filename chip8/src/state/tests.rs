@@ -0,0 +1,28 @@
+use super::*;
+
+#[test]
+fn crc32_check_value() {
+    assert_eq!(crc32(b"123456789"), 0xCBF43926);
+}
+
+#[test]
+fn round_trip_fields() {
+    let mut writer = StateWriter::new();
+    writer.write_u8(0x12);
+    writer.write_bool(true);
+    writer.write_u16(0x3456);
+    writer.write_u32(0x789ABCDE);
+    writer.write_u64(u64::MAX);
+    writer.write_bytes(&[1, 2, 3]);
+    let state = writer.finish(Mode::SuperChip);
+
+    let mut reader = StateReader::open(&state, Mode::SuperChip).unwrap();
+    assert_eq!(reader.read_u8().unwrap(), 0x12);
+    assert!(reader.read_bool().unwrap());
+    assert_eq!(reader.read_u16().unwrap(), 0x3456);
+    assert_eq!(reader.read_u32().unwrap(), 0x789ABCDE);
+    assert_eq!(reader.read_u64().unwrap(), u64::MAX);
+    assert_eq!(reader.read_bytes(3).unwrap(), [1, 2, 3]);
+    assert!(reader.read_u8().is_err());
+    reader.finish().unwrap();
+}
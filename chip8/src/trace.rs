@@ -0,0 +1,381 @@
+//! Instruction trace log, for comparing runs with each other or with
+//! reference emulators.
+//!
+//! Every record holds the machine state before an instruction executes.
+//! Text traces have one line per record, with the decoded mnemonic after
+//! a `;` for reading only:
+//!
+//! ```text
+//! cycle=42 pc=0204 op=A20A v=05000000000000000000000000000000 i=0000 sp=00 dt=00 st=00 ; LD I, 0x20A
+//! ```
+//!
+//! Binary traces are the magic "C8TR" and a version u16, followed by
+//! fixed size records in the same field order. All integers are
+//! big-endian, like CHIP-8 itself.
+
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::io::{self, BufRead, BufWriter, Write};
+use std::ops::RangeInclusive;
+use crate::config::Mode;
+use crate::cpu::{CpuState, NUMBER_OF_REGISTERS};
+use crate::instruction::decode_for;
+
+/// Identifies a binary trace
+pub const MAGIC: [u8; 4] = *b"C8TR";
+/// Bumped whenever the record layout changes
+pub const VERSION: u16 = 1;
+/// Bytes in a binary record
+pub const RECORD_SIZE: usize = 8 + 2 + 2 + NUMBER_OF_REGISTERS + 2 + 3;
+
+/// Field names, as used in text traces and by `TraceRecord::differences`
+const V_NAMES: [&str; NUMBER_OF_REGISTERS] = [
+    "v0", "v1", "v2", "v3", "v4", "v5", "v6", "v7", "v8", "v9", "va", "vb", "vc", "vd", "ve", "vf",
+];
+
+/// How a tracer writes its records
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceFormat {
+    Text,
+    Binary,
+}
+
+/// Machine state before one instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceRecord {
+    /// Instructions executed since tracing started
+    pub cycle: u64,
+    pub pc: u16,
+    pub opcode: u16,
+    pub v_registers: [u8; NUMBER_OF_REGISTERS],
+    pub i_register: u16,
+    pub stack_pointer: u8,
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+}
+
+impl TraceRecord {
+    pub(crate) fn new(cycle: u64, opcode: u16, state: &CpuState, delay_timer: u8, sound_timer: u8) -> Self {
+        Self {
+            cycle,
+            pc: state.program_counter,
+            opcode,
+            v_registers: state.v_registers,
+            i_register: state.i_register,
+            stack_pointer: state.stack_pointer as u8,
+            delay_timer,
+            sound_timer,
+        }
+    }
+
+    /// Text trace line, without a newline
+    pub fn to_line(&self, mode: Mode) -> String {
+        let mut line = format!("cycle={} pc={:04X} op={:04X} v=", self.cycle, self.pc, self.opcode);
+        for value in self.v_registers {
+            let _ = write!(line, "{value:02X}");
+        }
+        let _ = write!(
+            line,
+            " i={:04X} sp={:02X} dt={:02X} st={:02X} ; {}",
+            self.i_register,
+            self.stack_pointer,
+            self.delay_timer,
+            self.sound_timer,
+            decode_for(self.opcode, mode)
+        );
+        line
+    }
+
+    /// Parses a text trace line. Fields may come in any order and the
+    /// mnemonic is ignored, so traces from other tools only need the same
+    /// field names.
+    pub fn parse_line(line: &str) -> Result<Self, String> {
+        let fields = line.split(';').next().unwrap_or_default();
+        let mut record = TraceRecord {
+            cycle: 0,
+            pc: 0,
+            opcode: 0,
+            v_registers: [0; NUMBER_OF_REGISTERS],
+            i_register: 0,
+            stack_pointer: 0,
+            delay_timer: 0,
+            sound_timer: 0,
+        };
+        for field in fields.split_whitespace() {
+            let (name, value) = field.split_once('=').ok_or_else(|| format!("expected name=value: {field}"))?;
+            let invalid = || format!("invalid {name}: {value}");
+            let hex8 = || u8::from_str_radix(value, 16).map_err(|_| invalid());
+            let hex16 = || u16::from_str_radix(value, 16).map_err(|_| invalid());
+            match name.to_ascii_lowercase().as_str() {
+                "cycle" => record.cycle = value.parse().map_err(|_| invalid())?,
+                "pc" => record.pc = hex16()?,
+                "op" => record.opcode = hex16()?,
+                "i" => record.i_register = hex16()?,
+                "sp" => record.stack_pointer = hex8()?,
+                "dt" => record.delay_timer = hex8()?,
+                "st" => record.sound_timer = hex8()?,
+                "v" if value.len() == NUMBER_OF_REGISTERS * 2 && value.is_ascii() => {
+                    for (x, register) in record.v_registers.iter_mut().enumerate() {
+                        *register = u8::from_str_radix(&value[x * 2..x * 2 + 2], 16).map_err(|_| invalid())?;
+                    }
+                }
+                "v" => return Err(invalid()),
+                _ => return Err(format!("unknown field: {name}")),
+            }
+        }
+        Ok(record)
+    }
+
+    pub fn to_bytes(&self) -> [u8; RECORD_SIZE] {
+        let mut bytes = [0; RECORD_SIZE];
+        bytes[0..8].copy_from_slice(&self.cycle.to_be_bytes());
+        bytes[8..10].copy_from_slice(&self.pc.to_be_bytes());
+        bytes[10..12].copy_from_slice(&self.opcode.to_be_bytes());
+        bytes[12..28].copy_from_slice(&self.v_registers);
+        bytes[28..30].copy_from_slice(&self.i_register.to_be_bytes());
+        bytes[30] = self.stack_pointer;
+        bytes[31] = self.delay_timer;
+        bytes[32] = self.sound_timer;
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8; RECORD_SIZE]) -> Self {
+        let word = |index: usize| u16::from_be_bytes([bytes[index], bytes[index + 1]]);
+        Self {
+            cycle: u64::from_be_bytes(bytes[0..8].try_into().unwrap()),
+            pc: word(8),
+            opcode: word(10),
+            v_registers: bytes[12..28].try_into().unwrap(),
+            i_register: word(28),
+            stack_pointer: bytes[30],
+            delay_timer: bytes[31],
+            sound_timer: bytes[32],
+        }
+    }
+
+    /// Names of the fields that differ from `other`
+    pub fn differences(&self, other: &TraceRecord) -> Vec<&'static str> {
+        let mut fields = Vec::new();
+        let mut compare = |name, differs| {
+            if differs {
+                fields.push(name);
+            }
+        };
+        compare("cycle", self.cycle != other.cycle);
+        compare("pc", self.pc != other.pc);
+        compare("op", self.opcode != other.opcode);
+        for (x, name) in V_NAMES.iter().enumerate() {
+            compare(name, self.v_registers[x] != other.v_registers[x]);
+        }
+        compare("i", self.i_register != other.i_register);
+        compare("sp", self.stack_pointer != other.stack_pointer);
+        compare("dt", self.delay_timer != other.delay_timer);
+        compare("st", self.sound_timer != other.sound_timer);
+        fields
+    }
+}
+
+/// Whether `name` is a field `TraceRecord::differences` reports
+pub fn is_field(name: &str) -> bool {
+    ["cycle", "pc", "op", "i", "sp", "dt", "st"].contains(&name) || V_NAMES.contains(&name)
+}
+
+enum Sink {
+    Writer(BufWriter<Box<dyn Write + Send>>),
+    /// Keeps only the newest records
+    Ring { records: VecDeque<TraceRecord>, capacity: usize },
+}
+
+/// Records executed instructions, plugged into an emulator with
+/// `Emulator::set_tracer`
+pub struct Tracer {
+    sink: Sink,
+    format: TraceFormat,
+    /// Only instructions at these addresses are recorded
+    filter: Option<RangeInclusive<u16>>,
+    cycle: u64,
+    started: bool,
+    /// First write error, reported by `finish`
+    error: Option<io::Error>,
+}
+
+impl Tracer {
+    /// Writes every record to `writer` as it happens
+    pub fn new(writer: impl Write + Send + 'static, format: TraceFormat) -> Self {
+        Self::with_sink(Sink::Writer(BufWriter::new(Box::new(writer))), format)
+    }
+
+    /// Keeps the newest `capacity` records in memory, to be written out
+    /// with `write_records` after something went wrong
+    pub fn ring(capacity: usize) -> Self {
+        let sink = Sink::Ring { records: VecDeque::with_capacity(capacity), capacity };
+        Self::with_sink(sink, TraceFormat::Text)
+    }
+
+    fn with_sink(sink: Sink, format: TraceFormat) -> Self {
+        Self { sink, format, filter: None, cycle: 0, started: false, error: None }
+    }
+
+    /// Only records instructions with their address in `range`
+    pub fn set_filter(&mut self, range: RangeInclusive<u16>) {
+        self.filter = Some(range);
+    }
+
+    /// Records held in ring buffer mode, oldest first
+    pub fn get_records(&self) -> impl Iterator<Item = &TraceRecord> {
+        let records = match &self.sink {
+            Sink::Ring { records, .. } => Some(records.iter()),
+            Sink::Writer(_) => None,
+        };
+        records.into_iter().flatten()
+    }
+
+    /// Writes the records held in ring buffer mode as a trace
+    pub fn write_records(&self, writer: &mut impl Write, format: TraceFormat, mode: Mode) -> io::Result<()> {
+        if format == TraceFormat::Binary {
+            write_header(writer)?;
+        }
+        for record in self.get_records() {
+            write_record(writer, record, format, mode)?;
+        }
+        writer.flush()
+    }
+
+    /// Flushes the trace, returning the first error writing it
+    pub fn finish(mut self) -> io::Result<()> {
+        if let Some(error) = self.error.take() {
+            return Err(error);
+        }
+        match &mut self.sink {
+            Sink::Writer(writer) => writer.flush(),
+            Sink::Ring { .. } => Ok(()),
+        }
+    }
+
+    /// Counts an instruction about to execute, recording it unless filtered
+    pub(crate) fn trace(&mut self, record: impl FnOnce(u64) -> TraceRecord, pc: u16, mode: Mode) {
+        let cycle = self.cycle;
+        self.cycle += 1;
+        if self.filter.as_ref().is_some_and(|range| !range.contains(&pc)) {
+            return;
+        }
+        let record = record(cycle);
+        match &mut self.sink {
+            Sink::Ring { records, capacity } => {
+                if records.len() == *capacity {
+                    records.pop_front();
+                }
+                if *capacity > 0 {
+                    records.push_back(record);
+                }
+            }
+            Sink::Writer(writer) => {
+                // Tracing stops at the first error rather than failing the emulator
+                if self.error.is_some() {
+                    return;
+                }
+                let mut result = Ok(());
+                if !self.started && self.format == TraceFormat::Binary {
+                    result = write_header(writer);
+                }
+                self.started = true;
+                if let Err(error) = result.and_then(|()| write_record(writer, &record, self.format, mode)) {
+                    self.error = Some(error);
+                }
+            }
+        }
+    }
+}
+
+fn write_header(writer: &mut impl Write) -> io::Result<()> {
+    writer.write_all(&MAGIC)?;
+    writer.write_all(&VERSION.to_be_bytes())
+}
+
+fn write_record(writer: &mut impl Write, record: &TraceRecord, format: TraceFormat, mode: Mode) -> io::Result<()> {
+    match format {
+        TraceFormat::Text => writeln!(writer, "{}", record.to_line(mode)),
+        TraceFormat::Binary => writer.write_all(&record.to_bytes()),
+    }
+}
+
+/// Reads records from a text or binary trace, telling them apart by the
+/// binary magic. Blank lines and lines starting with `#` are skipped.
+pub struct TraceReader<R> {
+    reader: R,
+    format: Option<TraceFormat>,
+    line: usize,
+}
+
+impl<R: BufRead> TraceReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self { reader, format: None, line: 0 }
+    }
+
+    fn detect_format(&mut self) -> Result<TraceFormat, String> {
+        let start = self.reader.fill_buf().map_err(|err| err.to_string())?;
+        if !start.starts_with(&MAGIC) {
+            return Ok(TraceFormat::Text);
+        }
+        let mut header = [0; 6];
+        self.reader.read_exact(&mut header).map_err(|err| err.to_string())?;
+        match u16::from_be_bytes([header[4], header[5]]) {
+            VERSION => Ok(TraceFormat::Binary),
+            version => Err(format!("unsupported trace version {version}")),
+        }
+    }
+
+    fn next_binary(&mut self) -> Option<Result<TraceRecord, String>> {
+        let mut bytes = [0; RECORD_SIZE];
+        let mut len = 0;
+        while len < RECORD_SIZE {
+            match self.reader.read(&mut bytes[len..]) {
+                Ok(0) if len == 0 => return None,
+                Ok(0) => return Some(Err("trace ends in the middle of a record".to_string())),
+                Ok(read) => len += read,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Some(Err(err.to_string())),
+            }
+        }
+        self.line += 1;
+        Some(Ok(TraceRecord::from_bytes(&bytes)))
+    }
+
+    fn next_text(&mut self) -> Option<Result<TraceRecord, String>> {
+        let mut line = String::new();
+        loop {
+            line.clear();
+            match self.reader.read_line(&mut line) {
+                Ok(0) => return None,
+                Ok(_) => self.line += 1,
+                Err(err) => return Some(Err(err.to_string())),
+            }
+            let text = line.trim();
+            if !text.is_empty() && !text.starts_with('#') {
+                let line_number = self.line;
+                return Some(TraceRecord::parse_line(text).map_err(|err| format!("line {line_number}: {err}")));
+            }
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for TraceReader<R> {
+    type Item = Result<TraceRecord, String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let format = match self.format {
+            Some(format) => format,
+            None => match self.detect_format() {
+                Ok(format) => *self.format.insert(format),
+                Err(err) => return Some(Err(err)),
+            },
+        };
+        match format {
+            TraceFormat::Text => self.next_text(),
+            TraceFormat::Binary => self.next_binary(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,123 @@
+use std::env;
+use std::fs;
+use std::io::Cursor;
+use super::*;
+use crate::asm::assemble;
+use crate::config::Config;
+use crate::emulator::Emulator;
+
+const PROGRAM: &str = "
+loop:   ADD V0, 1       ; 200
+        LD I, 0x300     ; 202
+        CALL sub        ; 204
+        JP loop         ; 206
+sub:    LD DT, V0       ; 208
+        RET             ; 20A
+";
+
+fn emulator() -> Emulator {
+    let mut emulator = Emulator::with_config(Config::default());
+    emulator.load_rom(&assemble(PROGRAM).unwrap()).unwrap();
+    emulator
+}
+
+fn run(emulator: &mut Emulator, instructions: usize) {
+    for _ in 0..instructions {
+        emulator.tick().unwrap();
+    }
+}
+
+#[test]
+fn records_state_before_each_instruction() {
+    let mut emulator = emulator();
+    emulator.set_tracer(Tracer::ring(100));
+    run(&mut emulator, 7);
+    let records: Vec<_> = emulator.take_tracer().unwrap().get_records().copied().collect();
+    assert_eq!(records.len(), 7);
+    let pcs: Vec<_> = records.iter().map(|record| record.pc).collect();
+    assert_eq!(pcs, [0x200, 0x202, 0x204, 0x208, 0x20A, 0x206, 0x200]);
+    assert_eq!(records[1].v_registers[0], 1);
+    assert_eq!(records[4].stack_pointer, 1);
+    assert_eq!(records[4].delay_timer, 1);
+    assert_eq!(records[6].cycle, 6);
+    assert_eq!(records[3].opcode, 0xF015);
+    assert_eq!(
+        records[1].to_line(Mode::Chip8),
+        "cycle=1 pc=0202 op=A300 v=01000000000000000000000000000000 i=0000 sp=00 dt=00 st=00 ; LD I, 0x300"
+    );
+}
+
+#[test]
+fn ring_buffer_keeps_the_newest_records_in_range() {
+    let mut emulator = emulator();
+    let mut tracer = Tracer::ring(3);
+    tracer.set_filter(0x208..=0x20A);
+    emulator.set_tracer(tracer);
+    run(&mut emulator, 20);
+    let tracer = emulator.get_tracer().unwrap();
+    let cycles: Vec<_> = tracer.get_records().map(|record| record.cycle).collect();
+    // Cycles still count the filtered instructions
+    assert_eq!(cycles, [10, 15, 16]);
+    let mut out = Vec::new();
+    tracer.write_records(&mut out, TraceFormat::Text, Mode::Chip8).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap().lines().count(), 3);
+}
+
+#[test]
+fn text_and_binary_traces_read_back() {
+    let mut emulator = emulator();
+    emulator.set_tracer(Tracer::ring(10));
+    run(&mut emulator, 10);
+    let tracer = emulator.get_tracer().unwrap();
+    let records: Vec<_> = tracer.get_records().copied().collect();
+    for format in [TraceFormat::Text, TraceFormat::Binary] {
+        let mut out = Vec::new();
+        tracer.write_records(&mut out, format, Mode::Chip8).unwrap();
+        let read: Result<Vec<_>, _> = TraceReader::new(Cursor::new(out)).collect();
+        assert_eq!(read.unwrap(), records, "{format:?}");
+    }
+}
+
+#[test]
+fn writes_traces_as_it_runs() {
+    let path = env::temp_dir().join(format!("chip8-trace-{}.bin", std::process::id()));
+    let mut emulator = emulator();
+    emulator.set_tracer(Tracer::new(fs::File::create(&path).unwrap(), TraceFormat::Binary));
+    run(&mut emulator, 5);
+    // Tracing carries over loading a save state
+    emulator.load_state(&emulator.save_state()).unwrap();
+    run(&mut emulator, 5);
+    emulator.take_tracer().unwrap().finish().unwrap();
+    let data = fs::read(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    assert_eq!(data.len(), 6 + 10 * RECORD_SIZE);
+    let records: Vec<_> = TraceReader::new(&data[..]).map(Result::unwrap).collect();
+    assert_eq!(records[9].cycle, 9);
+}
+
+#[test]
+fn parses_lines_from_other_tools() {
+    let record = TraceRecord::parse_line("PC=0200 op=6005 I=0123 v=00112233445566778899AABBCCDDEEFF").unwrap();
+    assert_eq!(record.pc, 0x200);
+    assert_eq!(record.i_register, 0x123);
+    assert_eq!(record.v_registers[15], 0xFF);
+    assert_eq!(TraceRecord::parse_line("pc=02").unwrap().pc, 2);
+    assert_eq!(TraceRecord::parse_line("pc").unwrap_err(), "expected name=value: pc");
+    assert_eq!(TraceRecord::parse_line("v=00").unwrap_err(), "invalid v: 00");
+    assert_eq!(TraceRecord::parse_line("x=1").unwrap_err(), "unknown field: x");
+
+    let text = "# comment\n\ncycle=0 pc=0200\ncycle=1 pc=zz\n";
+    let results: Vec<_> = TraceReader::new(text.as_bytes()).collect();
+    assert_eq!(results[1], Err("line 4: invalid pc: zz".to_string()));
+}
+
+#[test]
+fn differences_name_the_fields() {
+    let record = TraceRecord::parse_line("cycle=3 pc=0200 v=00000000000000000000000000000000").unwrap();
+    let mut other = record;
+    assert!(record.differences(&other).is_empty());
+    other.v_registers[10] = 1;
+    other.delay_timer = 2;
+    assert_eq!(record.differences(&other), ["va", "dt"]);
+    assert!(is_field("va") && is_field("cycle") && !is_field("vg"));
+}
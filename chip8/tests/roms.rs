@@ -0,0 +1,202 @@
+//! Screen snapshot tests over the test programs in `tests/roms`.
+//!
+//! Each program runs for a fixed number of frames, then its screen is
+//! compared as ASCII art with `tests/roms/golden/NAME.txt`. After an
+//! intended change, run with `CHIP8_BLESS=1` to rewrite the goldens and
+//! review them in the diff.
+//!
+//! The reference suites are not committed. The IBM logo, corax+, flags,
+//! quirks and keypad ROMs come from Timendus' chip8-test-suite, which is
+//! GPL-3.0, and this repository declares no licence to check that against.
+//! The IBM logo has no stated licence of its own. Their cases are
+//! `#[ignore]`d: copy the `.ch8` files into `tests/roms` and run
+//! `CHIP8_BLESS=1 cargo test --test roms -- --ignored` to create the
+//! goldens.
+//!
+//! The `.8o` programs are written for this suite in the same spirit. They
+//! are built by this crate's own Octo compiler, so they only catch changes
+//! against the blessed output, not disagreements with other interpreters.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use chip8::config::{Config, Mode};
+use chip8::emulator::Emulator;
+use chip8::image::to_ascii;
+use chip8::octo;
+
+/// Frames every program runs for, long enough to finish drawing
+const FRAMES: u64 = 120;
+
+/// Scripted key press: frame, key and frames held
+type Press = (u64, u8, u64);
+
+fn roms_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("roms")
+}
+
+/// One program run compared with one golden
+struct Case<'a> {
+    name: &'a str,
+    file: &'a str,
+    mode: Mode,
+    frames: u64,
+    /// Bytes written after loading, e.g. to pick a menu entry
+    pokes: &'a [(usize, u8)],
+    presses: &'a [Press],
+}
+
+impl<'a> Case<'a> {
+    fn new(name: &'a str, file: &'a str, mode: Mode) -> Self {
+        Self { name, file, mode, frames: FRAMES, pokes: &[], presses: &[] }
+    }
+}
+
+fn load(file: &str) -> Vec<u8> {
+    let path = roms_dir().join(file);
+    if file.ends_with(".8o") {
+        octo::compile_file(&path).unwrap_or_else(|err| panic!("{err}"))
+    } else {
+        fs::read(&path)
+            .unwrap_or_else(|err| panic!("Failed to read {}, see the notes in roms.rs: {err}", path.display()))
+    }
+}
+
+/// Runs a case and checks its screen against its golden
+fn check(case: Case) {
+    let Case { name, file, mode, frames, pokes, presses } = case;
+    let mut emulator = Emulator::with_config(Config { seed: Some(1), ..Config::for_mode(mode) });
+    emulator.load_rom(&load(file)).unwrap();
+    for &(address, value) in pokes {
+        emulator.write_memory(address, &[value]).unwrap();
+    }
+    for frame in 0..frames {
+        for &(start, key, hold) in presses {
+            if frame == start {
+                emulator.key_down(key);
+            } else if frame == start + hold {
+                emulator.key_up(key);
+            }
+        }
+        emulator.run_frame().unwrap_or_else(|err| panic!("{file} faulted in frame {frame}: {err}"));
+    }
+    let actual = to_ascii(&emulator.get_framebuffer());
+
+    let golden_path = roms_dir().join("golden").join(format!("{name}.txt"));
+    if env::var_os("CHIP8_BLESS").is_some() {
+        fs::write(&golden_path, &actual).unwrap();
+        return;
+    }
+    let expected = fs::read_to_string(&golden_path)
+        .unwrap_or_else(|_| panic!("No golden for {name}, run with CHIP8_BLESS=1 to create it"));
+    if expected != actual {
+        panic!("Screen of {name} differs from its golden\n{}", diff(&expected, &actual));
+    }
+}
+
+/// Expected and actual screens side by side, with differing rows marked
+fn diff(expected: &str, actual: &str) -> String {
+    let expected: Vec<&str> = expected.lines().collect();
+    let actual: Vec<&str> = actual.lines().collect();
+    let width = expected.iter().map(|line| line.len()).chain([8]).max().unwrap_or(0);
+    let mut pixels = 0;
+    let mut out = format!("  {:width$} | actual\n", "expected");
+    for row in 0..expected.len().max(actual.len()) {
+        let (left, right) = (expected.get(row).copied().unwrap_or(""), actual.get(row).copied().unwrap_or(""));
+        let differing = left.chars().zip(right.chars()).filter(|(a, b)| a != b).count()
+            + left.len().abs_diff(right.len());
+        pixels += differing;
+        let marker = if differing > 0 { '>' } else { ' ' };
+        out.push_str(&format!("{marker} {left:width$} | {right}\n"));
+    }
+    out.push_str(&format!("{pixels} pixels differ"));
+    out
+}
+
+#[test]
+fn logo() {
+    check(Case::new("logo", "logo.8o", Mode::Chip8));
+}
+
+#[test]
+fn opcodes() {
+    check(Case::new("opcodes", "opcodes.8o", Mode::Chip8));
+}
+
+#[test]
+fn flags() {
+    check(Case::new("flags", "flags.8o", Mode::Chip8));
+}
+
+#[test]
+fn quirks_chip8() {
+    check(Case::new("quirks-chip8", "quirks.8o", Mode::Chip8));
+}
+
+#[test]
+fn quirks_schip() {
+    check(Case::new("quirks-schip", "quirks.8o", Mode::SuperChip));
+}
+
+#[test]
+fn quirks_xochip() {
+    check(Case::new("quirks-xochip", "quirks.8o", Mode::XoChip));
+}
+
+#[test]
+fn keypad() {
+    // Key A completes FX0A on release, then key 2 is held
+    check(Case { presses: &[(10, 0xA, 2), (30, 0x2, 5)], ..Case::new("keypad", "keypad.8o", Mode::Chip8) });
+}
+
+/// Address the Timendus quirks and keypad ROMs read a menu choice from
+const MENU_CHOICE: usize = 0x1FF;
+
+/// Frames for the Timendus quirks ROM, whose display wait test takes a
+/// few seconds
+const QUIRKS_FRAMES: u64 = 600;
+
+#[test]
+#[ignore = "needs 2-ibm-logo.ch8, see the module docs"]
+fn timendus_ibm_logo() {
+    check(Case::new("timendus-ibm-logo", "2-ibm-logo.ch8", Mode::Chip8));
+}
+
+#[test]
+#[ignore = "needs 3-corax+.ch8, see the module docs"]
+fn timendus_corax_plus() {
+    check(Case::new("timendus-corax-plus", "3-corax+.ch8", Mode::Chip8));
+}
+
+#[test]
+#[ignore = "needs 4-flags.ch8, see the module docs"]
+fn timendus_flags() {
+    check(Case::new("timendus-flags", "4-flags.ch8", Mode::Chip8));
+}
+
+#[test]
+#[ignore = "needs 5-quirks.ch8, see the module docs"]
+fn timendus_quirks() {
+    for (name, mode, choice) in [
+        ("timendus-quirks-chip8", Mode::Chip8, 1),
+        ("timendus-quirks-schip", Mode::SuperChip, 2),
+        ("timendus-quirks-xochip", Mode::XoChip, 3),
+    ] {
+        let pokes = &[(MENU_CHOICE, choice)];
+        check(Case { frames: QUIRKS_FRAMES, pokes, ..Case::new(name, "5-quirks.ch8", mode) });
+    }
+}
+
+#[test]
+#[ignore = "needs 6-keypad.ch8, see the module docs"]
+fn timendus_keypad() {
+    // The FX0A test, completed by pressing and releasing key A
+    let pokes = &[(MENU_CHOICE, 3)];
+    check(Case { pokes, presses: &[(10, 0xA, 2)], ..Case::new("timendus-keypad", "6-keypad.ch8", Mode::Chip8) });
+}
+
+#[test]
+fn diff_marks_differing_rows() {
+    let text = diff("..\n##\n", "..\n#.\n");
+    assert_eq!(text, "  expected | actual\n  ..       | ..\n> ##       | #.\n1 pixels differ");
+}
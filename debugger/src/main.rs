@@ -0,0 +1,452 @@
+use std::env;
+use std::io::{self, BufRead, Write};
+use std::net::TcpListener;
+use std::ops::RangeInclusive;
+use std::process;
+use chip8::config::{Config, Mode};
+use chip8::cpu::NUMBER_OF_REGISTERS;
+use chip8::debug::{Debugger, Expression, Stop, Watchpoint};
+use chip8::emulator::Emulator;
+use chip8::gdb::GdbServer;
+use chip8::instruction::Instruction;
+use chip8::memory::Access;
+use chip8::octo;
+
+const USAGE: &str = "Usage: chip8-debugger [--mode chip8|schip|xochip] [--seed N] [--gdb HOST:PORT] <rom | source.8o>";
+
+const HELP: &str = "\
+Addresses and values are hex, counts are decimal. An empty line repeats the last command.
+
+  s, step [N]              execute N instructions
+  n, next                  step over subroutine calls
+  c, continue              run until a breakpoint or watchpoint
+  rs, reverse-step         undo the last instruction
+  rc, reverse-continue     run backwards to the previous breakpoint or watchpoint
+  finish                   run until the current subroutine returns
+  u, until ADDR            run until ADDR or the current subroutine returns
+  b, break ADDR [if EXPR]  set a breakpoint, stopping only when EXPR holds
+  d, delete ADDR           remove a breakpoint
+  breakpoints              list breakpoints
+  watch ADDR[-END] [rwx]   stop after reads, writes or fetches (default w)
+  watch if EXPR            stop when EXPR turns true
+  unwatch N                remove watchpoint N
+  watchpoints              list watchpoints
+  p, print EXPR            evaluate an expression
+  r, regs                  show registers and timers
+  set V0-VF|I|PC VALUE     change a register
+  x ADDR [LEN]             dump LEN bytes of memory
+  w, write ADDR BYTE...    write bytes to memory
+  l, list [ADDR]           disassemble around the PC or at ADDR
+  bt, backtrace            show the call stack
+  key K down|up            press or release keypad key K
+  q, quit                  exit the debugger
+
+Expressions use V0-VF, I, PC, SP, DT, ST, [ADDR] for a byte of memory and C
+operators, e.g. 'V3 == 0x10 && I > 0x300'.";
+
+/// Instructions listed before and after the PC by `list`
+const LIST_CONTEXT: u16 = 4;
+
+/// Command line options
+struct Args {
+    rom_path: String,
+    mode: Mode,
+    /// Seed for CXNN, to reproduce a run
+    seed: Option<u64>,
+    /// Address to serve the GDB remote protocol on instead of the prompt
+    gdb: Option<String>,
+}
+
+fn main() {
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(message) => {
+            eprintln!("{message}\n{USAGE}");
+            process::exit(2);
+        }
+    };
+    if let Err(message) = run(args) {
+        eprintln!("{message}");
+        process::exit(1);
+    }
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut rom_path = None;
+    let mut mode = Mode::Chip8;
+    let mut seed = None;
+    let mut gdb = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--mode" => mode = args.next().ok_or("Missing mode")?.parse()?,
+            "--seed" => {
+                let value = args.next().ok_or("Missing seed")?;
+                seed = Some(value.parse().map_err(|_| format!("Invalid seed: {value}"))?);
+            }
+            "--gdb" => gdb = Some(args.next().ok_or("Missing GDB address")?),
+            _ if rom_path.is_none() => rom_path = Some(arg),
+            _ => return Err(format!("Unexpected argument: {arg}")),
+        }
+    }
+    let rom_path = rom_path.ok_or("Missing ROM path")?;
+    Ok(Args { rom_path, mode, seed, gdb })
+}
+
+fn run(args: Args) -> Result<(), String> {
+    let mut emulator = Emulator::with_config(Config { seed: args.seed, ..Config::for_mode(args.mode) });
+    if args.rom_path.ends_with(".8o") {
+        let rom = octo::compile_file(&args.rom_path).map_err(|err| err.to_string())?;
+        emulator
+            .load_rom(&rom)
+            .map_err(|err| format!("Failed to load {}: {err}", args.rom_path))?;
+    } else {
+        emulator
+            .load_rom_file(&args.rom_path)
+            .map_err(|err| format!("Failed to load {}: {err}", args.rom_path))?;
+    }
+    let mut debugger = Debugger::new(emulator);
+    if let Some(address) = args.gdb {
+        return serve_gdb(debugger, &address);
+    }
+    print_location(&debugger);
+
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    let mut last_command = String::new();
+    loop {
+        print!("(chip8) ");
+        io::stdout().flush().map_err(|err| err.to_string())?;
+        let Some(line) = lines.next() else {
+            return Ok(());
+        };
+        let line = line.map_err(|err| err.to_string())?;
+        let command = if line.trim().is_empty() { last_command.clone() } else { line.trim().to_string() };
+        match execute(&mut debugger, &command) {
+            Ok(true) => {}
+            Ok(false) => return Ok(()),
+            Err(message) => println!("{message}"),
+        }
+        last_command = command;
+    }
+}
+
+/// Waits for a GDB client on `address` and serves it until it detaches
+fn serve_gdb(debugger: Debugger, address: &str) -> Result<(), String> {
+    let listener = TcpListener::bind(address).map_err(|err| format!("Failed to listen on {address}: {err}"))?;
+    let local_address = listener.local_addr().map_err(|err| err.to_string())?;
+    println!("Waiting for GDB on {local_address}");
+    io::stdout().flush().map_err(|err| err.to_string())?;
+    let (stream, _) = listener.accept().map_err(|err| err.to_string())?;
+    GdbServer::new(debugger).serve(stream).map_err(|err| format!("GDB connection failed: {err}"))
+}
+
+/// Runs one debugger command, returning false to quit
+fn execute(debugger: &mut Debugger, command: &str) -> Result<bool, String> {
+    let words: Vec<&str> = command.split_whitespace().collect();
+    let Some((&name, operands)) = words.split_first() else {
+        return Ok(true);
+    };
+    match (name, operands) {
+        ("s" | "step", [] | [_]) => {
+            let count = match operands.first() {
+                Some(count) => count.parse().map_err(|_| format!("Invalid count: {count}"))?,
+                None => 1,
+            };
+            let mut stop = Stop::Done;
+            for _ in 0..count {
+                stop = debugger.step();
+                if !matches!(stop, Stop::Done) {
+                    break;
+                }
+            }
+            report(debugger, stop);
+        }
+        ("n" | "next", []) => {
+            let stop = debugger.step_over();
+            report(debugger, stop);
+        }
+        ("c" | "continue", []) => {
+            let stop = debugger.resume();
+            report(debugger, stop);
+        }
+        ("rs" | "reverse-step", []) => {
+            let stop = debugger.reverse_step();
+            report(debugger, stop);
+        }
+        ("rc" | "reverse-continue", []) => {
+            let stop = debugger.reverse_continue();
+            report(debugger, stop);
+        }
+        ("finish", []) => match debugger.finish() {
+            Some(stop) => report(debugger, stop),
+            None => return Err("Not in a subroutine".to_string()),
+        },
+        ("u" | "until", [address]) => {
+            let stop = debugger.run_until(parse_address(address)?);
+            report(debugger, stop);
+        }
+        ("b" | "break", [address]) => {
+            let address = parse_address(address)?;
+            if !debugger.add_breakpoint(address) {
+                return Err(format!("Breakpoint already set at {address:03X}"));
+            }
+            println!("Breakpoint set at {address:03X}");
+        }
+        ("b" | "break", [address, "if", condition @ ..]) if !condition.is_empty() => {
+            let address = parse_address(address)?;
+            let condition: Expression = condition.join(" ").parse()?;
+            println!("Breakpoint set at {address:03X} if {condition}");
+            debugger.add_conditional_breakpoint(address, condition);
+        }
+        ("watch", ["if", condition @ ..]) if !condition.is_empty() => {
+            let condition = condition.join(" ").parse()?;
+            let number = debugger.add_watchpoint(Watchpoint::Condition(condition));
+            println!("Watchpoint {number} set");
+        }
+        ("watch", [range] | [range, _]) => {
+            let range = parse_range(range)?;
+            let accesses = parse_accesses(operands.get(1).copied().unwrap_or("w"))?;
+            let number = debugger.add_watchpoint(Watchpoint::Memory { range, accesses });
+            println!("Watchpoint {number} set");
+        }
+        ("unwatch", [number]) => {
+            let number = number.parse().map_err(|_| format!("Invalid watchpoint: {number}"))?;
+            if !debugger.remove_watchpoint(number) {
+                return Err(format!("No watchpoint {number}"));
+            }
+        }
+        ("watchpoints", []) => {
+            if debugger.get_watchpoints().is_empty() {
+                println!("No watchpoints");
+            }
+            for (number, watchpoint) in debugger.get_watchpoints() {
+                println!("  {number}: {}", format_watchpoint(watchpoint));
+            }
+        }
+        ("p" | "print", expression @ [_, ..]) => {
+            let expression: Expression = expression.join(" ").parse()?;
+            let value = expression.evaluate(debugger.get_emulator());
+            println!("{value} ({value:#X})");
+        }
+        ("d" | "delete", [address]) => {
+            let address = parse_address(address)?;
+            if !debugger.remove_breakpoint(address) {
+                return Err(format!("No breakpoint at {address:03X}"));
+            }
+        }
+        ("breakpoints", []) => {
+            if debugger.get_breakpoints().is_empty() {
+                println!("No breakpoints");
+            }
+            for &address in debugger.get_breakpoints() {
+                match debugger.get_breakpoint_condition(address) {
+                    Some(condition) => println!("  {} if {condition}", format_line(debugger, address)),
+                    None => println!("  {}", format_line(debugger, address)),
+                }
+            }
+        }
+        ("r" | "regs", []) => print_registers(debugger),
+        ("set", [register, value]) => set_register(debugger, register, value)?,
+        ("x", [address] | [address, _]) => {
+            let address = parse_address(address)? as usize;
+            let len = match operands.get(1) {
+                Some(len) => len.parse().map_err(|_| format!("Invalid length: {len}"))?,
+                None => 16,
+            };
+            dump_memory(debugger, address, len);
+        }
+        ("w" | "write", [address, bytes @ ..]) if !bytes.is_empty() => {
+            let address = parse_address(address)? as usize;
+            let bytes = bytes.iter().map(|byte| parse_byte(byte)).collect::<Result<Vec<_>, _>>()?;
+            debugger
+                .get_emulator_mut()
+                .write_memory(address, &bytes)
+                .map_err(|err| err.to_string())?;
+        }
+        ("l" | "list", []) => {
+            let pc = debugger.get_emulator().get_cpu_state().program_counter;
+            list(debugger, pc.saturating_sub(LIST_CONTEXT * 2), LIST_CONTEXT as usize * 2 + 1);
+        }
+        ("l" | "list", [address]) => list(debugger, parse_address(address)?, LIST_CONTEXT as usize * 2 + 1),
+        ("bt" | "backtrace", []) => {
+            for (depth, address) in debugger.backtrace().into_iter().enumerate() {
+                println!("#{depth} {}", format_line(debugger, address));
+            }
+        }
+        ("key", [key, state @ ("down" | "up")]) => {
+            let key = u8::from_str_radix(key, 16)
+                .ok()
+                .filter(|&key| key < 0x10)
+                .ok_or_else(|| format!("Invalid key: {key}"))?;
+            if *state == "down" {
+                debugger.get_emulator_mut().key_down(key);
+            } else {
+                debugger.get_emulator_mut().key_up(key);
+            }
+        }
+        ("h" | "help", []) => println!("{HELP}"),
+        ("q" | "quit", []) => return Ok(false),
+        _ => return Err(format!("Unknown command: {command} (try 'help')")),
+    }
+    Ok(true)
+}
+
+/// Parses a hex address, with or without a `0x` prefix
+fn parse_address(text: &str) -> Result<u16, String> {
+    let digits = text.strip_prefix("0x").unwrap_or(text);
+    u16::from_str_radix(digits, 16).map_err(|_| format!("Invalid address: {text}"))
+}
+
+/// Parses `ADDR` or `ADDR-END`
+fn parse_range(text: &str) -> Result<RangeInclusive<usize>, String> {
+    let (start, end) = text.split_once('-').unwrap_or((text, text));
+    let (start, end) = (parse_address(start)? as usize, parse_address(end)? as usize);
+    if end < start {
+        return Err(format!("Invalid range: {text}"));
+    }
+    Ok(start..=end)
+}
+
+/// Parses access flags, `r` for reads, `w` for writes and `x` for fetches
+fn parse_accesses(text: &str) -> Result<Vec<Access>, String> {
+    text.chars()
+        .map(|flag| match flag {
+            'r' => Ok(Access::Read),
+            'w' => Ok(Access::Write),
+            'x' => Ok(Access::Fetch),
+            _ => Err(format!("Invalid access flags: {text}")),
+        })
+        .collect()
+}
+
+fn format_watchpoint(watchpoint: &Watchpoint) -> String {
+    match watchpoint {
+        Watchpoint::Memory { range, accesses } => {
+            let flags: String = accesses
+                .iter()
+                .map(|access| match access {
+                    Access::Read => 'r',
+                    Access::Write => 'w',
+                    Access::Fetch => 'x',
+                })
+                .collect();
+            format!("{:03X}-{:03X} {flags}", range.start(), range.end())
+        }
+        Watchpoint::Condition(condition) => condition.to_string(),
+    }
+}
+
+fn parse_byte(text: &str) -> Result<u8, String> {
+    let digits = text.strip_prefix("0x").unwrap_or(text);
+    u8::from_str_radix(digits, 16).map_err(|_| format!("Invalid byte: {text}"))
+}
+
+fn set_register(debugger: &mut Debugger, register: &str, value: &str) -> Result<(), String> {
+    let emulator = debugger.get_emulator_mut();
+    match register.to_ascii_uppercase().as_str() {
+        "I" => emulator.set_i_register(parse_address(value)?),
+        "PC" => emulator.set_program_counter(parse_address(value)?),
+        name => {
+            let x = name
+                .strip_prefix('V')
+                .and_then(|digit| usize::from_str_radix(digit, 16).ok())
+                .filter(|&x| x < NUMBER_OF_REGISTERS)
+                .ok_or_else(|| format!("Unknown register: {register}"))?;
+            emulator.set_register(x, parse_byte(value)?);
+        }
+    }
+    Ok(())
+}
+
+/// Prints why execution stopped and where
+fn report(debugger: &Debugger, stop: Stop) {
+    match stop {
+        Stop::Done => {}
+        Stop::Breakpoint(address) => println!("Breakpoint at {address:03X}"),
+        Stop::WaitingForKey => println!("Waiting for a key, press one with 'key K down'"),
+        Stop::Exited => println!("Program exited"),
+        Stop::Halted => println!("Machine is halted"),
+        Stop::Fault(err) => println!("Fault: {err}"),
+        Stop::LimitReached => println!("Stopped after {} instructions", debugger.get_cycles()),
+        Stop::MemoryWatch { number, access } => {
+            let kind = match access.access {
+                Access::Fetch => "fetch",
+                Access::Read => "read",
+                Access::Write => "write",
+            };
+            println!("Watchpoint {number}: {kind} of {:02X} at {:03X}", access.value, access.address);
+        }
+        Stop::Condition { number } => {
+            let watchpoint = &debugger.get_watchpoints()[&number];
+            println!("Watchpoint {number}: {}", format_watchpoint(watchpoint));
+        }
+        Stop::HistoryStart => println!("Reached the start of the recorded history"),
+    }
+    print_location(debugger);
+}
+
+fn print_location(debugger: &Debugger) {
+    let pc = debugger.get_emulator().get_cpu_state().program_counter;
+    println!("=> {}", format_line(debugger, pc));
+}
+
+/// Address, opcode and mnemonic of the instruction at `address`
+fn format_line(debugger: &Debugger, address: u16) -> String {
+    let ram = debugger.get_emulator().get_ram();
+    let Some((_, instruction)) = debugger.disassemble(address, 1).pop() else {
+        return format!("{address:03X}: <out of memory>");
+    };
+    let byte = |offset: u16| ram.get(address as usize + offset as usize).copied().unwrap_or_default();
+    let word = |offset: u16| u16::from_be_bytes([byte(offset), byte(offset + 1)]);
+    match instruction {
+        Instruction::SetILong => format!("{address:03X}: {:04X} {:04X}  LD I, {:#06X}", word(0), word(2), word(2)),
+        _ => format!("{address:03X}: {:04X}       {instruction}", word(0)),
+    }
+}
+
+fn list(debugger: &Debugger, address: u16, count: usize) {
+    let pc = debugger.get_emulator().get_cpu_state().program_counter;
+    for (line_address, _) in debugger.disassemble(address, count) {
+        let marker = if line_address == pc { "=>" } else { "  " };
+        let breakpoint = if debugger.get_breakpoints().contains(&line_address) { "*" } else { " " };
+        println!("{marker}{breakpoint}{}", format_line(debugger, line_address));
+    }
+}
+
+fn print_registers(debugger: &Debugger) {
+    let emulator = debugger.get_emulator();
+    let state = emulator.get_cpu_state();
+    for (row, values) in state.v_registers.chunks(8).enumerate() {
+        let cells: Vec<String> = values
+            .iter()
+            .enumerate()
+            .map(|(column, value)| format!("V{:X}={value:02X}", row * 8 + column))
+            .collect();
+        println!("{}", cells.join(" "));
+    }
+    let memory = emulator.get_memory();
+    println!(
+        "I={:04X} PC={:04X} SP={} DT={:02X} ST={:02X} frame={} cycles={}",
+        state.i_register,
+        state.program_counter,
+        state.stack_pointer,
+        memory.get_delay_timer(),
+        memory.get_sound_timer(),
+        emulator.get_frame_count(),
+        debugger.get_cycles(),
+    );
+}
+
+fn dump_memory(debugger: &Debugger, address: usize, len: usize) {
+    let ram = debugger.get_emulator().get_ram();
+    let end = address.saturating_add(len).min(ram.len());
+    if address >= end {
+        println!("Address {address:03X} is outside of memory");
+        return;
+    }
+    for (row, bytes) in ram[address..end].chunks(16).enumerate() {
+        let hex: Vec<String> = bytes.iter().map(|byte| format!("{byte:02X}")).collect();
+        println!("{:03X}: {}", address + row * 16, hex.join(" "));
+    }
+}
@@ -0,0 +1,106 @@
+//! Drives `chip8-debugger --gdb` with a scripted remote protocol client
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::process::{Child, Command, Stdio};
+use std::{env, fs};
+
+/// LD V0, 5; LD I, 0x20A; ADD V0, 1; JP 0x204; then two data bytes
+const ROM: [u8; 12] = [0x60, 0x05, 0xA2, 0x0A, 0x70, 0x01, 0x12, 0x04, 0x00, 0x00, 0xBE, 0xEF];
+
+struct Client {
+    stream: TcpStream,
+    server: Child,
+}
+
+impl Client {
+    fn start(name: &str) -> Self {
+        let rom_path = env::temp_dir().join(format!("chip8-gdb-{name}-{}.ch8", std::process::id()));
+        fs::write(&rom_path, ROM).unwrap();
+        let mut server = Command::new(env!("CARGO_BIN_EXE_chip8-debugger"))
+            .args(["--gdb", "127.0.0.1:0"])
+            .arg(&rom_path)
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut line = String::new();
+        BufReader::new(server.stdout.take().unwrap()).read_line(&mut line).unwrap();
+        let address = line.trim().strip_prefix("Waiting for GDB on ").unwrap().to_string();
+        let stream = TcpStream::connect(address).unwrap();
+        fs::remove_file(rom_path).unwrap();
+        Self { stream, server }
+    }
+
+    fn read_byte(&mut self) -> u8 {
+        let mut byte = [0];
+        self.stream.read_exact(&mut byte).unwrap();
+        byte[0]
+    }
+
+    /// Sends a packet and returns the reply, checking both acks
+    fn request(&mut self, data: &str) -> String {
+        let checksum = data.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+        write!(self.stream, "${data}#{checksum:02x}").unwrap();
+        assert_eq!(self.read_byte(), b'+');
+        self.reply()
+    }
+
+    fn reply(&mut self) -> String {
+        assert_eq!(self.read_byte(), b'$');
+        let mut data = Vec::new();
+        loop {
+            match self.read_byte() {
+                b'#' => break,
+                byte => data.push(byte),
+            }
+        }
+        let checksum = [self.read_byte(), self.read_byte()];
+        let expected = data.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte));
+        assert_eq!(std::str::from_utf8(&checksum).unwrap(), format!("{expected:02x}"));
+        self.stream.write_all(b"+").unwrap();
+        String::from_utf8(data).unwrap()
+    }
+
+    fn finish(mut self) {
+        assert_eq!(self.request("D"), "OK");
+        assert!(self.server.wait().unwrap().success());
+    }
+}
+
+#[test]
+fn inspects_and_steps() {
+    let mut client = Client::start("step");
+    assert!(client.request("qSupported:swbreak+").contains("PacketSize"));
+    assert_eq!(client.request("?"), "S05");
+    assert_eq!(client.request("p11"), "0200");
+    assert_eq!(client.request("m20a,2"), "beef");
+
+    assert_eq!(client.request("s"), "S05");
+    assert_eq!(client.request("p0"), "05");
+    assert_eq!(client.request("Z0,206,2"), "OK");
+    assert_eq!(client.request("c"), "S05");
+    assert_eq!(client.request("p11"), "0206");
+    assert_eq!(client.request("p10"), "020a");
+    assert_eq!(client.request("p0"), "06");
+
+    assert_eq!(client.request("M20a,1:42"), "OK");
+    assert_eq!(client.request("m20a,2"), "42ef");
+    client.finish();
+}
+
+#[test]
+fn interrupts_a_running_target() {
+    let mut client = Client::start("interrupt");
+    // Corrupt packets are rejected and resent
+    client.stream.write_all(b"$?#00").unwrap();
+    assert_eq!(client.read_byte(), b'-');
+    assert_eq!(client.request("QStartNoAckMode"), "OK");
+
+    client.stream.write_all(b"$c#63").unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(50));
+    client.stream.write_all(&[0x03]).unwrap();
+    assert_eq!(client.reply(), "S02");
+    client.stream.write_all(b"$D#44").unwrap();
+    assert_eq!(client.reply(), "OK");
+    assert!(client.server.wait().unwrap().success());
+}
@@ -0,0 +1,59 @@
+use std::env;
+use std::fs;
+use std::process;
+use chip8::disasm::{disassemble, DisasmOptions, Syntax};
+
+const USAGE: &str = "Usage: chip8-disasm [--mode chip8|schip|xochip] [--syntax classic|octo] [--no-annotate] <rom>";
+
+/// Command line options
+struct Args {
+    rom_path: String,
+    options: DisasmOptions,
+}
+
+fn main() {
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(message) => {
+            eprintln!("{message}\n{USAGE}");
+            process::exit(2);
+        }
+    };
+    let rom = match fs::read(&args.rom_path) {
+        Ok(rom) => rom,
+        Err(err) => {
+            eprintln!("Failed to read {}: {err}", args.rom_path);
+            process::exit(1);
+        }
+    };
+    match disassemble(&rom, &args.options) {
+        Ok(listing) => print!("{listing}"),
+        Err(err) => {
+            eprintln!("Failed to disassemble {}: {err}", args.rom_path);
+            process::exit(1);
+        }
+    }
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut rom_path = None;
+    let mut options = DisasmOptions::default();
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--mode" => options.mode = args.next().ok_or("Missing mode")?.parse()?,
+            "--syntax" => {
+                options.syntax = match args.next().ok_or("Missing syntax")?.as_str() {
+                    "classic" => Syntax::Classic,
+                    "octo" => Syntax::Octo,
+                    other => return Err(format!("Unknown syntax: {other}")),
+                }
+            }
+            "--no-annotate" => options.annotate = false,
+            _ if rom_path.is_none() => rom_path = Some(arg),
+            _ => return Err(format!("Unexpected argument: {arg}")),
+        }
+    }
+    let rom_path = rom_path.ok_or("Missing ROM path")?;
+    Ok(Args { rom_path, options })
+}
@@ -0,0 +1,263 @@
+use std::env;
+use std::fmt::Write as _;
+use std::fs;
+use std::io::{self, Write};
+use std::process;
+use chip8::config::{Config, Mode};
+use chip8::emulator::{Emulator, StepOutcome};
+use chip8::error::Chip8Error;
+use chip8::image::{to_ascii, to_pbm, to_png};
+use chip8::instruction::{decode_for, Instruction};
+use chip8::octo;
+use chip8::trace::{TraceFormat, Tracer};
+
+const USAGE: &str = "\
+Usage: chip8-headless [--mode chip8|schip|xochip] [--seed N] [--frames N] [--until-loop]
+                      [--press FRAME:KEY[:HOLD]]... [--screen FILE] [--registers FILE]
+                      [--trace FILE] <rom | source.8o>
+
+Screens are written as PNG or PBM by extension, as ASCII otherwise. A FILE
+of '-' writes to stdout. Exits with 1 when the emulator faults.";
+
+/// Frames run when no count is given, ten seconds of emulated time
+const DEFAULT_FRAMES: u64 = 600;
+
+/// Scripted key press
+struct Press {
+    frame: u64,
+    key: u8,
+    /// Frames the key is held down
+    hold: u64,
+}
+
+/// Command line options
+struct Args {
+    rom_path: String,
+    mode: Mode,
+    /// Seed for CXNN, to reproduce a run
+    seed: Option<u64>,
+    frames: u64,
+    /// Stop early at an instruction that jumps to itself, the usual way
+    /// test ROMs end
+    until_loop: bool,
+    presses: Vec<Press>,
+    screen_path: Option<String>,
+    registers_path: Option<String>,
+    trace_path: Option<String>,
+}
+
+/// Why the run ended
+enum Stop {
+    Frames,
+    Loop,
+    Exit,
+    Fault(Chip8Error),
+}
+
+fn main() {
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(message) => {
+            eprintln!("{message}\n{USAGE}");
+            process::exit(2);
+        }
+    };
+    match run(args) {
+        Ok(true) => {}
+        Ok(false) => process::exit(1),
+        Err(message) => {
+            eprintln!("{message}");
+            process::exit(1);
+        }
+    }
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut rom_path = None;
+    let mut mode = Mode::Chip8;
+    let mut seed = None;
+    let mut frames = DEFAULT_FRAMES;
+    let mut until_loop = false;
+    let mut presses = Vec::new();
+    let mut screen_path = None;
+    let mut registers_path = None;
+    let mut trace_path = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--mode" => mode = args.next().ok_or("Missing mode")?.parse()?,
+            "--seed" => {
+                let value = args.next().ok_or("Missing seed")?;
+                seed = Some(value.parse().map_err(|_| format!("Invalid seed: {value}"))?);
+            }
+            "--frames" => {
+                let value = args.next().ok_or("Missing frame count")?;
+                frames = value.parse().map_err(|_| format!("Invalid frame count: {value}"))?;
+            }
+            "--until-loop" => until_loop = true,
+            "--press" => presses.push(parse_press(&args.next().ok_or("Missing key press")?)?),
+            "--screen" => screen_path = Some(args.next().ok_or("Missing screen path")?),
+            "--registers" => registers_path = Some(args.next().ok_or("Missing registers path")?),
+            "--trace" => trace_path = Some(args.next().ok_or("Missing trace path")?),
+            _ if rom_path.is_none() => rom_path = Some(arg),
+            _ => return Err(format!("Unexpected argument: {arg}")),
+        }
+    }
+    let rom_path = rom_path.ok_or("Missing ROM path")?;
+    Ok(Args { rom_path, mode, seed, frames, until_loop, presses, screen_path, registers_path, trace_path })
+}
+
+/// Parses `FRAME:KEY[:HOLD]`, with the key in hex
+fn parse_press(text: &str) -> Result<Press, String> {
+    let invalid = || format!("Invalid key press: {text}");
+    let mut fields = text.split(':');
+    let frame: u64 = fields.next().and_then(|frame| frame.parse().ok()).ok_or_else(invalid)?;
+    let key = fields
+        .next()
+        .and_then(|key| u8::from_str_radix(key, 16).ok())
+        .filter(|&key| key <= 0xF)
+        .ok_or_else(invalid)?;
+    let hold = match fields.next() {
+        Some(hold) => hold.parse().ok().filter(|&hold| hold > 0).ok_or_else(invalid)?,
+        None => 1,
+    };
+    // The release frame must be representable
+    if fields.next().is_some() || frame.checked_add(hold).is_none() {
+        return Err(invalid());
+    }
+    Ok(Press { frame, key, hold })
+}
+
+/// Runs the ROM and writes the outputs, returning false after a fault
+fn run(args: Args) -> Result<bool, String> {
+    let mut emulator = Emulator::with_config(Config { seed: args.seed, ..Config::for_mode(args.mode) });
+    if args.rom_path.ends_with(".8o") {
+        let rom = octo::compile_file(&args.rom_path).map_err(|err| err.to_string())?;
+        emulator
+            .load_rom(&rom)
+            .map_err(|err| format!("Failed to load {}: {err}", args.rom_path))?;
+    } else {
+        emulator
+            .load_rom_file(&args.rom_path)
+            .map_err(|err| format!("Failed to load {}: {err}", args.rom_path))?;
+    }
+    if let Some(path) = &args.trace_path {
+        let file = fs::File::create(path).map_err(|err| format!("Failed to create {path}: {err}"))?;
+        emulator.set_tracer(Tracer::new(file, TraceFormat::Text));
+    }
+
+    let (stop, skipped) = run_frames(&mut emulator, &args);
+    if let Some(tracer) = emulator.take_tracer() {
+        tracer.finish().map_err(|err| format!("Failed to write trace: {err}"))?;
+    }
+    if let Some(path) = &args.screen_path {
+        let framebuffer = emulator.get_framebuffer();
+        let data = if path.ends_with(".png") {
+            to_png(&framebuffer)
+        } else if path.ends_with(".pbm") {
+            to_pbm(&framebuffer)
+        } else {
+            to_ascii(&framebuffer).into_bytes()
+        };
+        write_output(path, &data)?;
+    }
+    let fault = match &stop {
+        Stop::Fault(err) => Some(err),
+        _ => skipped.as_ref(),
+    };
+    if let Some(path) = &args.registers_path {
+        write_output(path, registers_json(&emulator, &stop, fault).as_bytes())?;
+    }
+    if let Some(err) = fault {
+        eprintln!("The emulator faulted: {err}");
+    }
+    Ok(fault.is_none())
+}
+
+/// Runs frames until the frame count or a stop condition. Faults skipped
+/// by the fault policy don't stop the run, the first one is returned.
+fn run_frames(emulator: &mut Emulator, args: &Args) -> (Stop, Option<Chip8Error>) {
+    let mut skipped = None;
+    for frame in 0..args.frames {
+        for press in &args.presses {
+            if press.frame == frame {
+                emulator.key_down(press.key);
+            } else if press.frame + press.hold == frame {
+                emulator.key_up(press.key);
+            }
+        }
+        for _ in 0..emulator.get_config().instructions_per_frame {
+            if args.until_loop && is_jump_to_self(emulator) {
+                return (Stop::Loop, skipped);
+            }
+            match emulator.tick() {
+                Ok(StepOutcome::Executed) => {}
+                Ok(StepOutcome::Skipped(err)) => {
+                    skipped.get_or_insert(err);
+                }
+                Ok(StepOutcome::WaitingForVblank | StepOutcome::WaitingForKey) => break,
+                Ok(StepOutcome::Exited | StepOutcome::Halted) => return (Stop::Exit, skipped),
+                Err(err) => return (Stop::Fault(err), skipped),
+            }
+        }
+        emulator.end_frame();
+    }
+    (Stop::Frames, skipped)
+}
+
+fn is_jump_to_self(emulator: &Emulator) -> bool {
+    let pc = emulator.get_cpu_state().program_counter;
+    let operation = emulator.get_memory().fetch_word(pc).unwrap_or_default();
+    decode_for(operation, emulator.get_config().mode) == Instruction::Jump(pc)
+}
+
+fn write_output(path: &str, data: &[u8]) -> Result<(), String> {
+    let result = if path == "-" { io::stdout().write_all(data) } else { fs::write(path, data) };
+    result.map_err(|err| format!("Failed to write {path}: {err}"))
+}
+
+/// Registers, timers and how the run ended as a JSON object
+fn registers_json(emulator: &Emulator, stop: &Stop, fault: Option<&Chip8Error>) -> String {
+    let state = emulator.get_cpu_state();
+    let memory = emulator.get_memory();
+    let list = |values: Vec<String>| values.join(", ");
+    let stop = match stop {
+        Stop::Frames => "frames",
+        Stop::Loop => "loop",
+        Stop::Exit => "exit",
+        Stop::Fault(_) => "fault",
+    };
+    let fault = match fault {
+        Some(err) => json_string(&err.to_string()),
+        None => "null".to_string(),
+    };
+    let depth = (state.stack_pointer as usize).min(state.stack.len());
+    format!(
+        "{{\n  \"stop\": \"{stop}\",\n  \"fault\": {fault},\n  \"frames\": {},\n  \"pc\": {},\n  \"i\": {},\n  \"sp\": {},\n  \
+         \"dt\": {},\n  \"st\": {},\n  \"v\": [{}],\n  \"stack\": [{}]\n}}\n",
+        emulator.get_frame_count(),
+        state.program_counter,
+        state.i_register,
+        state.stack_pointer,
+        memory.get_delay_timer(),
+        memory.get_sound_timer(),
+        list(state.v_registers.iter().map(u8::to_string).collect()),
+        list(state.stack[..depth].iter().map(u16::to_string).collect()),
+    )
+}
+
+fn json_string(text: &str) -> String {
+    let mut out = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if c.is_control() => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
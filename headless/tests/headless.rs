@@ -0,0 +1,81 @@
+//! Runs `chip8-headless` on small ROMs and checks its outputs
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+/// Draws the digit in V0 once key 5 is held, then loops in place:
+/// LD V0, 7; LD V1, 5; SKP V1; JP 0x204; LD F, V0; DRW V2, V2, 5; JP 0x20C
+const ROM: [u8; 16] = [
+    0x60, 0x07, 0x61, 0x05, 0xE1, 0x9E, 0x12, 0x04, 0xF0, 0x29, 0xD2, 0x25, 0x12, 0x0C, 0x00, 0x00,
+];
+
+fn temp_path(name: &str) -> PathBuf {
+    env::temp_dir().join(format!("chip8-headless-{}-{name}", std::process::id()))
+}
+
+fn run(rom: &[u8], name: &str, args: &[&str]) -> Output {
+    let rom_path = temp_path(&format!("{name}.ch8"));
+    fs::write(&rom_path, rom).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_chip8-headless")).args(args).arg(&rom_path).output().unwrap();
+    fs::remove_file(rom_path).unwrap();
+    output
+}
+
+#[test]
+fn runs_until_the_final_loop() {
+    let screen_path = temp_path("screen.txt");
+    let screen = screen_path.to_str().unwrap();
+    let output = run(&ROM, "loop", &["--until-loop", "--press", "3:5", "--screen", screen, "--registers", "-"]);
+    assert!(output.status.success());
+    let registers = String::from_utf8(output.stdout).unwrap();
+    assert!(registers.contains("\"stop\": \"loop\""), "{registers}");
+    assert!(registers.contains("\"fault\": null"));
+    assert!(registers.contains("\"pc\": 524"));
+    assert!(registers.contains("\"frames\": 3"));
+    assert!(registers.contains("\"v\": [7, 5, 0, 0,"));
+
+    let text = fs::read_to_string(&screen_path).unwrap();
+    fs::remove_file(screen_path).unwrap();
+    let lines: Vec<&str> = text.lines().collect();
+    assert_eq!(lines.len(), 32);
+    assert_eq!(&lines[0][..5], "####.");
+    assert_eq!(&lines[1][..5], "...#.");
+}
+
+#[test]
+fn runs_a_fixed_number_of_frames() {
+    let output = run(&ROM, "frames", &["--frames", "10", "--registers", "-", "--screen", "-"]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    // No key press, so nothing was drawn
+    assert!(stdout.starts_with(&".".repeat(64)));
+    assert!(stdout.contains("\"stop\": \"frames\""));
+    assert!(stdout.contains("\"frames\": 10"));
+}
+
+#[test]
+fn faults_exit_with_an_error() {
+    // RET with an empty stack
+    let output = run(&[0x00, 0xEE], "fault", &["--registers", "-"]);
+    assert_eq!(output.status.code(), Some(1));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("\"stop\": \"fault\""));
+    assert!(!stdout.contains("\"fault\": null"));
+    assert!(String::from_utf8(output.stderr).unwrap().contains("The emulator faulted"));
+}
+
+#[test]
+fn rejects_bad_key_presses() {
+    let output = run(&ROM, "usage", &["--press", "3:G"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8(output.stderr).unwrap().starts_with("Invalid key press: 3:G"));
+
+    // Release frames past the end of the frame counter
+    for press in ["18446744073709551615:1", "5:1:18446744073709551615"] {
+        let output = run(&ROM, "overflow", &["--press", press]);
+        assert_eq!(output.status.code(), Some(2));
+        assert!(String::from_utf8(output.stderr).unwrap().starts_with(&format!("Invalid key press: {press}")));
+    }
+}
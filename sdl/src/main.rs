@@ -1,21 +1,292 @@
-use chip8::*;
-use chip8::display::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use std::env;
+use std::fs;
+use std::process;
+use std::thread;
+use std::time::{Duration, Instant};
+use chip8::audio::AudioGenerator;
+use chip8::config::{Config, Mode};
+use chip8::display::{HIRES_HEIGHT, HIRES_WIDTH, LORES_HEIGHT, LORES_WIDTH};
+use chip8::emulator::Emulator;
+use chip8::image::PALETTE;
+use chip8::octo;
+use chip8::rewind::RewindConfig;
+use chip8::trace::{TraceFormat, Tracer};
+use sdl2::audio::{AudioCallback, AudioSpecDesired};
+use sdl2::event::Event;
+use sdl2::keyboard::{Mod, Scancode};
+use sdl2::messagebox::{show_simple_message_box, MessageBoxFlag};
+use sdl2::pixels::PixelFormatEnum;
+use sdl2::rect::Rect;
 
 const SCALE: u32 = 15;
-const WINDOW_WIDTH: u32 = (SCREEN_WIDTH as u32) * SCALE;
-const WINDOW_HEIGHT: u32 = (SCREEN_HEIGHT as u32) * SCALE;
+const WINDOW_WIDTH: u32 = (LORES_WIDTH as u32) * SCALE;
+const WINDOW_HEIGHT: u32 = (LORES_HEIGHT as u32) * SCALE;
+const FRAME_DURATION: Duration = Duration::from_nanos(1_000_000_000 / 60);
+/// Frames the loop may fall behind before it stops trying to catch up
+const MAX_FRAME_LAG: u32 = 5;
+const SAMPLE_RATE: i32 = 44100;
+
+const USAGE: &str = "Usage: sdl [--mode chip8|schip|xochip] [--frames N] [--seed N] [--trace FILE] <rom | source.8o>";
+
+/// Command line options
+struct Args {
+    rom_path: String,
+    mode: Mode,
+    /// Quit after this many frames, for smoke tests with SDL's dummy drivers
+    frames: Option<u64>,
+    /// Seed for CXNN, to reproduce a run
+    seed: Option<u64>,
+    /// Writes a text trace of every executed instruction here
+    trace_path: Option<String>,
+}
+
+/// Feeds the emulator's audio generator to an SDL playback device
+struct Speaker {
+    generator: AudioGenerator,
+}
+
+impl AudioCallback for Speaker {
+    type Channel = f32;
+
+    fn callback(&mut self, out: &mut [f32]) {
+        self.generator.generate(out);
+    }
+}
 
 fn main() {
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(message) => {
+            eprintln!("{message}\n{USAGE}");
+            process::exit(2);
+        }
+    };
+    if let Err(message) = run(args) {
+        eprintln!("{message}");
+        process::exit(1);
+    }
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut rom_path = None;
+    let mut mode = Mode::Chip8;
+    let mut frames = None;
+    let mut seed = None;
+    let mut trace_path = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--mode" => mode = args.next().ok_or("Missing mode")?.parse()?,
+            "--frames" => {
+                let value = args.next().ok_or("Missing frame count")?;
+                frames = Some(value.parse().map_err(|_| format!("Invalid frame count: {value}"))?);
+            }
+            "--seed" => {
+                let value = args.next().ok_or("Missing seed")?;
+                seed = Some(value.parse().map_err(|_| format!("Invalid seed: {value}"))?);
+            }
+            "--trace" => trace_path = Some(args.next().ok_or("Missing trace path")?),
+            _ if rom_path.is_none() => rom_path = Some(arg),
+            _ => return Err(format!("Unexpected argument: {arg}")),
+        }
+    }
+    let rom_path = rom_path.ok_or("Missing ROM path")?;
+    Ok(Args { rom_path, mode, frames, seed, trace_path })
+}
+
+/// Maps the left side of a QWERTY keyboard onto the hex keypad:
+///
+/// ```text
+/// 1 2 3 4      1 2 3 C
+/// Q W E R  ->  4 5 6 D
+/// A S D F      7 8 9 E
+/// Z X C V      A 0 B F
+/// ```
+fn keypad_key(scancode: Scancode) -> Option<u8> {
+    match scancode {
+        Scancode::Num1 => Some(0x1),
+        Scancode::Num2 => Some(0x2),
+        Scancode::Num3 => Some(0x3),
+        Scancode::Num4 => Some(0xC),
+        Scancode::Q => Some(0x4),
+        Scancode::W => Some(0x5),
+        Scancode::E => Some(0x6),
+        Scancode::R => Some(0xD),
+        Scancode::A => Some(0x7),
+        Scancode::S => Some(0x8),
+        Scancode::D => Some(0x9),
+        Scancode::F => Some(0xE),
+        Scancode::Z => Some(0xA),
+        Scancode::X => Some(0x0),
+        Scancode::C => Some(0xB),
+        Scancode::V => Some(0xF),
+        _ => None,
+    }
+}
+
+/// Maps F1 - F9 onto quick-save slots 1 - 9
+fn state_slot(scancode: Scancode) -> Option<u8> {
+    match scancode {
+        Scancode::F1 => Some(1),
+        Scancode::F2 => Some(2),
+        Scancode::F3 => Some(3),
+        Scancode::F4 => Some(4),
+        Scancode::F5 => Some(5),
+        Scancode::F6 => Some(6),
+        Scancode::F7 => Some(7),
+        Scancode::F8 => Some(8),
+        Scancode::F9 => Some(9),
+        _ => None,
+    }
+}
+
+/// Save states live next to the ROM, e.g. `game.ch8.state1`
+fn state_path(rom_path: &str, slot: u8) -> String {
+    format!("{rom_path}.state{slot}")
+}
+
+/// Shift+F1 - F9 saves to a slot, F1 - F9 loads it again
+fn quick_save(emulator: &mut Emulator, rom_path: &str, slot: u8, save: bool) {
+    let path = state_path(rom_path, slot);
+    let result = if save {
+        fs::write(&path, emulator.save_state()).map_err(|err| err.to_string())
+    } else {
+        fs::read(&path)
+            .map_err(|err| err.to_string())
+            .and_then(|state| emulator.load_state(&state).map_err(|err| err.to_string()))
+    };
+    match result {
+        Ok(()) if save => println!("Saved state to slot {slot}"),
+        Ok(()) => println!("Loaded state from slot {slot}"),
+        Err(err) => eprintln!("Quick-save slot {slot} ({path}): {err}"),
+    }
+}
+
+fn run(args: Args) -> Result<(), String> {
+    let config = Config {
+        rewind: Some(RewindConfig::default()),
+        seed: args.seed,
+        ..Config::for_mode(args.mode)
+    };
+    let mut emulator = Emulator::with_config(config);
+    if args.rom_path.ends_with(".8o") {
+        // Octo source is compiled on the fly
+        let rom = octo::compile_file(&args.rom_path).map_err(|err| err.to_string())?;
+        emulator
+            .load_rom(&rom)
+            .map_err(|err| format!("Failed to load {}: {err}", args.rom_path))?;
+    } else {
+        emulator
+            .load_rom_file(&args.rom_path)
+            .map_err(|err| format!("Failed to load {}: {err}", args.rom_path))?;
+    }
+    if let Some(path) = &args.trace_path {
+        let file = fs::File::create(path).map_err(|err| format!("Failed to create {path}: {err}"))?;
+        emulator.set_tracer(Tracer::new(file, TraceFormat::Text));
+    }
+
     // Setup SDL
-    let sdl_context = sdl2::init().unwrap();
-    let video_subsystem = sdl_context.video().unwrap();
+    let sdl_context = sdl2::init()?;
+    let video_subsystem = sdl_context.video()?;
     let window = video_subsystem
         .window("Chip-8 Emulator", WINDOW_WIDTH, WINDOW_HEIGHT)
         .position_centered()
-        .opengl()
         .build()
-        .unwrap();
-    let mut canvas = window.into_canvas().present_vsync().build().unwrap();
-    canvas.clear();
-    canvas.present();
+        .map_err(|err| err.to_string())?;
+    let mut canvas = window.into_canvas().build().map_err(|err| err.to_string())?;
+    let texture_creator = canvas.texture_creator();
+    // Sized for hires, lores frames only use the top left corner
+    let mut texture = texture_creator
+        .create_texture_streaming(PixelFormatEnum::RGB24, HIRES_WIDTH as u32, HIRES_HEIGHT as u32)
+        .map_err(|err| err.to_string())?;
+    let mut pixels = vec![0; HIRES_WIDTH * HIRES_HEIGHT * 3];
+
+    // Audio is optional, keep running silently without a device
+    let audio_spec = AudioSpecDesired { freq: Some(SAMPLE_RATE), channels: Some(1), samples: None };
+    let speaker = sdl_context.audio().and_then(|audio| {
+        audio.open_playback(None, &audio_spec, |spec| Speaker {
+            generator: AudioGenerator::new(spec.freq as u32),
+        })
+    });
+    let mut speaker = match speaker {
+        Ok(device) => {
+            device.resume();
+            Some(device)
+        }
+        Err(err) => {
+            eprintln!("Audio disabled: {err}");
+            None
+        }
+    };
+
+    let mut event_pump = sdl_context.event_pump()?;
+    let mut next_frame = Instant::now();
+    let mut frames_shown = 0;
+    'running: loop {
+        for event in event_pump.poll_iter() {
+            match event {
+                Event::Quit { .. } | Event::KeyDown { scancode: Some(Scancode::Escape), .. } => break 'running,
+                Event::KeyDown { scancode: Some(scancode), keymod, repeat: false, .. } => {
+                    if let Some(slot) = state_slot(scancode) {
+                        let save = keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD);
+                        quick_save(&mut emulator, &args.rom_path, slot, save);
+                    } else if let Some(key) = keypad_key(scancode) {
+                        emulator.key_down(key);
+                    }
+                }
+                Event::KeyUp { scancode: Some(scancode), .. } => {
+                    if let Some(key) = keypad_key(scancode) {
+                        emulator.key_up(key);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        // Holding backspace plays the game backwards, also out of a halt
+        if event_pump.keyboard_state().is_scancode_pressed(Scancode::Backspace) {
+            emulator.rewind(1).map_err(|err| err.to_string())?;
+        } else if !emulator.is_halted() {
+            if let Err(err) = emulator.run_frame() {
+                let message = format!("The emulator stopped: {err}");
+                eprintln!("{message}");
+                // Best effort, there may be no way to show a dialog
+                let _ = show_simple_message_box(MessageBoxFlag::ERROR, "Chip-8 Emulator", &message, canvas.window());
+            }
+        }
+        if let Some(device) = &mut speaker {
+            device.lock().generator.set_state(emulator.get_audio_state());
+        }
+
+        // Render framebuffer
+        let framebuffer = emulator.get_framebuffer();
+        let (width, height) = (framebuffer.width(), framebuffer.height());
+        for (x, y, pixel) in framebuffer.pixels() {
+            let color = PALETTE[pixel as usize & 0x3];
+            let offset = (x + y * HIRES_WIDTH) * 3;
+            pixels[offset..offset + 3].copy_from_slice(&color);
+        }
+        texture.update(None, &pixels, HIRES_WIDTH * 3).map_err(|err| err.to_string())?;
+        canvas.clear();
+        canvas.copy(&texture, Rect::new(0, 0, width as u32, height as u32), None)?;
+        canvas.present();
+
+        frames_shown += 1;
+        if args.frames.is_some_and(|frames| frames_shown >= frames) {
+            break;
+        }
+
+        // Fixed 60 Hz timestep
+        next_frame += FRAME_DURATION;
+        let now = Instant::now();
+        if next_frame > now {
+            thread::sleep(next_frame - now);
+        } else if now - next_frame > FRAME_DURATION * MAX_FRAME_LAG {
+            next_frame = now;
+        }
+    }
+    match emulator.take_tracer() {
+        Some(tracer) => tracer.finish().map_err(|err| format!("Failed to write trace: {err}")),
+        None => Ok(()),
+    }
 }
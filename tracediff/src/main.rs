@@ -0,0 +1,133 @@
+use std::collections::VecDeque;
+use std::env;
+use std::fs::File;
+use std::io::BufReader;
+use std::process;
+use chip8::config::Mode;
+use chip8::trace::{is_field, TraceReader, TraceRecord};
+
+const USAGE: &str =
+    "Usage: chip8-tracediff [--mode chip8|schip|xochip] [--ignore FIELD,...] [--context N] <expected> <actual>";
+
+/// Records shown before the divergence by default
+const DEFAULT_CONTEXT: usize = 3;
+
+/// Command line options
+struct Args {
+    expected_path: String,
+    actual_path: String,
+    /// Mode used to decode mnemonics for display
+    mode: Mode,
+    /// Fields left out of the comparison, e.g. timers when emulators tick
+    /// them at different points of a frame
+    ignore: Vec<String>,
+    context: usize,
+}
+
+/// Exits with 0 when the traces match, 1 when they diverge and 2 when they
+/// can't be read, like `cmp`
+fn main() {
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(message) => {
+            eprintln!("{message}\n{USAGE}");
+            process::exit(2);
+        }
+    };
+    match compare(&args) {
+        Ok(true) => {}
+        Ok(false) => process::exit(1),
+        Err(message) => {
+            eprintln!("{message}");
+            process::exit(2);
+        }
+    }
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut paths = Vec::new();
+    let mut mode = Mode::Chip8;
+    let mut ignore = Vec::new();
+    let mut context = DEFAULT_CONTEXT;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--mode" => mode = args.next().ok_or("Missing mode")?.parse()?,
+            "--ignore" => {
+                for field in args.next().ok_or("Missing fields")?.split(',') {
+                    let field = field.trim().to_ascii_lowercase();
+                    if !is_field(&field) {
+                        return Err(format!("Unknown field: {field}"));
+                    }
+                    ignore.push(field);
+                }
+            }
+            "--context" => {
+                let value = args.next().ok_or("Missing context")?;
+                context = value.parse().map_err(|_| format!("Invalid context: {value}"))?;
+            }
+            _ if paths.len() < 2 => paths.push(arg),
+            _ => return Err(format!("Unexpected argument: {arg}")),
+        }
+    }
+    let [expected_path, actual_path]: [String; 2] = paths.try_into().map_err(|_| "Missing trace path")?;
+    Ok(Args { expected_path, actual_path, mode, ignore, context })
+}
+
+fn open(path: &str) -> Result<TraceReader<BufReader<File>>, String> {
+    let file = File::open(path).map_err(|err| format!("Failed to open {path}: {err}"))?;
+    Ok(TraceReader::new(BufReader::new(file)))
+}
+
+/// Reports the first record where the traces differ, returning whether
+/// they match
+fn compare(args: &Args) -> Result<bool, String> {
+    let mut expected = open(&args.expected_path)?;
+    let mut actual = open(&args.actual_path)?;
+    let mut history: VecDeque<TraceRecord> = VecDeque::with_capacity(args.context);
+    let mut index = 0;
+    loop {
+        let next = |reader: &mut TraceReader<_>, path: &str| {
+            reader.next().transpose().map_err(|err| format!("{path}: {err}"))
+        };
+        let (expected_record, actual_record) = match (
+            next(&mut expected, &args.expected_path)?,
+            next(&mut actual, &args.actual_path)?,
+        ) {
+            (Some(expected), Some(actual)) => (expected, actual),
+            (None, None) => {
+                println!("Traces match ({index} records)");
+                return Ok(true);
+            }
+            (None, Some(_)) => {
+                println!("{} ends after {index} records", args.expected_path);
+                return Ok(false);
+            }
+            (Some(_), None) => {
+                println!("{} ends after {index} records", args.actual_path);
+                return Ok(false);
+            }
+        };
+        let differences: Vec<_> = expected_record
+            .differences(&actual_record)
+            .into_iter()
+            .filter(|field| !args.ignore.iter().any(|ignored| ignored == field))
+            .collect();
+        if !differences.is_empty() {
+            println!("Traces diverge at record {index}: {}", differences.join(", "));
+            for record in &history {
+                println!("  {}", record.to_line(args.mode));
+            }
+            println!("- {}", expected_record.to_line(args.mode));
+            println!("+ {}", actual_record.to_line(args.mode));
+            return Ok(false);
+        }
+        if args.context > 0 {
+            if history.len() == args.context {
+                history.pop_front();
+            }
+            history.push_back(expected_record);
+        }
+        index += 1;
+    }
+}